@@ -1,5 +1,5 @@
 use crate::{
-    grid_pos::GridPos,
+    grid_pos::{gp, GridPos},
     line_fragment::{LineFragment, LineFragmentKind},
 };
 use std::fmt::Display;
@@ -29,6 +29,7 @@ pub fn are_line_fragments_connecting(
                 InvertedCaret => [BelowLeft, Below, BelowRight].contains(&adjacency),
                 LeftSlash => [Left, Below, BelowRight].contains(&adjacency),
                 RightSlash => [Right, Below, BelowLeft].contains(&adjacency),
+                Wild | Horizontal | Vertical => shares_corner_node(lf_a.kind, adjacency, lf_b.kind),
             }
         }
         InvertedCaret => {
@@ -41,6 +42,7 @@ pub fn are_line_fragments_connecting(
                 InvertedCaret => [Left, Right].contains(&adjacency),
                 LeftSlash => [Right, Above, AboveLeft].contains(&adjacency),
                 RightSlash => [Left, Above, AboveRight].contains(&adjacency),
+                Wild | Horizontal | Vertical => shares_corner_node(lf_a.kind, adjacency, lf_b.kind),
             }
         }
         LeftSlash => {
@@ -53,6 +55,7 @@ pub fn are_line_fragments_connecting(
                 InvertedCaret => [Left, Below, BelowRight].contains(&adjacency),
                 LeftSlash => [AboveLeft, BelowRight].contains(&adjacency),
                 RightSlash => [Left, Right, Above, Below].contains(&adjacency),
+                Wild | Horizontal | Vertical => shares_corner_node(lf_a.kind, adjacency, lf_b.kind),
             }
         }
         RightSlash => {
@@ -65,11 +68,32 @@ pub fn are_line_fragments_connecting(
                 InvertedCaret => [Right, Below, BelowLeft].contains(&adjacency),
                 LeftSlash => [Left, Right, Above, Below].contains(&adjacency),
                 RightSlash => [AboveRight, BelowLeft].contains(&adjacency),
+                Wild | Horizontal | Vertical => shares_corner_node(lf_a.kind, adjacency, lf_b.kind),
             }
         }
+        Wild | Horizontal | Vertical => shares_corner_node(lf_a.kind, adjacency, lf_b.kind),
     }
 }
 
+/// Wild fragments don't have a fixed shape, so instead of a lookup table they connect to
+/// anything that touches one of their corners. Straight fragments work the same way.
+fn shares_corner_node(
+    kind_a: LineFragmentKind,
+    adjacency: Adjacency,
+    kind_b: LineFragmentKind,
+) -> bool {
+    let offset_of_b = match adjacency.offset() {
+        Some(offset) => offset,
+        None => return false,
+    };
+    let corners_of_a = kind_a.corner_offsets();
+
+    kind_b
+        .corner_offsets()
+        .into_iter()
+        .any(|corner| corners_of_a.contains(&(corner + offset_of_b)))
+}
+
 pub fn adjacency_of_grid_positions(gp_a: GridPos, gp_b: GridPos) -> Adjacency {
     match gp_a - gp_b {
         GridPos { x: 0, y: 0 } => Adjacency::Same,
@@ -94,6 +118,24 @@ pub enum Adjacency {
     NotAdjacent,
 }
 
+impl Adjacency {
+    /// The position of the neighbor relative to the cell it's adjacent to
+    pub fn offset(&self) -> Option<GridPos> {
+        match self {
+            Adjacency::AboveLeft => Some(gp(-1, 1)),
+            Adjacency::Above => Some(gp(0, 1)),
+            Adjacency::AboveRight => Some(gp(1, 1)),
+            Adjacency::Left => Some(gp(-1, 0)),
+            Adjacency::Same => Some(gp(0, 0)),
+            Adjacency::Right => Some(gp(1, 0)),
+            Adjacency::BelowLeft => Some(gp(-1, -1)),
+            Adjacency::Below => Some(gp(0, -1)),
+            Adjacency::BelowRight => Some(gp(1, -1)),
+            Adjacency::NotAdjacent => None,
+        }
+    }
+}
+
 impl Display for Adjacency {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -181,4 +223,140 @@ mod tests {
 
         assert_eq!(true, are_line_fragments_connecting(&a, adjacency, &b));
     }
+
+    #[test]
+    fn test_wild_connects_to_anything_touching_its_corners() {
+        let wild = LineFragment::from_char(&'*');
+
+        for c in ['∧', '∨', '\\', '/', '*'] {
+            let lf = LineFragment::from_char(&c);
+
+            assert!(are_line_fragments_connecting(&wild, Adjacency::Left, &lf));
+            assert!(are_line_fragments_connecting(&lf, Adjacency::Right, &wild));
+        }
+    }
+
+    #[test]
+    fn test_wild_only_connects_diagonally_through_a_shared_corner() {
+        let wild = LineFragment::from_char(&'*');
+        let right_slash = LineFragment::from_char(&'/');
+
+        assert!(are_line_fragments_connecting(
+            &wild,
+            Adjacency::AboveRight,
+            &right_slash
+        ));
+        assert!(!are_line_fragments_connecting(
+            &wild,
+            Adjacency::AboveLeft,
+            &right_slash
+        ));
+    }
+
+    #[test]
+    fn test_should_connect_horizontal_1() {
+        let a = LineFragment::from_char(&'─');
+        let b = LineFragment::from_char(&'─');
+        let adjacency = Adjacency::Right;
+
+        assert!(are_line_fragments_connecting(&a, adjacency, &b));
+    }
+
+    #[test]
+    fn test_should_connect_horizontal_2() {
+        let a = LineFragment::from_char(&'─');
+        let b = LineFragment::from_char(&'∧');
+        let adjacency = Adjacency::Left;
+
+        assert!(are_line_fragments_connecting(&a, adjacency, &b));
+    }
+
+    #[test]
+    fn test_should_connect_horizontal_3() {
+        let a = LineFragment::from_char(&'─');
+        let b = LineFragment::from_char(&'/');
+        let adjacency = Adjacency::BelowLeft;
+
+        assert!(are_line_fragments_connecting(&a, adjacency, &b));
+    }
+
+    #[test]
+    fn test_should_not_connect_horizontal() {
+        let a = LineFragment::from_char(&'─');
+        let b = LineFragment::from_char(&'─');
+        let adjacency = Adjacency::Above;
+
+        assert!(!are_line_fragments_connecting(&a, adjacency, &b));
+    }
+
+    #[test]
+    fn test_should_connect_vertical_1() {
+        let a = LineFragment::from_char(&'│');
+        let b = LineFragment::from_char(&'│');
+        let adjacency = Adjacency::Above;
+
+        assert!(are_line_fragments_connecting(&a, adjacency, &b));
+    }
+
+    #[test]
+    fn test_should_connect_vertical_2() {
+        let a = LineFragment::from_char(&'│');
+        let b = LineFragment::from_char(&'─');
+        let adjacency = Adjacency::Left;
+
+        assert!(are_line_fragments_connecting(&a, adjacency, &b));
+    }
+
+    #[test]
+    fn test_should_connect_vertical_3() {
+        let a = LineFragment::from_char(&'│');
+        let b = LineFragment::from_char(&'∨');
+        let adjacency = Adjacency::Below;
+
+        assert!(are_line_fragments_connecting(&a, adjacency, &b));
+    }
+
+    #[test]
+    fn test_should_not_connect_vertical() {
+        let a = LineFragment::from_char(&'│');
+        let b = LineFragment::from_char(&'│');
+        let adjacency = Adjacency::Right;
+
+        assert!(!are_line_fragments_connecting(&a, adjacency, &b));
+    }
+
+    #[test]
+    fn test_straight_fragments_connect_both_ways() {
+        let kinds = ['∧', '∨', '\\', '/', '*', '─', '│'];
+        let adjacencies = [
+            Adjacency::AboveLeft,
+            Adjacency::Above,
+            Adjacency::AboveRight,
+            Adjacency::Left,
+            Adjacency::Right,
+            Adjacency::BelowLeft,
+            Adjacency::Below,
+            Adjacency::BelowRight,
+        ];
+
+        for straight in ['─', '│'] {
+            let a = LineFragment::from_char(&straight);
+            for c in kinds {
+                let b = LineFragment::from_char(&c);
+                for adjacency in adjacencies {
+                    let opposite =
+                        adjacency_of_grid_positions(adjacency.offset().unwrap(), gp(0, 0));
+
+                    assert_eq!(
+                        are_line_fragments_connecting(&a, adjacency, &b),
+                        are_line_fragments_connecting(&b, opposite, &a),
+                        "{} and {} disagree about connecting to the {}",
+                        straight,
+                        c,
+                        adjacency
+                    );
+                }
+            }
+        }
+    }
 }
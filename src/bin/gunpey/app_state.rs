@@ -1,6 +1,8 @@
+use crate::assets::SpriteStyle;
 use druid::{Data, Lens};
 use gunpey_lib::{
-    grid::Grid, grid_pos::GridPos, new_random_row, new_small_grid, NewRowGenerationParams,
+    cycle_rows, grid::Grid, grid_pos::GridPos, history::UndoHistory, score::Score, seeded_rng,
+    GameConfig,
 };
 use log::{debug, error};
 use rand::{prelude::StdRng, SeedableRng};
@@ -10,6 +12,7 @@ use std::sync::Arc;
 pub enum View {
     Start,
     Game,
+    GameOver,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -28,39 +31,107 @@ pub struct AppState {
     pub grid: Grid,
     pub rng: Arc<StdRng>,
     pub test: Arc<Test>,
-    score: usize,
+    score: Score,
+    history: Arc<UndoHistory>,
     view: View,
-    pub updates_per_second: f64,
+    /// The board size and speed. The game board widget is built from this too so it always
+    /// matches the grid.
+    pub config: Arc<GameConfig>,
+    /// Whether the game board's timer pushes a new row up every tick
+    pub auto_rise: bool,
+    /// While paused rows stop rising, but the game board's timer keeps running
+    pub paused: bool,
+    /// Whether cells can still be swapped (and swaps undone or redone) while paused
+    pub allow_swaps_while_paused: bool,
     pub paint_mode: bool,
+    pub sprite_style: SpriteStyle,
 }
 
 impl AppState {
     pub fn new() -> Self {
-        let grid = new_small_grid();
+        let config = Arc::new(GameConfig::default());
+        let grid = config.new_grid();
         let test = Arc::new(Test::new(0));
-        let rng = Arc::new(SeedableRng::from_entropy());
+        // Set GUNPEY_SEED, e.g. in .env, to get the same rows every game
+        let rng = Arc::new(
+            match std::env::var("GUNPEY_SEED").map(|seed| seed.parse()) {
+                Ok(Ok(seed)) => seeded_rng(seed),
+                _ => SeedableRng::from_entropy(),
+            },
+        );
 
         Self {
             view: View::Start,
             grid,
             rng,
-            score: 0,
+            score: Score::default(),
+            history: Arc::new(UndoHistory::default()),
             test,
-            updates_per_second: 60.0,
+            config,
+            auto_rise: true,
+            paused: false,
+            allow_swaps_while_paused: false,
             paint_mode: true,
+            sprite_style: SpriteStyle::default(),
         }
     }
 
-    pub fn _score(&self) -> usize {
-        self.score
+    pub fn points(&self) -> u64 {
+        self.score.points
     }
 
-    pub fn _score_points(&mut self, points: usize) {
-        self.score += points;
+    pub fn score_points(&mut self, points: u64) {
+        self.score.points += points;
     }
 
     pub fn reset_score(&mut self) {
-        self.score = 0;
+        self.score = Score::default();
+    }
+
+    /// Start over with a fresh grid, score and history and switch to the game view.
+    pub fn new_game(&mut self) {
+        self.grid = self.config.new_grid();
+        self.score = Score::default();
+        self.history = Arc::new(UndoHistory::default());
+        self.paused = false;
+        self.set_current_view(View::Game);
+    }
+
+    pub fn toggle_auto_rise(&mut self) {
+        self.auto_rise = !self.auto_rise;
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    /// Whether the player is allowed to move cells around right now
+    pub fn can_swap(&self) -> bool {
+        !self.paused || self.allow_swaps_while_paused
+    }
+
+    /// The stack reached the top. Record the run and show the game over screen.
+    pub fn game_over(&mut self) {
+        #[cfg(feature = "serde")]
+        self.save_high_score();
+        #[cfg(not(feature = "serde"))]
+        log::warn!("Built without the serde feature, so high scores aren't saved");
+        self.set_current_view(View::GameOver);
+    }
+
+    /// Set GUNPEY_HIGH_SCORES and GUNPEY_PLAYER, e.g. in .env, to pick where scores are saved
+    /// and who they're saved under
+    #[cfg(feature = "serde")]
+    fn save_high_score(&self) {
+        use gunpey_lib::highscore::{save_high_score, ScoreEntry};
+
+        let path =
+            std::env::var("GUNPEY_HIGH_SCORES").unwrap_or_else(|_| "high_scores.json".to_string());
+        let name = std::env::var("GUNPEY_PLAYER").unwrap_or_else(|_| "Player".to_string());
+        match save_high_score(path.as_ref(), ScoreEntry::new(name, self.points())) {
+            Ok(scores) => debug!("Saved score to {}, {} high scores", path, scores.len()),
+            Err(err) => error!("Couldn't save high score: {}", err),
+        }
     }
 
     /// Set the app state's current view.
@@ -69,30 +140,71 @@ impl AppState {
     }
 
     pub fn iter_interval(&self) -> u64 {
-        (1000. / self.updates_per_second) as u64
+        (1000. / self.config.updates_per_second) as u64
     }
 
     pub fn swap_cells(&mut self, grid_pos_a: GridPos, grid_pos_b: GridPos) {
+        if !self.can_swap() {
+            debug!("Not swapping tiles while paused");
+            return;
+        }
+
         debug!("Swapping tiles at {} and {}", grid_pos_a, grid_pos_b);
 
         match self.grid.swap_cells(grid_pos_a, grid_pos_b) {
-            Ok(_) => self.grid.recalculate_active_cells(),
+            Ok(_) => {
+                self.grid.recalculate_active_cells();
+                Arc::make_mut(&mut self.history).push(grid_pos_a, grid_pos_b);
+            }
             Err(err) => error!("Couldn't swap: {}", err),
         };
+
+        let cleared = self.grid.clear_active_cells_scored(&mut self.score, 1);
+        if !cleared.is_empty() {
+            debug!("Cleared {} cells", cleared.len());
+            // Cleared cells can't be swapped back so there's nothing sensible left to undo
+            Arc::make_mut(&mut self.history).clear();
+        }
     }
 
-    pub fn cycle_grid_rows(&mut self) {
-        let new_row_params = NewRowGenerationParams {
-            width: self.grid.width,
-        };
+    pub fn undo(&mut self) {
+        if !self.can_swap() {
+            debug!("Not undoing while paused");
+            return;
+        }
+
+        match Arc::make_mut(&mut self.history).undo(&mut self.grid) {
+            Ok(Some((grid_pos_a, grid_pos_b))) => {
+                debug!("Undid swap of {} and {}", grid_pos_a, grid_pos_b);
+                self.grid.recalculate_active_cells();
+            }
+            Ok(None) => debug!("Nothing to undo"),
+            Err(err) => error!("Couldn't undo: {}", err),
+        }
+    }
 
-        let popped_row = self.grid.pop_top_row();
-        debug!("popped row: {:#?}", popped_row);
+    pub fn redo(&mut self) {
+        if !self.can_swap() {
+            debug!("Not redoing while paused");
+            return;
+        }
 
+        match Arc::make_mut(&mut self.history).redo(&mut self.grid) {
+            Ok(Some((grid_pos_a, grid_pos_b))) => {
+                debug!("Redid swap of {} and {}", grid_pos_a, grid_pos_b);
+                self.grid.recalculate_active_cells();
+            }
+            Ok(None) => debug!("Nothing to redo"),
+            Err(err) => error!("Couldn't redo: {}", err),
+        }
+    }
+
+    pub fn cycle_grid_rows(&mut self) {
         let rng = Arc::make_mut(&mut self.rng);
-        let new_row = new_random_row(rng, new_row_params);
-        match self.grid.push_bottom_row(new_row) {
-            Ok(_) => self.grid.recalculate_active_cells(),
+        match cycle_rows(&mut self.grid, rng, &self.config) {
+            Ok(false) => debug!("Not adding a row, the stack has reached the top"),
+            // Everything moved up a row, including the cells undo would swap back
+            Ok(true) => Arc::make_mut(&mut self.history).shift_up_one_row(self.grid.height),
             Err(err) => error!("failed push_row_to_bottom_and_pop_row_from_top: {}", err),
         };
     }
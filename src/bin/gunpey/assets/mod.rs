@@ -1,9 +1,18 @@
 use druid::{
     piet::InterpolationMode,
     widget::{FillStrat, Image},
-    ImageBuf,
+    Data, ImageBuf,
 };
 
+/// Which set of sprites fragments are drawn with
+#[derive(Debug, Default, Clone, Copy, PartialEq, Data)]
+pub enum SpriteStyle {
+    #[default]
+    Normal,
+    /// Thicker lines, with active fragments outlined so they stand out
+    HighContrast,
+}
+
 // Creating functions to load assets is boring and boilerplate-ey, this macro makes it concise
 macro_rules! image_asset {
     ( $asset_name:ident ) => {
@@ -26,5 +35,27 @@ image_asset!(caret);
 image_asset!(inverted_caret);
 image_asset!(left_slash);
 image_asset!(right_slash);
+image_asset!(active_wild);
+image_asset!(wild);
+image_asset!(active_horizontal);
+image_asset!(horizontal);
+image_asset!(active_vertical);
+image_asset!(vertical);
 image_asset!(empty_cell);
+image_asset!(blocked);
+image_asset!(bold_active_caret);
+image_asset!(bold_active_inverted_caret);
+image_asset!(bold_active_left_slash);
+image_asset!(bold_active_right_slash);
+image_asset!(bold_caret);
+image_asset!(bold_inverted_caret);
+image_asset!(bold_left_slash);
+image_asset!(bold_right_slash);
+image_asset!(bold_active_wild);
+image_asset!(bold_wild);
+image_asset!(bold_active_horizontal);
+image_asset!(bold_horizontal);
+image_asset!(bold_active_vertical);
+image_asset!(bold_vertical);
+image_asset!(bold_blocked);
 // image_asset!(cursor);
@@ -1,50 +1,143 @@
-use crate::{app_state::AppState, assets};
+use crate::{
+    app_state::AppState,
+    assets::{self, SpriteStyle},
+};
 use druid::{
-    widget::{prelude::*, Flex, SizedBox, WidgetExt},
-    Widget,
+    widget::{prelude::*, Image, SizedBox, WidgetExt},
+    Widget, WidgetPod,
 };
-use druid::{MouseButton, Point, TimerToken};
+use druid::{HotKey, MouseButton, Point, SysMods, TimerToken};
 use gunpey_lib::grid_pos::gp;
-use gunpey_lib::line_fragment::LineFragment;
-use gunpey_lib::{cell::Cell, grid::Grid, grid_pos::GridPos, line_fragment::LineFragmentKind};
+use gunpey_lib::{
+    cell::Cell, grid::Grid, grid_pos::GridPos, line_fragment::LineFragmentKind, GameConfig,
+};
 use log::{debug, trace};
 use std::time::{Duration, Instant};
 
-fn build_widget(app_state: &AppState) -> Box<dyn Widget<AppState>> {
-    let mut container = Flex::column();
-    app_state
-        .grid
-        .cell_rows_in_render_order()
-        .into_iter()
-        .for_each(|row| {
-            let mut child = Flex::row();
-            for cell in row {
-                let cell = match cell {
-                    Cell::Filled(line_fragment) => {
-                        trace!("creating new cell from {:?}", line_fragment);
-                        match (line_fragment.is_active, line_fragment.kind) {
-                            (true, LineFragmentKind::Caret) => assets::active_caret(),
-                            (false, LineFragmentKind::Caret) => assets::caret(),
-                            (true, LineFragmentKind::InvertedCaret) => {
-                                assets::active_inverted_caret()
-                            }
-                            (false, LineFragmentKind::InvertedCaret) => assets::inverted_caret(),
-                            (true, LineFragmentKind::LeftSlash) => assets::active_left_slash(),
-                            (false, LineFragmentKind::LeftSlash) => assets::left_slash(),
-                            (true, LineFragmentKind::RightSlash) => assets::active_right_slash(),
-                            (false, LineFragmentKind::RightSlash) => assets::right_slash(),
-                        }
-                    }
-                    Cell::Empty => assets::empty_cell(),
-                };
-                let cell = SizedBox::new(cell).fix_width(52.0).fix_height(32.0);
-                child.add_child(cell)
+/// One widget per cell, in the same order as the cells of the grid
+fn build_cells(app_state: &AppState) -> Vec<WidgetPod<AppState, CellWidget>> {
+    let grid = &app_state.grid;
+    let mut cells = Vec::with_capacity(grid.width * grid.height);
+    for y in 0..grid.height as isize {
+        for x in 0..grid.width as isize {
+            let grid_pos = gp(x, y);
+            let cell = grid.get_cell_at_pos(&grid_pos).unwrap();
+            trace!("creating new cell from {:?}", cell);
+            cells.push(WidgetPod::new(CellWidget::new(
+                grid_pos,
+                cell,
+                app_state.sprite_style,
+            )));
+        }
+    }
+
+    cells
+}
+
+fn build_cell_image(cell: &Cell, style: SpriteStyle) -> Box<dyn Widget<AppState>> {
+    let image = match (style, cell.is_active(), cell.kind()) {
+        (SpriteStyle::Normal, _, None) if cell.is_blocked() => assets::blocked(),
+        (SpriteStyle::HighContrast, _, None) if cell.is_blocked() => assets::bold_blocked(),
+        (_, _, None) => assets::empty_cell(),
+        (SpriteStyle::Normal, is_active, Some(kind)) => normal_fragment_image(is_active, kind),
+        (SpriteStyle::HighContrast, is_active, Some(kind)) => bold_fragment_image(is_active, kind),
+    };
+
+    SizedBox::new(image)
+        .fix_width(52.0)
+        .fix_height(32.0)
+        .boxed()
+}
+
+fn normal_fragment_image(is_active: bool, kind: LineFragmentKind) -> Image {
+    match (is_active, kind) {
+        (true, LineFragmentKind::Caret) => assets::active_caret(),
+        (false, LineFragmentKind::Caret) => assets::caret(),
+        (true, LineFragmentKind::InvertedCaret) => assets::active_inverted_caret(),
+        (false, LineFragmentKind::InvertedCaret) => assets::inverted_caret(),
+        (true, LineFragmentKind::LeftSlash) => assets::active_left_slash(),
+        (false, LineFragmentKind::LeftSlash) => assets::left_slash(),
+        (true, LineFragmentKind::RightSlash) => assets::active_right_slash(),
+        (false, LineFragmentKind::RightSlash) => assets::right_slash(),
+        (true, LineFragmentKind::Wild) => assets::active_wild(),
+        (false, LineFragmentKind::Wild) => assets::wild(),
+        (true, LineFragmentKind::Horizontal) => assets::active_horizontal(),
+        (false, LineFragmentKind::Horizontal) => assets::horizontal(),
+        (true, LineFragmentKind::Vertical) => assets::active_vertical(),
+        (false, LineFragmentKind::Vertical) => assets::vertical(),
+    }
+}
+
+fn bold_fragment_image(is_active: bool, kind: LineFragmentKind) -> Image {
+    match (is_active, kind) {
+        (true, LineFragmentKind::Caret) => assets::bold_active_caret(),
+        (false, LineFragmentKind::Caret) => assets::bold_caret(),
+        (true, LineFragmentKind::InvertedCaret) => assets::bold_active_inverted_caret(),
+        (false, LineFragmentKind::InvertedCaret) => assets::bold_inverted_caret(),
+        (true, LineFragmentKind::LeftSlash) => assets::bold_active_left_slash(),
+        (false, LineFragmentKind::LeftSlash) => assets::bold_left_slash(),
+        (true, LineFragmentKind::RightSlash) => assets::bold_active_right_slash(),
+        (false, LineFragmentKind::RightSlash) => assets::bold_right_slash(),
+        (true, LineFragmentKind::Wild) => assets::bold_active_wild(),
+        (false, LineFragmentKind::Wild) => assets::bold_wild(),
+        (true, LineFragmentKind::Horizontal) => assets::bold_active_horizontal(),
+        (false, LineFragmentKind::Horizontal) => assets::bold_horizontal(),
+        (true, LineFragmentKind::Vertical) => assets::bold_active_vertical(),
+        (false, LineFragmentKind::Vertical) => assets::bold_vertical(),
+    }
+}
+
+/// Draws a single cell of the grid and swaps its image when that cell changes, so the board
+/// doesn't have to rebuild every cell when one of them changes.
+struct CellWidget {
+    grid_pos: GridPos,
+    image: Box<dyn Widget<AppState>>,
+}
+
+impl CellWidget {
+    fn new(grid_pos: GridPos, cell: &Cell, style: SpriteStyle) -> Self {
+        Self {
+            grid_pos,
+            image: build_cell_image(cell, style),
+        }
+    }
+}
+
+impl Widget<AppState> for CellWidget {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut AppState, env: &Env) {
+        self.image.event(ctx, event, data, env)
+    }
+
+    fn lifecycle(&mut self, ctx: &mut LifeCycleCtx, event: &LifeCycle, data: &AppState, env: &Env) {
+        self.image.lifecycle(ctx, event, data, env)
+    }
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &AppState, data: &AppState, env: &Env) {
+        let old_cell = old_data.grid.get_cell_at_pos(&self.grid_pos);
+        let style_changed = data.sprite_style != old_data.sprite_style;
+        match data.grid.get_cell_at_pos(&self.grid_pos) {
+            Some(cell) if Some(cell) != old_cell || style_changed => {
+                trace!("updating cell at {} to {:?}", self.grid_pos, cell);
+                self.image = build_cell_image(cell, data.sprite_style);
+                ctx.request_paint();
             }
+            _ => self.image.update(ctx, old_data, data, env),
+        }
+    }
 
-            container.add_child(child);
-        });
+    fn layout(
+        &mut self,
+        ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        data: &AppState,
+        env: &Env,
+    ) -> Size {
+        self.image.layout(ctx, bc, data, env)
+    }
 
-    container.boxed()
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &AppState, env: &Env) {
+        self.image.paint(ctx, data, env)
+    }
 }
 
 struct GameBoardWidget {
@@ -53,12 +146,26 @@ struct GameBoardWidget {
     timer_id: TimerToken,
     cell_size: Size,
     last_update: Instant,
-    children: Box<dyn Widget<AppState>>,
+    cells: Vec<WidgetPod<AppState, CellWidget>>,
 }
 
 impl GameBoardWidget {
     fn rebuild_inner(&mut self, data: &AppState) {
-        self.children = build_widget(&data);
+        self.cells = build_cells(data);
+    }
+
+    fn cell_mut(&mut self, grid_pos: GridPos) -> Option<&mut WidgetPod<AppState, CellWidget>> {
+        if grid_pos.x < 0 || grid_pos.y < 0 || grid_pos.x as usize >= self.width {
+            return None;
+        }
+        let index = grid_pos.y as usize * self.width + grid_pos.x as usize;
+        self.cells.get_mut(index)
+    }
+
+    /// How long until the next tick, `data.iter_interval()` from now
+    fn next_tick(&mut self, data: &AppState) -> Duration {
+        self.last_update = Instant::now();
+        Duration::from_millis(data.iter_interval())
     }
 
     fn grid_pos(&self, p: Point) -> Option<GridPos> {
@@ -78,17 +185,8 @@ impl GameBoardWidget {
     }
 
     fn cursor_pos(&self, grid: &Grid, p: Point) -> Option<(GridPos, GridPos)> {
-        self.grid_pos(p)
-            .map(|a_pos| {
-                let b_pos = if a_pos.y == grid.height as isize - 1 {
-                    grid.below(a_pos)
-                } else {
-                    grid.above(a_pos)
-                };
-
-                b_pos.map(|b_pos| (a_pos, b_pos))
-            })
-            .flatten()
+        let a_pos = self.grid_pos(p)?;
+        grid.swap_partner(a_pos).map(|b_pos| (a_pos, b_pos))
     }
 }
 
@@ -96,16 +194,21 @@ impl Widget<AppState> for GameBoardWidget {
     fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut AppState, env: &Env) {
         match event {
             Event::WindowConnected => {
+                // The board handles the undo and redo shortcuts so it needs keyboard focus
+                ctx.request_focus();
                 ctx.request_paint();
-                let deadline = Duration::from_millis(data.iter_interval());
-                self.last_update = Instant::now();
-                self.timer_id = ctx.request_timer(deadline);
             }
-            Event::Timer(id) => {
-                if *id == self.timer_id {
-                    let deadline = Duration::from_millis(data.iter_interval());
-                    self.last_update = Instant::now();
-                    self.timer_id = ctx.request_timer(deadline);
+            Event::Timer(id) if *id == self.timer_id => {
+                if !data.paused && data.grid.is_topped_out() {
+                    // No room left for another row, so the timer stops here
+                    debug!("Stack reached the top, game over");
+                    data.game_over();
+                } else {
+                    // The timer keeps going while paused so the rise picks up again on resume
+                    if data.auto_rise && !data.paused {
+                        data.cycle_grid_rows();
+                    }
+                    self.timer_id = ctx.request_timer(self.next_tick(data));
                 }
             }
             Event::MouseDown(e) => {
@@ -113,22 +216,29 @@ impl Widget<AppState> for GameBoardWidget {
                     match e.button {
                         MouseButton::Right => {
                             if let Some(grid_pos) = self.grid_pos(e.pos) {
-                                data.grid.set_cell(&grid_pos, Cell::Empty);
+                                if let Err(err) = data.grid.set_cell(&grid_pos, Cell::Empty) {
+                                    debug!("Couldn't erase cell: {}", err);
+                                }
                             }
                         }
                         MouseButton::Left => {
                             if let Some(grid_pos) = self.grid_pos(e.pos) {
-                                let cell = match data.grid.get_cell_at_pos(&grid_pos).unwrap() {
-                                    Cell::Filled(LineFragment { kind, .. }) => match kind {
-                                        LineFragmentKind::Caret => Cell::from_str("i"),
-                                        LineFragmentKind::InvertedCaret => Cell::from_str("l"),
-                                        LineFragmentKind::LeftSlash => Cell::from_str("r"),
-                                        LineFragmentKind::RightSlash => Cell::from_str("."),
-                                    },
-                                    Cell::Empty => Cell::from_str("c"),
+                                let current_cell = data.grid.get_cell_at_pos(&grid_pos).unwrap();
+                                let cell = match current_cell.kind() {
+                                    Some(LineFragmentKind::Caret) => Cell::from_str("i"),
+                                    Some(LineFragmentKind::InvertedCaret) => Cell::from_str("l"),
+                                    Some(LineFragmentKind::LeftSlash) => Cell::from_str("r"),
+                                    Some(LineFragmentKind::RightSlash) => Cell::from_str("w"),
+                                    Some(LineFragmentKind::Wild) => Cell::from_str("h"),
+                                    Some(LineFragmentKind::Horizontal) => Cell::from_str("v"),
+                                    Some(LineFragmentKind::Vertical) => Cell::Blocked,
+                                    None if current_cell.is_blocked() => Cell::Empty,
+                                    None => Cell::from_str("c"),
                                 };
 
-                                data.grid.set_cell(&grid_pos, cell);
+                                if let Err(err) = data.grid.set_cell(&grid_pos, cell) {
+                                    debug!("Couldn't paint cell: {}", err);
+                                }
                             }
                         }
                         _ => (),
@@ -139,6 +249,13 @@ impl Widget<AppState> for GameBoardWidget {
                     }
                 }
             }
+            Event::KeyDown(e) => {
+                if HotKey::new(SysMods::CmdShift, "Z").matches(e) {
+                    data.redo();
+                } else if HotKey::new(SysMods::Cmd, "z").matches(e) {
+                    data.undo();
+                }
+            }
             Event::MouseUp(e) => {
                 if let Some(grid_pos) = self.grid_pos(e.pos) {
                     debug!("MouseUp event at {:?}", grid_pos);
@@ -152,54 +269,93 @@ impl Widget<AppState> for GameBoardWidget {
             _ => {}
         }
 
-        self.children.event(ctx, event, data, env)
+        for cell in &mut self.cells {
+            cell.event(ctx, event, data, env);
+        }
     }
 
     fn lifecycle(&mut self, ctx: &mut LifeCycleCtx, event: &LifeCycle, data: &AppState, env: &Env) {
         if let LifeCycle::WidgetAdded = event {
             self.rebuild_inner(data);
+            // The board is only added when a game starts, which is long after WindowConnected
+            self.timer_id = ctx.request_timer(self.next_tick(data));
+        }
+        for cell in &mut self.cells {
+            cell.lifecycle(ctx, event, data, env);
         }
-        self.children.lifecycle(ctx, event, data, env)
     }
 
     fn update(&mut self, ctx: &mut UpdateCtx, old_data: &AppState, data: &AppState, env: &Env) {
-        if !old_data.grid.same(&data.grid) {
+        let grid_resized =
+            old_data.grid.width != data.grid.width || old_data.grid.height != data.grid.height;
+
+        if grid_resized {
+            self.width = data.grid.width;
+            self.height = data.grid.height;
             self.rebuild_inner(data);
             ctx.children_changed();
-        } else {
-            self.children.update(ctx, old_data, data, env);
+        } else if data.sprite_style != old_data.sprite_style {
+            // Every image changes with the style
+            for cell in &mut self.cells {
+                cell.update(ctx, data, env);
+            }
+        } else if !old_data.grid.same(&data.grid) {
+            // Only the cells that changed need new images, the rest of the board stays put
+            let changed_positions = Grid::changed_positions(&old_data.grid, &data.grid);
+            trace!("{} cells changed", changed_positions.len());
+
+            for grid_pos in changed_positions {
+                if let Some(cell) = self.cell_mut(grid_pos) {
+                    cell.update(ctx, data, env);
+                }
+            }
         }
     }
 
     fn layout(
         &mut self,
         ctx: &mut LayoutCtx,
-        bc: &BoxConstraints,
+        _bc: &BoxConstraints,
         data: &AppState,
         env: &Env,
     ) -> Size {
-        self.children.layout(ctx, bc, data, env)
-    }
+        let cell_bc = BoxConstraints::tight(self.cell_size);
+        for (index, cell) in self.cells.iter_mut().enumerate() {
+            let x = index % self.width;
+            let y = index / self.width;
+            cell.layout(ctx, &cell_bc, data, env);
+            // Grid is rendered top to bottom so flipping the y value is necessary
+            let origin = Point::new(
+                x as f64 * self.cell_size.width,
+                (self.height - 1 - y) as f64 * self.cell_size.height,
+            );
+            cell.set_origin(ctx, origin);
+        }
 
-    fn paint(&mut self, ctx: &mut PaintCtx, data: &AppState, env: &Env) {
-        self.children.paint(ctx, data, env)
+        Size::new(
+            self.width as f64 * self.cell_size.width,
+            self.height as f64 * self.cell_size.height,
+        )
     }
 
-    fn id(&self) -> Option<WidgetId> {
-        self.children.id()
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &AppState, env: &Env) {
+        for cell in &mut self.cells {
+            cell.paint(ctx, data, env);
+        }
     }
 }
 
-pub fn make_widget() -> impl Widget<AppState> {
+/// A board for grids the size `config` describes
+pub fn make_widget(config: &GameConfig) -> impl Widget<AppState> {
     GameBoardWidget {
         timer_id: TimerToken::INVALID,
         cell_size: Size {
             width: 52.0,
             height: 32.0,
         },
-        height: 10,
-        width: 5,
+        height: config.height,
+        width: config.width,
         last_update: Instant::now(),
-        children: SizedBox::empty().boxed(),
+        cells: Vec::new(),
     }
 }
@@ -1,19 +1,22 @@
 mod game_board;
 
 use crate::app_state::{AppState, View};
+use crate::assets::SpriteStyle;
 use druid::widget::{LensWrap, Padding, Switch};
+use druid::LensExt;
 use druid::{
     widget::{Button, Flex, Label, ViewSwitcher, WidgetExt},
     Env, Widget,
 };
-use std::sync::Arc;
+use gunpey_lib::GameConfig;
 
 pub fn root() -> impl Widget<AppState> {
     ViewSwitcher::new(
         |data: &AppState, _env| *data.current_view(),
-        |selector, _data, _env| match selector {
+        |selector, data: &AppState, _env| match selector {
             View::Start => Box::new(start_screen()),
-            View::Game => Box::new(game_screen()),
+            View::Game => Box::new(game_screen(&data.config)),
+            View::GameOver => Box::new(game_over_screen()),
         },
     )
 }
@@ -25,7 +28,7 @@ pub fn start_screen() -> impl Widget<AppState> {
         .center();
 
     let start_game_button = Button::new("New Game")
-        .on_click(|_ctx, data: &mut AppState, _env: &Env| data.set_current_view(View::Game))
+        .on_click(|_ctx, data: &mut AppState, _env: &Env| data.new_game())
         .padding(5.0);
 
     Flex::column()
@@ -43,13 +46,33 @@ pub fn paint_mode_toggle() -> impl Widget<AppState> {
     row
 }
 
-pub fn game_screen() -> impl Widget<AppState> {
+pub fn high_contrast_toggle() -> impl Widget<AppState> {
+    let mut row = Flex::row();
+    let switch_label = Label::new("High Contrast");
+    let high_contrast = AppState::sprite_style.map(
+        |style| *style == SpriteStyle::HighContrast,
+        |style, high_contrast| {
+            *style = if high_contrast {
+                SpriteStyle::HighContrast
+            } else {
+                SpriteStyle::Normal
+            }
+        },
+    );
+    let high_contrast_toggle = LensWrap::new(Switch::new(), high_contrast);
+    row.add_child(Padding::new(5.0, switch_label));
+    row.add_child(Padding::new(5.0, high_contrast_toggle));
+
+    row
+}
+
+pub fn game_screen(config: &GameConfig) -> impl Widget<AppState> {
     let score = game_score_widget();
     // let game_grid = game_board::widget();
 
     let score_button = Button::new("Score a test point")
         .on_click(|_ctx, data: &mut AppState, _env: &Env| {
-            Arc::make_mut(&mut data.test).inner += 1;
+            data.score_points(1);
         })
         .padding(5.0);
 
@@ -59,25 +82,62 @@ pub fn game_screen() -> impl Widget<AppState> {
         })
         .padding(5.0);
 
-    let back_button = Button::new("Back to main menu")
-        .on_click(|_ctx, data: &mut AppState, _env: &Env| {
-            data.reset_score();
-            data.set_current_view(View::Start);
-        })
-        .padding(5.0);
+    let auto_rise_button = Button::dynamic(|data: &AppState, _env: &Env| {
+        if data.auto_rise {
+            "Stop rising".to_string()
+        } else {
+            "Start rising".to_string()
+        }
+    })
+    .on_click(|_ctx, data: &mut AppState, _env: &Env| data.toggle_auto_rise())
+    .padding(5.0);
+
+    let pause_button = Button::dynamic(|data: &AppState, _env: &Env| {
+        if data.paused {
+            "Resume".to_string()
+        } else {
+            "Pause".to_string()
+        }
+    })
+    .on_click(|_ctx, data: &mut AppState, _env: &Env| data.toggle_pause())
+    .padding(5.0);
 
     Flex::column()
         .with_child(score)
-        .with_child(game_board::make_widget())
+        .with_child(game_board::make_widget(config))
+        .with_child(pause_button)
         .with_child(score_button)
         .with_child(paint_mode_toggle())
+        .with_child(high_contrast_toggle())
         .with_child(new_row_button)
-        .with_child(back_button)
+        .with_child(auto_rise_button)
+        .with_child(back_button())
+}
+
+pub fn game_over_screen() -> impl Widget<AppState> {
+    let label = Label::new("Game Over")
+        .with_text_size(40.0)
+        .padding(10.0)
+        .center();
+
+    Flex::column()
+        .with_child(label)
+        .with_child(game_score_widget())
+        .with_child(back_button())
+}
+
+fn back_button() -> impl Widget<AppState> {
+    Button::new("Back to main menu")
+        .on_click(|_ctx, data: &mut AppState, _env: &Env| {
+            data.reset_score();
+            data.set_current_view(View::Start);
+        })
+        .padding(5.0)
 }
 
 fn game_score_widget() -> impl Widget<AppState> {
     let score_label = Label::new("Score:");
-    let score_counter = Label::new(|data: &AppState, _env: &Env| data.test.inner.to_string());
+    let score_counter = Label::new(|data: &AppState, _env: &Env| data.points().to_string());
 
     Flex::row()
         .with_child(score_label)
@@ -5,17 +5,143 @@ use std::io::Cursor;
 #[derive(Debug, Eq, Hash, PartialEq, Clone, Copy)]
 pub enum Asset {
     ActiveCaret,
+    ActiveHorizontal,
     ActiveInvertedCaret,
     ActiveLeftSlash,
     ActiveRightSlash,
+    ActiveVertical,
+    ActiveWild,
+    Blocked,
     Caret,
     Cursor,
     EmptyCell,
+    Horizontal,
     InvertedCaret,
     LeftSlash,
     RightSlash,
+    Vertical,
+    Wild,
 }
 
+impl Asset {
+    pub const ALL: [Asset; 17] = [
+        Asset::ActiveCaret,
+        Asset::ActiveHorizontal,
+        Asset::ActiveInvertedCaret,
+        Asset::ActiveLeftSlash,
+        Asset::ActiveRightSlash,
+        Asset::ActiveVertical,
+        Asset::ActiveWild,
+        Asset::Blocked,
+        Asset::Caret,
+        Asset::Cursor,
+        Asset::EmptyCell,
+        Asset::Horizontal,
+        Asset::InvertedCaret,
+        Asset::LeftSlash,
+        Asset::RightSlash,
+        Asset::Vertical,
+        Asset::Wild,
+    ];
+
+    /// The PNG this asset is loaded from. Only fragments and blocked cells have high contrast
+    /// versions, the cursor and empty cells look the same in both styles.
+    pub fn file_name(&self, style: SpriteStyle) -> &'static str {
+        use Asset::*;
+        use SpriteStyle::*;
+
+        match (style, self) {
+            (HighContrast, ActiveCaret) => "bold_active_caret.png",
+            (HighContrast, ActiveHorizontal) => "bold_active_horizontal.png",
+            (HighContrast, ActiveInvertedCaret) => "bold_active_inverted_caret.png",
+            (HighContrast, ActiveLeftSlash) => "bold_active_left_slash.png",
+            (HighContrast, ActiveRightSlash) => "bold_active_right_slash.png",
+            (HighContrast, ActiveVertical) => "bold_active_vertical.png",
+            (HighContrast, ActiveWild) => "bold_active_wild.png",
+            (HighContrast, Blocked) => "bold_blocked.png",
+            (HighContrast, Caret) => "bold_caret.png",
+            (HighContrast, Horizontal) => "bold_horizontal.png",
+            (HighContrast, InvertedCaret) => "bold_inverted_caret.png",
+            (HighContrast, LeftSlash) => "bold_left_slash.png",
+            (HighContrast, RightSlash) => "bold_right_slash.png",
+            (HighContrast, Vertical) => "bold_vertical.png",
+            (HighContrast, Wild) => "bold_wild.png",
+            (_, ActiveCaret) => "active_caret.png",
+            (_, ActiveHorizontal) => "active_horizontal.png",
+            (_, ActiveInvertedCaret) => "active_inverted_caret.png",
+            (_, ActiveLeftSlash) => "active_left_slash.png",
+            (_, ActiveRightSlash) => "active_right_slash.png",
+            (_, ActiveVertical) => "active_vertical.png",
+            (_, ActiveWild) => "active_wild.png",
+            (_, Blocked) => "blocked.png",
+            (_, Caret) => "caret.png",
+            (_, Cursor) => "cursor.png",
+            (_, EmptyCell) => "empty_cell.png",
+            (_, Horizontal) => "horizontal.png",
+            (_, InvertedCaret) => "inverted_caret.png",
+            (_, LeftSlash) => "left_slash.png",
+            (_, RightSlash) => "right_slash.png",
+            (_, Vertical) => "vertical.png",
+            (_, Wild) => "wild.png",
+        }
+    }
+}
+
+/// Which set of sprites fragments are drawn with
+#[derive(Debug, Default, Eq, PartialEq, Clone, Copy)]
+pub enum SpriteStyle {
+    #[default]
+    Normal,
+    /// Thicker lines, with active fragments drawn dark on a light cell so they stand out
+    HighContrast,
+}
+
+impl SpriteStyle {
+    pub fn toggled(&self) -> Self {
+        match self {
+            SpriteStyle::Normal => SpriteStyle::HighContrast,
+            SpriteStyle::HighContrast => SpriteStyle::Normal,
+        }
+    }
+}
+
+/// Every PNG embedded in the binary along with its file name
+#[rustfmt::skip]
+const PNGS: &[(&str, &[u8])] = &[
+    ("active_caret.png", include_bytes!("active_caret.png")),
+    ("active_horizontal.png", include_bytes!("active_horizontal.png")),
+    ("active_inverted_caret.png", include_bytes!("active_inverted_caret.png")),
+    ("active_left_slash.png", include_bytes!("active_left_slash.png")),
+    ("active_right_slash.png", include_bytes!("active_right_slash.png")),
+    ("active_vertical.png", include_bytes!("active_vertical.png")),
+    ("active_wild.png", include_bytes!("active_wild.png")),
+    ("bold_active_caret.png", include_bytes!("bold_active_caret.png")),
+    ("bold_active_horizontal.png", include_bytes!("bold_active_horizontal.png")),
+    ("bold_active_inverted_caret.png", include_bytes!("bold_active_inverted_caret.png")),
+    ("bold_active_left_slash.png", include_bytes!("bold_active_left_slash.png")),
+    ("bold_active_right_slash.png", include_bytes!("bold_active_right_slash.png")),
+    ("bold_active_vertical.png", include_bytes!("bold_active_vertical.png")),
+    ("bold_active_wild.png", include_bytes!("bold_active_wild.png")),
+    ("blocked.png", include_bytes!("blocked.png")),
+    ("bold_blocked.png", include_bytes!("bold_blocked.png")),
+    ("bold_caret.png", include_bytes!("bold_caret.png")),
+    ("bold_horizontal.png", include_bytes!("bold_horizontal.png")),
+    ("bold_inverted_caret.png", include_bytes!("bold_inverted_caret.png")),
+    ("bold_left_slash.png", include_bytes!("bold_left_slash.png")),
+    ("bold_right_slash.png", include_bytes!("bold_right_slash.png")),
+    ("bold_vertical.png", include_bytes!("bold_vertical.png")),
+    ("bold_wild.png", include_bytes!("bold_wild.png")),
+    ("caret.png", include_bytes!("caret.png")),
+    ("cursor.png", include_bytes!("cursor.png")),
+    ("empty_cell.png", include_bytes!("empty_cell.png")),
+    ("horizontal.png", include_bytes!("horizontal.png")),
+    ("inverted_caret.png", include_bytes!("inverted_caret.png")),
+    ("left_slash.png", include_bytes!("left_slash.png")),
+    ("right_slash.png", include_bytes!("right_slash.png")),
+    ("vertical.png", include_bytes!("vertical.png")),
+    ("wild.png", include_bytes!("wild.png")),
+];
+
 pub type RawSprite = (usize, usize, Vec<u8>);
 
 /// A list of assets loaded into memory.
@@ -30,27 +156,23 @@ impl Assets {
     }
 }
 
-/// Load all static assets into an `Assets` structure
-#[rustfmt::skip]
-pub fn load_assets() -> Assets {
-    use Asset::*;
-
-    let mut sprites = HashMap::new();
-
-    sprites.insert( ActiveCaret, load_png(include_bytes!("active_caret.png")));
-    sprites.insert( ActiveInvertedCaret, load_png(include_bytes!("active_inverted_caret.png")));
-    sprites.insert( ActiveLeftSlash, load_png(include_bytes!("active_left_slash.png")));
-    sprites.insert( ActiveRightSlash, load_png(include_bytes!("active_right_slash.png")));
-    sprites.insert( Caret, load_png(include_bytes!("caret.png")));
-    sprites.insert( Cursor, load_png(include_bytes!("cursor.png")));
-    sprites.insert( EmptyCell, load_png(include_bytes!("empty_cell.png")));
-    sprites.insert( InvertedCaret, load_png(include_bytes!("inverted_caret.png")));
-    sprites.insert( LeftSlash, load_png(include_bytes!("left_slash.png")));
-    sprites.insert( RightSlash, load_png(include_bytes!("right_slash.png")));
+/// Load all static assets in the given style into an `Assets` structure
+pub fn load_assets(style: SpriteStyle) -> Assets {
+    let sprites = Asset::ALL
+        .iter()
+        .map(|asset| (*asset, load_png(embedded_png(asset.file_name(style)))))
+        .collect();
 
     Assets { sprites }
 }
 
+fn embedded_png(file_name: &str) -> &'static [u8] {
+    PNGS.iter()
+        .find(|(name, _)| *name == file_name)
+        .map(|(_, png)| *png)
+        .unwrap_or_else(|| panic!("{} isn't embedded", file_name))
+}
+
 /// Convert PNG data to raw pixels
 fn load_png(png: &[u8]) -> RawSprite {
     let img = ImageReader::new(Cursor::new(png))
@@ -83,4 +205,35 @@ mod tests {
         assert_eq!(height, 5, "Height differs");
         assert_eq!(pixels, expected, "Pixels differ");
     }
+
+    #[test]
+    fn test_file_name_by_style() {
+        assert_eq!(Asset::Caret.file_name(SpriteStyle::Normal), "caret.png");
+        assert_eq!(
+            Asset::Caret.file_name(SpriteStyle::HighContrast),
+            "bold_caret.png"
+        );
+        assert_eq!(
+            Asset::ActiveWild.file_name(SpriteStyle::Normal),
+            "active_wild.png"
+        );
+        assert_eq!(
+            Asset::ActiveWild.file_name(SpriteStyle::HighContrast),
+            "bold_active_wild.png"
+        );
+        assert_eq!(
+            Asset::Cursor.file_name(SpriteStyle::HighContrast),
+            "cursor.png"
+        );
+    }
+
+    #[test]
+    fn test_every_style_is_embedded() {
+        for style in [SpriteStyle::Normal, SpriteStyle::HighContrast] {
+            for asset in Asset::ALL {
+                let (width, height, _) = load_png(embedded_png(asset.file_name(style)));
+                assert_eq!((width, height), (16, 16), "{:?} in {:?}", asset, style);
+            }
+        }
+    }
 }
@@ -106,6 +106,10 @@ impl Gui {
 
                 ui.separator();
 
+                ui.monospace(format!("Score {}", world.score.points));
+
+                ui.separator();
+
                 ui.horizontal(|ui| {
                     ui.spacing_mut().item_spacing.x /= 2.0;
                     if ui.button("Cycle rows").clicked() {
@@ -115,6 +119,10 @@ impl Gui {
                     if ui.button("Reset grid").clicked() {
                         world.reset_grid();
                     }
+
+                    if ui.button("High contrast").clicked() {
+                        world.toggle_sprite_style();
+                    }
                 });
 
                 ui.separator();
@@ -0,0 +1,121 @@
+use egui::{Pos2, Rect};
+use gunpey_lib::grid_pos::GridPos;
+
+/// Where the grid's cells sit on screen. Clicks and drawing both go through this so they can't
+/// disagree about where a cell is, whatever size the grid is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GridLayout {
+    /// Top left corner of the top left cell
+    origin: Pos2,
+    width: usize,
+    height: usize,
+    cell_size: f32,
+}
+
+impl GridLayout {
+    /// Center a `width` by `height` grid of square cells inside `screen`. The origin is snapped
+    /// to whole pixels so cells line up with the frame buffer.
+    pub fn centered(width: usize, height: usize, cell_size: usize, screen: Rect) -> Self {
+        let cell_size = cell_size as f32;
+        let origin = Pos2::new(
+            (screen.center().x - width as f32 * cell_size / 2.0).floor(),
+            (screen.center().y - height as f32 * cell_size / 2.0).floor(),
+        );
+
+        Self {
+            origin,
+            width,
+            height,
+            cell_size,
+        }
+    }
+
+    /// The area covered by the cells
+    pub fn rect(&self) -> Rect {
+        Rect::from_min_size(
+            self.origin,
+            egui::vec2(
+                self.width as f32 * self.cell_size,
+                self.height as f32 * self.cell_size,
+            ),
+        )
+    }
+
+    /// Top left corner of the cell at `pos`. Row 0 is the bottom row, so it's drawn last.
+    pub fn cell_top_left(&self, pos: GridPos) -> Pos2 {
+        let rows_from_top = self.height as isize - 1 - pos.y;
+
+        Pos2::new(
+            self.origin.x + pos.x as f32 * self.cell_size,
+            self.origin.y + rows_from_top as f32 * self.cell_size,
+        )
+    }
+
+    /// The cell under the pixel at `p`, or `None` if it's outside the grid
+    pub fn grid_pos(&self, p: Pos2) -> Option<GridPos> {
+        let x = ((p.x - self.origin.x) / self.cell_size).floor() as isize;
+        let rows_from_top = ((p.y - self.origin.y) / self.cell_size).floor() as isize;
+        let y = self.height as isize - 1 - rows_from_top;
+
+        if x < 0 || x >= self.width as isize || y < 0 || y >= self.height as isize {
+            None
+        } else {
+            Some(GridPos { x, y })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gunpey_lib::grid_pos::gp;
+
+    fn screen() -> Rect {
+        Rect::from_x_y_ranges(0.0..=320.0, 0.0..=256.0)
+    }
+
+    #[test]
+    fn test_corner_pixels_of_a_7x9_board() {
+        let layout = GridLayout::centered(7, 9, 16, screen());
+        let rect = layout.rect();
+
+        // The last pixel inside each edge, not the edge itself
+        let (left, right) = (rect.left(), rect.right() - 1.0);
+        let (top, bottom) = (rect.top(), rect.bottom() - 1.0);
+        assert_eq!(Some(gp(0, 8)), layout.grid_pos(Pos2::new(left, top)));
+        assert_eq!(Some(gp(6, 8)), layout.grid_pos(Pos2::new(right, top)));
+        assert_eq!(Some(gp(0, 0)), layout.grid_pos(Pos2::new(left, bottom)));
+        assert_eq!(Some(gp(6, 0)), layout.grid_pos(Pos2::new(right, bottom)));
+
+        // One pixel further out is off the board
+        assert_eq!(None, layout.grid_pos(Pos2::new(left - 1.0, top)));
+        assert_eq!(None, layout.grid_pos(Pos2::new(left, top - 1.0)));
+        assert_eq!(None, layout.grid_pos(Pos2::new(right + 1.0, bottom)));
+        assert_eq!(None, layout.grid_pos(Pos2::new(right, bottom + 1.0)));
+    }
+
+    #[test]
+    fn test_cells_are_drawn_where_they_are_clicked() {
+        for (width, height) in [(5, 10), (7, 9), (8, 12), (1, 2)] {
+            let layout = GridLayout::centered(width, height, 16, screen());
+            for x in 0..width as isize {
+                for y in 0..height as isize {
+                    let top_left = layout.cell_top_left(gp(x, y));
+                    let middle = top_left + egui::vec2(8.0, 8.0);
+
+                    assert_eq!(Some(gp(x, y)), layout.grid_pos(top_left));
+                    assert_eq!(Some(gp(x, y)), layout.grid_pos(middle));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_default_board_is_centered() {
+        let layout = GridLayout::centered(5, 10, 16, screen());
+
+        assert_eq!(Pos2::new(120.0, 48.0), layout.rect().left_top());
+        assert_eq!(Pos2::new(200.0, 208.0), layout.rect().right_bottom());
+        assert_eq!(Pos2::new(120.0, 192.0), layout.cell_top_left(gp(0, 0)));
+    }
+}
@@ -5,11 +5,16 @@ use std::convert::TryInto;
 use std::sync::Arc;
 
 use crate::gui::Gui;
-use assets::{Asset, Assets};
+use assets::{Asset, Assets, SpriteStyle};
 use egui::{Pos2, Rect};
-use gunpey_lib::grid_pos::GridPos;
-use gunpey_lib::{cell::Cell, grid::Grid, line_fragment::LineFragmentKind};
-use gunpey_lib::{new_random_row, new_small_grid, NewRowGenerationParams};
+use gunpey_lib::grid_pos::{gp, GridPos};
+use gunpey_lib::{grid::Grid, line_fragment::LineFragmentKind};
+use gunpey_lib::{
+    replay::{Replay, ReplayEvent},
+    score::Score,
+    GameConfig,
+};
+use layout::GridLayout;
 use log::{debug, error, trace};
 use pixels::{Error, Pixels, SurfaceTexture};
 use rand::prelude::*;
@@ -25,6 +30,7 @@ use winit_input_helper::WinitInputHelper;
 mod assets;
 // mod button;
 mod gui;
+mod layout;
 mod sprite;
 
 const WINDOW_WIDTH: u32 = 800;
@@ -43,9 +49,16 @@ pub struct World {
     // If Some, mouse pointer is over the screen,
     // If None, mouse pointer is outside the screen
     mouse_coordinates: Option<MouseCoordinates>,
+    // Moved with the arrow keys, shown whenever the mouse isn't over the grid
+    cursor: GridPos,
     assets: Assets,
+    sprite_style: SpriteStyle,
     grid: Grid,
+    score: Score,
     rng: Arc<StdRng>,
+    // Every move since the grid was last reset, rows come from its seed. Also holds the
+    // config the game is played with.
+    replay: Replay,
 }
 
 fn main() -> Result<(), Error> {
@@ -151,15 +164,25 @@ fn main() -> Result<(), Error> {
                     .and_then(|coords| coords.grid_space)
                     .and_then(|(x, y)| world.cursor_pos(GridPos::new(x, y)))
                 {
-                    match world.grid.swap_cells(cell_pos_a, cell_pos_b) {
-                        Ok(_) => {
-                            debug!("swapped {} with {}", cell_pos_a, cell_pos_b)
-                        }
-                        Err(e) => error!("can't swap, {}", e),
-                    }
+                    world.swap_cells(cell_pos_a, cell_pos_b);
                 }
             }
 
+            for (key, delta) in [
+                (VirtualKeyCode::Up, gp(0, 1)),
+                (VirtualKeyCode::Down, gp(0, -1)),
+                (VirtualKeyCode::Left, gp(-1, 0)),
+                (VirtualKeyCode::Right, gp(1, 0)),
+            ] {
+                if input.key_pressed(key) {
+                    world.move_cursor(delta);
+                }
+            }
+
+            if input.key_pressed(VirtualKeyCode::Space) {
+                world.swap_at_cursor();
+            }
+
             // Update internal state and request a redraw
             world.update();
             window.request_redraw();
@@ -167,12 +190,25 @@ fn main() -> Result<(), Error> {
     });
 }
 
+/// A fresh game played with `config`. Set GUNPEY_SEED, e.g. in .env, to get the same rows every
+/// game.
+fn new_replay(config: GameConfig) -> Replay {
+    let seed = match std::env::var("GUNPEY_SEED").map(|seed| seed.parse()) {
+        Ok(Ok(seed)) => seed,
+        _ => thread_rng().gen(),
+    };
+
+    Replay::new(seed, config)
+}
+
 impl World {
     /// Create a new `World` instance that can draw a moving box.
     fn new() -> Self {
-        let rng = Arc::new(SeedableRng::from_entropy());
-        let grid = new_small_grid();
-        let assets = assets::load_assets();
+        let replay = new_replay(GameConfig::default());
+        let rng = Arc::new(replay.rng());
+        let grid = replay.initial_grid();
+        let sprite_style = SpriteStyle::default();
+        let assets = assets::load_assets(sprite_style);
 
         Self {
             box_x: 24,
@@ -180,75 +216,85 @@ impl World {
             velocity_x: 1,
             velocity_y: 1,
             mouse_coordinates: None,
+            cursor: gp(0, 0),
             assets,
+            sprite_style,
             rng,
             grid,
+            score: Score::default(),
+            replay,
         }
     }
 
     pub fn swap_cells(&mut self, grid_pos_a: GridPos, grid_pos_b: GridPos) {
         debug!("Swapping tiles at {} and {}", grid_pos_a, grid_pos_b);
 
-        match self.grid.swap_cells(grid_pos_a, grid_pos_b) {
-            Ok(_) => self.grid.recalculate_active_cells(),
-            Err(err) => error!("Couldn't swap: {}", err),
-        };
+        let event = ReplayEvent::Swap(grid_pos_a, grid_pos_b);
+        let rng = Arc::make_mut(&mut self.rng);
+        let config = self.replay.config();
+        if let Err(err) = event.apply(&mut self.grid, rng, config, &mut self.score) {
+            error!("Couldn't swap: {}", err);
+        }
+        // Refused swaps still clear whatever was active, so they're recorded too
+        self.replay.record(event);
     }
 
-    pub fn cycle_grid_rows(&mut self) {
-        let new_row_params = NewRowGenerationParams {
-            width: self.grid.width,
-        };
+    /// Move the keyboard cursor by `delta`, stopping at the edges of the grid
+    pub fn move_cursor(&mut self, delta: GridPos) {
+        self.cursor = self.grid.clamp_cursor(self.cursor, delta);
+        trace!("keyboard cursor moved to {}", self.cursor);
+    }
+
+    /// Swap the cells under the keyboard cursor, the same pair a click there would swap
+    pub fn swap_at_cursor(&mut self) {
+        if let Some((cell_pos_a, cell_pos_b)) = self.cursor_pos(self.cursor) {
+            self.swap_cells(cell_pos_a, cell_pos_b);
+        }
+    }
 
-        let popped_row = self.grid.pop_top_row();
-        trace!("popped row: {:#?}", popped_row);
+    pub fn cycle_grid_rows(&mut self) {
+        if self.grid.is_topped_out() {
+            debug!("Not adding a row, the stack has reached the top");
+            return;
+        }
 
+        let event = ReplayEvent::CycleRows;
         let rng = Arc::make_mut(&mut self.rng);
-        let new_row = new_random_row(rng, new_row_params);
-        match self.grid.push_bottom_row(new_row) {
-            Ok(_) => self.grid.recalculate_active_cells(),
+        let config = self.replay.config();
+        match event.apply(&mut self.grid, rng, config, &mut self.score) {
+            Ok(()) => self.replay.record(event),
             Err(err) => error!("failed push_row_to_bottom_and_pop_row_from_top: {}", err),
         };
     }
 
-    pub fn reset_grid(&mut self) {
-        self.grid = Grid::new(5, 10);
+    /// Switch between the normal and high contrast sprites
+    pub fn toggle_sprite_style(&mut self) {
+        self.sprite_style = self.sprite_style.toggled();
+        debug!("switching to {:?} sprites", self.sprite_style);
+        self.assets = assets::load_assets(self.sprite_style);
     }
 
-    fn game_grid_rect(&self) -> Rect {
-        let game_grid_width = self.grid.width * CELL_SIZE;
-        let game_grid_height = self.grid.height * CELL_SIZE;
-
-        Rect::from_x_y_ranges(
-            0.0..=(game_grid_width as f32),
-            0.0..=(game_grid_height as f32),
-        )
+    pub fn reset_grid(&mut self) {
+        // Keep playing with the same config
+        self.replay = new_replay(self.replay.config().clone());
+        self.rng = Arc::new(self.replay.rng());
+        self.grid = self.replay.initial_grid();
     }
 
-    fn world_space_pos_to_grid_space_pos(&self, p: Pos2) -> Option<GridPos> {
-        let mut game_grid_rect = self.game_grid_rect();
+    /// The grid centered on the screen
+    fn layout(&self) -> GridLayout {
         let screen_rect =
             Rect::from_x_y_ranges(0.0..=(GAME_WIDTH as f32), 0.0..=(GAME_HEIGHT as f32));
-        game_grid_rect.set_center(screen_rect.center());
 
-        let x = ((p.x - game_grid_rect.left()) / CELL_SIZE as f32) as isize;
-        let y = ((game_grid_rect.bottom() - p.y) / CELL_SIZE as f32) as isize;
+        GridLayout::centered(self.grid.width, self.grid.height, CELL_SIZE, screen_rect)
+    }
 
-        if x < 0 || x >= self.grid.width as isize || y < 0 || y >= self.grid.height as isize {
-            None
-        } else {
-            Some(GridPos { x, y })
-        }
+    fn world_space_pos_to_grid_space_pos(&self, p: Pos2) -> Option<GridPos> {
+        self.layout().grid_pos(p)
     }
 
     fn cursor_pos(&self, a_pos: GridPos) -> Option<(GridPos, GridPos)> {
-        let b_pos = if a_pos.y == self.grid.height as isize - 1 {
-            self.grid.below(a_pos)
-        } else {
-            self.grid.above(a_pos)
-        };
-
-        b_pos.map(|b_pos| (a_pos, b_pos))
+        self.grid.swap_partner(a_pos).map(|b_pos| (a_pos, b_pos))
     }
 
     /// Update the `World` internal state; bounce the box around the screen.
@@ -293,21 +339,16 @@ impl World {
             pixel.copy_from_slice(&rgba);
         }
 
-        let mut game_grid_rect = self.game_grid_rect().expand(1.0);
-        let screen_rect =
-            Rect::from_x_y_ranges(0.0..=(GAME_WIDTH as f32), 0.0..=(GAME_HEIGHT as f32));
-        game_grid_rect.set_center(screen_rect.center());
-
+        let layout = self.layout();
         let mut row_index = 0;
-        let (x_origin, y_origin) = (
-            game_grid_rect.left() as usize + 1,
-            game_grid_rect.top() as usize + 1,
-        );
+        let (x_origin, y_origin) = (layout.rect().left() as usize, layout.rect().top() as usize);
 
+        // The mouse takes over from the keyboard cursor while it's over the grid
         let cursor_pos = self
             .mouse_coordinates
             .and_then(|coords| coords.grid_space)
-            .and_then(|p| self.cursor_pos(p.into()));
+            .and_then(|p| self.cursor_pos(p.into()))
+            .or_else(|| self.cursor_pos(self.cursor));
 
         self.grid
             .cell_rows_in_render_order()
@@ -317,22 +358,23 @@ impl World {
 
                 for (cell_index, cell) in row.into_iter().enumerate() {
                     let x = cell_index * CELL_SIZE + x_origin;
-                    let sprite = match cell {
-                        Cell::Filled(line_fragment) => {
-                            match (line_fragment.is_active, line_fragment.kind) {
-                                (true, LineFragmentKind::Caret) => Asset::ActiveCaret,
-                                (false, LineFragmentKind::Caret) => Asset::Caret,
-                                (true, LineFragmentKind::InvertedCaret) => {
-                                    Asset::ActiveInvertedCaret
-                                }
-                                (false, LineFragmentKind::InvertedCaret) => Asset::InvertedCaret,
-                                (true, LineFragmentKind::LeftSlash) => Asset::ActiveLeftSlash,
-                                (false, LineFragmentKind::LeftSlash) => Asset::LeftSlash,
-                                (true, LineFragmentKind::RightSlash) => Asset::ActiveRightSlash,
-                                (false, LineFragmentKind::RightSlash) => Asset::RightSlash,
-                            }
-                        }
-                        Cell::Empty => Asset::EmptyCell,
+                    let sprite = match (cell.is_active(), cell.kind()) {
+                        (_, None) if cell.is_blocked() => Asset::Blocked,
+                        (true, Some(LineFragmentKind::Caret)) => Asset::ActiveCaret,
+                        (false, Some(LineFragmentKind::Caret)) => Asset::Caret,
+                        (true, Some(LineFragmentKind::InvertedCaret)) => Asset::ActiveInvertedCaret,
+                        (false, Some(LineFragmentKind::InvertedCaret)) => Asset::InvertedCaret,
+                        (true, Some(LineFragmentKind::LeftSlash)) => Asset::ActiveLeftSlash,
+                        (false, Some(LineFragmentKind::LeftSlash)) => Asset::LeftSlash,
+                        (true, Some(LineFragmentKind::RightSlash)) => Asset::ActiveRightSlash,
+                        (false, Some(LineFragmentKind::RightSlash)) => Asset::RightSlash,
+                        (true, Some(LineFragmentKind::Wild)) => Asset::ActiveWild,
+                        (false, Some(LineFragmentKind::Wild)) => Asset::Wild,
+                        (true, Some(LineFragmentKind::Horizontal)) => Asset::ActiveHorizontal,
+                        (false, Some(LineFragmentKind::Horizontal)) => Asset::Horizontal,
+                        (true, Some(LineFragmentKind::Vertical)) => Asset::ActiveVertical,
+                        (false, Some(LineFragmentKind::Vertical)) => Asset::Vertical,
+                        (_, None) => Asset::EmptyCell,
                     };
 
                     blit(
@@ -342,36 +384,23 @@ impl World {
                         &Pos2::new(x as f32, y as f32),
                         &Sprite::new(&self.assets, sprite),
                     );
-
-                    if let Some((a_pos, b_pos)) = cursor_pos {
-                        let flip = (game_grid_rect.center().y
-                            + (CELL_SIZE * self.grid.height) as f32)
-                            / 2.0;
-                        let top_x = (a_pos.x as f32 * CELL_SIZE as f32) + x_origin as f32;
-                        let top_y = flip - (a_pos.y as f32 * CELL_SIZE as f32) + y_origin as f32;
-                        let bottom_x = (b_pos.x as f32 * CELL_SIZE as f32) + x_origin as f32;
-                        let bottom_y = flip - (b_pos.y as f32 * CELL_SIZE as f32) + y_origin as f32;
-
-                        blit(
-                            frame,
-                            GAME_WIDTH as usize,
-                            GAME_HEIGHT as usize,
-                            &Pos2::new(top_x, top_y),
-                            &Sprite::new(&self.assets, Asset::Cursor),
-                        );
-
-                        blit(
-                            frame,
-                            GAME_WIDTH as usize,
-                            GAME_HEIGHT as usize,
-                            &Pos2::new(bottom_x, bottom_y),
-                            &Sprite::new(&self.assets, Asset::Cursor),
-                        );
-                    }
                 }
                 row_index += 1;
             });
 
+        if let Some((a_pos, b_pos)) = cursor_pos {
+            for cursor_cell_pos in [a_pos, b_pos] {
+                blit(
+                    frame,
+                    GAME_WIDTH as usize,
+                    GAME_HEIGHT as usize,
+                    &layout.cell_top_left(cursor_cell_pos),
+                    &Sprite::new(&self.assets, Asset::Cursor),
+                );
+            }
+        }
+
+        let game_grid_rect = layout.rect().expand(1.0);
         rect(
             frame,
             GAME_WIDTH.try_into().unwrap(),
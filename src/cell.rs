@@ -1,14 +1,19 @@
 use std::fmt::Display;
 
 use crate::adjacency::{are_line_fragments_connecting, Adjacency};
-use crate::grid_pos::{gp, GridPos};
+use crate::error::GunpeyLibError;
+use crate::grid_pos::GridPos;
 use crate::line_fragment::{LineFragment, LineFragmentKind};
 use druid::Data;
 
 #[derive(Debug, Clone, Copy, PartialEq, Data)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Cell {
     Filled(LineFragment),
     Empty,
+    /// A hole in the board, written `#`. Nothing connects to it, it can't be swapped and
+    /// fragments can't fall through it.
+    Blocked,
 }
 
 impl Cell {
@@ -16,16 +21,37 @@ impl Cell {
         self == &Cell::Empty
     }
 
+    /// Whether the cell holds a fragment, as opposed to being empty or blocked
+    pub fn is_filled(&self) -> bool {
+        matches!(self, Cell::Filled(_))
+    }
+
+    pub fn is_blocked(&self) -> bool {
+        self == &Cell::Blocked
+    }
+
     pub fn is_active(&self) -> bool {
+        self.map_fragment(|lf| lf.is_active).unwrap_or_default()
+    }
+
+    /// The kind of line fragment in this cell, or `None` if the cell is empty or blocked.
+    pub fn kind(&self) -> Option<LineFragmentKind> {
+        self.map_fragment(|lf| lf.kind)
+    }
+
+    /// Call `f` with this cell's line fragment, or return `None` if the cell is empty or
+    /// blocked.
+    pub fn map_fragment<T>(&self, f: impl FnOnce(&LineFragment) -> T) -> Option<T> {
         match self {
-            Cell::Empty => false,
-            Cell::Filled(LineFragment { is_active, .. }) => *is_active,
+            Cell::Filled(lf) => Some(f(lf)),
+            Cell::Empty | Cell::Blocked => None,
         }
     }
 
     pub fn to_char(&self) -> char {
         match self {
             Cell::Empty => '.',
+            Cell::Blocked => '#',
             Cell::Filled(lf) => lf.to_char(),
         }
     }
@@ -33,25 +59,40 @@ impl Cell {
     pub fn to_str(&self) -> &'static str {
         match self {
             Cell::Empty => ".",
+            Cell::Blocked => "#",
             Cell::Filled(lf) => lf.to_str(),
         }
     }
 
     pub fn from_char(c: &char) -> Self {
-        if *c == '.' {
-            Cell::Empty
-        } else {
-            let lf = LineFragment::from_char(c);
-            Cell::Filled(lf)
+        match c {
+            '.' => Cell::Empty,
+            '#' => Cell::Blocked,
+            _ => Cell::Filled(LineFragment::from_char(c)),
+        }
+    }
+
+    /// Like `from_char` but returns an error for anything that isn't a fragment symbol, `.` or
+    /// `#` instead of panicking.
+    pub fn try_from_char(c: char) -> Result<Self, GunpeyLibError> {
+        match c {
+            '.' => Ok(Cell::Empty),
+            '#' => Ok(Cell::Blocked),
+            _ => {
+                let kind = LineFragmentKind::try_from_char(c)?;
+                Ok(Cell::Filled(LineFragment {
+                    kind,
+                    is_active: false,
+                }))
+            }
         }
     }
 
     pub fn from_str(cell_str: &str) -> Self {
-        if cell_str == "." {
-            Cell::Empty
-        } else {
-            let lf = LineFragment::from_str(cell_str);
-            Cell::Filled(lf)
+        match cell_str {
+            "." => Cell::Empty,
+            "#" => Cell::Blocked,
+            _ => Cell::Filled(LineFragment::from_str(cell_str)),
         }
     }
 
@@ -61,6 +102,20 @@ impl Cell {
         }
     }
 
+    /// `LineFragment::rotate_cw`, doing nothing to empty cells
+    pub fn rotate_cw(&mut self) {
+        if let Cell::Filled(lf) = self {
+            lf.rotate_cw();
+        }
+    }
+
+    /// `LineFragment::rotate_ccw`, doing nothing to empty cells
+    pub fn rotate_ccw(&mut self) {
+        if let Cell::Filled(lf) = self {
+            lf.rotate_ccw();
+        }
+    }
+
     pub fn deactivate(&mut self) {
         if let Cell::Filled(lf) = self {
             lf.is_active = false;
@@ -69,8 +124,8 @@ impl Cell {
 
     pub fn is_connected_to(&self, other: &Cell, adjacency: Adjacency) -> bool {
         match (self, other) {
-            // If either Cell is empty, then no connection can be made
-            (Cell::Empty, _) | (_, Cell::Empty) => false,
+            // If either Cell is empty or blocked, then no connection can be made
+            (Cell::Empty | Cell::Blocked, _) | (_, Cell::Empty | Cell::Blocked) => false,
             (Cell::Filled(lf_a), Cell::Filled(lf_b)) => {
                 are_line_fragments_connecting(lf_a, adjacency, lf_b)
             }
@@ -78,16 +133,14 @@ impl Cell {
     }
 
     pub fn corner_nodes(&self, cell_pos: &GridPos) -> Vec<GridPos> {
-        match self {
-            Cell::Filled(LineFragment { kind, .. }) => match kind {
-                LineFragmentKind::Caret => vec![*cell_pos, *cell_pos + gp(1, 0)],
-                LineFragmentKind::InvertedCaret => vec![*cell_pos + gp(0, 1), *cell_pos + gp(1, 1)],
-                LineFragmentKind::LeftSlash => vec![*cell_pos + gp(0, 1), *cell_pos + gp(1, 0)],
-                LineFragmentKind::RightSlash => vec![*cell_pos, *cell_pos + gp(1, 1)],
-            },
-
-            Cell::Empty => vec![],
-        }
+        self.kind()
+            .map(|kind| {
+                kind.corner_offsets()
+                    .into_iter()
+                    .map(|offset| *cell_pos + offset)
+                    .collect()
+            })
+            .unwrap_or_default()
     }
 
     pub fn has_corner_node(&self, cell_pos: &GridPos, node_pos: &GridPos) -> bool {
@@ -102,8 +155,124 @@ impl Display for Cell {
             "{}",
             match self {
                 &Cell::Empty => ".",
+                &Cell::Blocked => "#",
                 &Cell::Filled(lf) => lf.to_str(),
             }
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kind_of_filled_cell() {
+        let cell = Cell::from_str("L");
+
+        assert_eq!(Some(LineFragmentKind::LeftSlash), cell.kind());
+    }
+
+    #[test]
+    fn test_kind_of_empty_cell() {
+        let cell = Cell::Empty;
+
+        assert_eq!(None, cell.kind());
+    }
+
+    #[test]
+    fn test_map_fragment_of_filled_cell() {
+        let cell = Cell::from_str("C");
+
+        assert_eq!(Some(true), cell.map_fragment(|lf| lf.is_active));
+        assert_eq!(Some('∧'), cell.map_fragment(LineFragment::to_char));
+    }
+
+    #[test]
+    fn test_map_fragment_of_empty_cell() {
+        let cell = Cell::Empty;
+
+        assert_eq!(None, cell.map_fragment(|lf| lf.is_active));
+    }
+
+    #[test]
+    fn test_try_from_char() {
+        assert_eq!(Cell::from_char(&'/'), Cell::try_from_char('/').unwrap());
+        assert_eq!(Cell::Empty, Cell::try_from_char('.').unwrap());
+        assert!(matches!(
+            Cell::try_from_char('x'),
+            Err(GunpeyLibError::InvalidCellChar('x'))
+        ));
+    }
+
+    #[test]
+    fn test_four_rotations_return_to_the_start() {
+        for c in ["c", "i", "l", "r", "w", "h", "v"] {
+            let original = Cell::from_str(c);
+            let mut cw = original;
+            let mut ccw = original;
+
+            cw.rotate_cw();
+            ccw.rotate_ccw();
+            if original.kind() != Some(LineFragmentKind::Wild) {
+                assert_ne!(original, cw);
+                assert_ne!(original, ccw);
+            }
+
+            for _ in 0..3 {
+                cw.rotate_cw();
+                ccw.rotate_ccw();
+            }
+            assert_eq!(original, cw);
+            assert_eq!(original, ccw);
+        }
+    }
+
+    #[test]
+    fn test_rotate_cw_then_ccw() {
+        let mut cell = Cell::from_str("C");
+
+        cell.rotate_cw();
+        assert_eq!(Cell::from_str("r"), cell);
+
+        cell.rotate_ccw();
+        assert_eq!(Cell::from_str("c"), cell);
+    }
+
+    #[test]
+    fn test_rotate_empty_cell() {
+        let mut cell = Cell::Empty;
+
+        cell.rotate_cw();
+        cell.rotate_ccw();
+
+        assert_eq!(Cell::Empty, cell);
+    }
+
+    #[test]
+    fn test_blocked_cell() {
+        let blocked = Cell::from_char(&'#');
+
+        assert_eq!(Cell::Blocked, blocked);
+        assert_eq!(Cell::Blocked, Cell::from_str("#"));
+        assert_eq!(Cell::Blocked, Cell::try_from_char('#').unwrap());
+        assert_eq!('#', blocked.to_char());
+        assert_eq!("#", blocked.to_string());
+        assert!(blocked.is_blocked());
+        assert!(!blocked.is_empty());
+        assert!(!blocked.is_filled());
+        assert_eq!(None, blocked.kind());
+        assert!(blocked.corner_nodes(&GridPos::new(1, 1)).is_empty());
+    }
+
+    #[test]
+    fn test_blocked_cell_never_connects() {
+        let wild = Cell::from_str("w");
+
+        for adjacency in [Adjacency::Left, Adjacency::Above, Adjacency::BelowRight] {
+            assert!(!Cell::Blocked.is_connected_to(&wild, adjacency));
+            assert!(!wild.is_connected_to(&Cell::Blocked, adjacency));
+            assert!(!Cell::Blocked.is_connected_to(&Cell::Blocked, adjacency));
+        }
+    }
+}
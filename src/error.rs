@@ -1,4 +1,4 @@
-use crate::grid_pos::GridPos;
+use crate::{grid_pos::GridPos, line_fragment::Grammar};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -20,4 +20,52 @@ pub enum GunpeyLibError {
         "invalid row size, input row length is {0} which does not equal expected row length of {1}"
     )]
     InvalidRowLength(usize, usize),
+    #[error("'{0}' isn't a cell, expected one of c, i, l, r, w, h or v in either case, one of ∧, ∨, \\, /, *, ─ or │, '.' or '#'")]
+    InvalidCellChar(char),
+    #[error("a {width}x{height} grid is too small, grids need at least one column and two rows")]
+    InvalidDimensions { width: usize, height: usize },
+    #[error("too many rows, input has {0} rows but the grid is only {1} rows tall")]
+    TooManyRows(usize, usize),
+    #[error("can't swap the blocked cell at {0}")]
+    CantSwapBlocked(GridPos),
+    #[error("can't swap tiles a={0} and b={1} because active tiles are protected")]
+    SwapProtectedActive(GridPos, GridPos),
+    #[error("position {pos} is out of bounds (width: {width}, height: {height})")]
+    OutOfBounds {
+        pos: GridPos,
+        width: usize,
+        height: usize,
+    },
+    #[error("column {x} is out of bounds (width: {width})")]
+    ColumnOutOfBounds { x: usize, width: usize },
+    #[error("row {y} is out of bounds (height: {height})")]
+    RowOutOfBounds { y: usize, height: usize },
+    #[error("grid has {cells} cells but should have {width}x{height}")]
+    CellCountMismatch {
+        cells: usize,
+        width: usize,
+        height: usize,
+    },
+    #[error("expected {expected} bytes of RGBA pixels but got {actual}")]
+    InvalidImageSize { expected: usize, actual: usize },
+    #[error("can't decode an image without any sprites to match against")]
+    NoSprites,
+    #[error("'{character}' at row {row}, column {col} is a {} but this grid is written with {grammar}s", grammar.other())]
+    WrongGrammar {
+        character: char,
+        row: usize,
+        col: usize,
+        grammar: Grammar,
+    },
+    #[error("'{character}' at row {row}, column {col} isn't a valid {grammar} or '.'")]
+    InvalidCharacter {
+        character: char,
+        row: usize,
+        col: usize,
+        grammar: Grammar,
+    },
+    #[error("couldn't read or write a file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid compact grid string \"{0}\", expected something like \"3x2:.c./r.i\"")]
+    InvalidCompactString(String),
 }
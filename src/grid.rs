@@ -1,29 +1,42 @@
-use crate::adjacency::adjacency_of_grid_positions;
+use crate::adjacency::{adjacency_of_grid_positions, Adjacency};
 use crate::cell::Cell;
-use crate::grid_algorithms::index_to_corner_nodes;
-use crate::grid_iterator_2d::{new_xy_iter, GridIterDirectionX, GridIterDirectionY};
-use crate::grid_pos::gp;
-use crate::{
-    error::GunpeyLibError,
-    grid_pos::GridPos,
-    line_fragment::{LineFragment, LineFragmentKind},
+use crate::grid_iterator_2d::{
+    new_index_iter, new_xy_iter, GridIterDirectionX, GridIterDirectionY,
 };
+use crate::grid_pos::gp;
+use crate::line_fragment::{Grammar, LineFragment};
+use crate::{error::GunpeyLibError, grid_pos::GridPos, line_fragment::LineFragmentKind};
 use druid::{im::Vector, Data};
 use log::{debug, trace};
-use std::cell::RefCell;
-use std::collections::{HashMap, HashSet};
+use rand::{seq::SliceRandom, Rng};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::fmt::Display;
 
 #[derive(Debug, Clone, Data, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Grid {
     pub width: usize,
     pub height: usize,
     pub cells: Vector<Cell>,
+    /// When set, `swap_cells` refuses to move cells that are part of an active chain
+    pub protect_active: bool,
+    /// When set, chains are cleared as soon as recalculation finds them and the cells above fall
+    /// into the gaps, so the grid never has any active cells
+    pub auto_clear_on_recalc: bool,
 }
 
 type CharGrid = Vec<Vec<char>>;
 type Bitmask = Vec<Vec<u8>>;
 
+/// The most spans `Grid::complete_spans` will return. A board covered in wild fragments has an
+/// enormous number of distinct paths across it so the search stops once it finds this many.
+pub const MAX_COMPLETE_SPANS: usize = 64;
+
+/// How many steps `Grid::complete_spans` takes through the grid before giving up. Paths that
+/// can't reach the right side are skipped, but a board full of wild fragments can still have
+/// more dead ends than are worth walking.
+pub const MAX_SPAN_SEARCH_STEPS: usize = 100_000;
+
 impl Grid {
     pub fn new(width: usize, height: usize) -> Self {
         assert!(width > 0, "width of new Grid must be greater than 0!");
@@ -32,37 +45,130 @@ impl Grid {
         let length = width * height;
         let cells = (0..length).map(|_| Cell::Empty).collect();
 
+        Self::from_parts(width, height, cells)
+    }
+
+    /// A grid made of `cells` with every mode flag off. Every constructor goes through here so
+    /// new flags only need a default in one place.
+    fn from_parts(width: usize, height: usize, cells: Vector<Cell>) -> Self {
         Self {
             width,
             height,
             cells,
+            protect_active: false,
+            auto_clear_on_recalc: false,
         }
     }
 
-    pub fn new_from_str(grid_str: &str) -> Self {
-        let rows: Vec<_> = grid_str.trim().split("\n").map(str::trim).collect();
+    /// Parse a grid written with letters, one row per line with the top row first. Errors if
+    /// there's a character that isn't a letter or `.`, if the rows aren't all the same length or
+    /// if the grid would be smaller than `Grid::new` allows.
+    pub fn new_from_str(grid_str: &str) -> Result<Self, GunpeyLibError> {
+        Self::try_from_str(grid_str).map_err(|err| match err {
+            GunpeyLibError::InvalidCharacter { character, .. }
+            | GunpeyLibError::WrongGrammar { character, .. } => {
+                GunpeyLibError::InvalidCellChar(character)
+            }
+            err => err,
+        })
+    }
 
-        let width = rows[0].len();
-        let height = rows.len();
-        assert!(width > 0, "width of new Grid must be greater than 0!");
-        assert!(height > 1, "height of new Grid must be greater than 1!");
+    /// Like `new_from_str` but errors say where the bad character is and point out characters
+    /// that belong to the symbol grammar. Rows and columns in the error count from zero starting
+    /// at the top left of the string.
+    pub fn try_from_str(grid_str: &str) -> Result<Self, GunpeyLibError> {
+        Self::try_from_grammar(grid_str, Grammar::Letters)
+    }
 
-        let cells = rows
-            .iter()
-            .rev()
-            .flat_map(|row| row.split("").filter(|&c| !c.is_empty()).map(Cell::from_str))
+    /// Like `try_from_str` but for grids written with symbols, see `new_from_chars`.
+    pub fn try_from_symbols(grid_str: &str) -> Result<Self, GunpeyLibError> {
+        Self::try_from_grammar(grid_str, Grammar::Symbols)
+    }
+
+    fn try_from_grammar(grid_str: &str, grammar: Grammar) -> Result<Self, GunpeyLibError> {
+        let rows: Vec<Vec<char>> = grid_str
+            .trim()
+            .lines()
+            .map(|row| row.trim().chars().collect())
             .collect();
+        if rows.is_empty() {
+            return Err(GunpeyLibError::InvalidDimensions {
+                width: 0,
+                height: 0,
+            });
+        }
+
+        let mut cell_rows = Vec::with_capacity(rows.len());
+        for (row, chars) in rows.iter().enumerate() {
+            let mut cell_row = Vector::new();
+            for (col, &character) in chars.iter().enumerate() {
+                let cell = match grammar {
+                    Grammar::Letters => cell_from_letter(character),
+                    Grammar::Symbols => Cell::try_from_char(character),
+                };
+                match cell {
+                    Ok(cell) => cell_row.push_back(cell),
+                    Err(_) if grammar.other().accepts(character) => {
+                        return Err(GunpeyLibError::WrongGrammar {
+                            character,
+                            row,
+                            col,
+                            grammar,
+                        })
+                    }
+                    Err(_) => {
+                        return Err(GunpeyLibError::InvalidCharacter {
+                            character,
+                            row,
+                            col,
+                            grammar,
+                        })
+                    }
+                }
+            }
+
+            if chars.len() != rows[0].len() {
+                return Err(GunpeyLibError::InvalidRowLength(chars.len(), rows[0].len()));
+            }
+            cell_rows.push(cell_row);
+        }
+
+        let width = rows[0].len();
+        let height = rows.len();
+        if width == 0 || height < 2 {
+            return Err(GunpeyLibError::InvalidDimensions { width, height });
+        }
 
         debug!(
             "creating new grid from str with width={}, height={}",
             width, height
         );
 
-        Self {
-            width,
-            height,
-            cells,
+        // Rows are written top first but stored bottom first
+        let mut cells = Vector::new();
+        for cell_row in cell_rows.into_iter().rev() {
+            cells.append(cell_row);
         }
+
+        Ok(Self::from_parts(width, height, cells))
+    }
+
+    /// Like `new_from_str` but every line starting with `#` is skipped and anything after ` #` on
+    /// a row is ignored, so level files can describe what each row is for. That means a row here
+    /// can't start with a blocked cell, use `new_from_str` for grids that need one.
+    pub fn from_annotated_str(grid_str: &str) -> Result<Self, GunpeyLibError> {
+        let rows: Vec<_> = grid_str
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| match line.find(" #") {
+                Some(comment_start) => line[..comment_start].trim_end(),
+                None => line,
+            })
+            .filter(|line| !line.is_empty())
+            .collect();
+
+        Self::new_from_str(&rows.join("\n"))
     }
 
     pub fn new_from_chars(chars: CharGrid) -> Self {
@@ -82,11 +188,159 @@ impl Grid {
             width, height
         );
 
-        Self {
+        Self::from_parts(width, height, cells)
+    }
+
+    /// Fill the bottom of the grid from a list of row patterns, then recalculate. Patterns use
+    /// the same letters as `new_from_str` but are ordered bottom-up, so `patterns[0]` becomes
+    /// the bottom row. Rows above the last pattern are left alone.
+    ///
+    /// Every pattern is validated before anything is changed, so on error the grid is untouched.
+    pub fn seed_from_patterns(&mut self, patterns: &[&str]) -> Result<(), GunpeyLibError> {
+        if patterns.len() > self.height {
+            return Err(GunpeyLibError::TooManyRows(patterns.len(), self.height));
+        }
+
+        let rows = patterns
+            .iter()
+            .map(|pattern| {
+                let row: Vec<_> = pattern
+                    .trim()
+                    .split("")
+                    .filter(|&c| !c.is_empty())
+                    .map(Cell::from_str)
+                    .collect();
+
+                if row.len() == self.width {
+                    Ok(row)
+                } else {
+                    Err(GunpeyLibError::InvalidRowLength(row.len(), self.width))
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (y, row) in rows.into_iter().enumerate() {
+            let start_of_row = y * self.width;
+            for (x, cell) in row.into_iter().enumerate() {
+                self.cells[start_of_row + x] = cell;
+            }
+        }
+
+        self.recalculate_active_cells();
+
+        Ok(())
+    }
+
+    /// Serialize the grid to a single line like `"3x2:.c./r.i"`: the dimensions, then each row
+    /// from top to bottom separated by `/`. Cells use the same letters as `new_from_str`, so
+    /// active flags survive a round trip through `from_compact_string`.
+    pub fn to_compact_string(&self) -> String {
+        let rows: Vec<String> = self
+            .cell_rows_in_render_order()
+            .iter()
+            .map(|row| row.iter().map(Cell::to_str).collect())
+            .collect();
+
+        format!("{}x{}:{}", self.width, self.height, rows.join("/"))
+    }
+
+    /// Parse a grid from the format produced by `to_compact_string`.
+    pub fn from_compact_string(compact_str: &str) -> Result<Self, GunpeyLibError> {
+        let invalid = || GunpeyLibError::InvalidCompactString(compact_str.to_owned());
+
+        let (dimensions, rows) = compact_str.trim().split_once(':').ok_or_else(invalid)?;
+        let (width, height) = dimensions.split_once('x').ok_or_else(invalid)?;
+        let width: usize = width.parse().map_err(|_| invalid())?;
+        let height: usize = height.parse().map_err(|_| invalid())?;
+
+        let rows: Vec<_> = rows.split('/').collect();
+        if width == 0 || height < 2 || rows.len() != height {
+            return Err(invalid());
+        }
+
+        let mut cells = Vector::new();
+        for row in rows.iter().rev() {
+            let row = row
+                .chars()
+                .map(cell_from_letter)
+                .collect::<Result<Vector<_>, _>>()?;
+            if row.len() != width {
+                return Err(GunpeyLibError::InvalidRowLength(row.len(), width));
+            }
+            cells.append(row);
+        }
+
+        Ok(Self::from_parts(width, height, cells))
+    }
+
+    /// Decode a grid from an RGBA image of a rendered board, e.g. a screenshot or a hand-drawn
+    /// level. The image must be `width * cell_px` pixels wide and `height * cell_px` pixels tall
+    /// with the top row of the grid at the top of the image. Each cell is matched against
+    /// `sprites`, a list of cells along with the `cell_px` square RGBA sprite they're drawn
+    /// with, and becomes whichever cell has the closest sprite. Active flags come from the
+    /// matched cells so the grid isn't recalculated.
+    pub fn from_rgba(
+        width: usize,
+        height: usize,
+        cell_px: usize,
+        pixels: &[u8],
+        sprites: &[(Cell, &[u8])],
+    ) -> Result<Self, GunpeyLibError> {
+        const BYTES_PER_PIXEL: usize = 4;
+
+        let expected = width * height * cell_px * cell_px * BYTES_PER_PIXEL;
+        if pixels.len() != expected {
+            return Err(GunpeyLibError::InvalidImageSize {
+                expected,
+                actual: pixels.len(),
+            });
+        }
+        if sprites.is_empty() {
+            return Err(GunpeyLibError::NoSprites);
+        }
+        let sprite_len = cell_px * cell_px * BYTES_PER_PIXEL;
+        if let Some((_, sprite)) = sprites
+            .iter()
+            .find(|(_, sprite)| sprite.len() != sprite_len)
+        {
+            return Err(GunpeyLibError::InvalidImageSize {
+                expected: sprite_len,
+                actual: sprite.len(),
+            });
+        }
+
+        let image_row_len = width * cell_px * BYTES_PER_PIXEL;
+        let sprite_row_len = cell_px * BYTES_PER_PIXEL;
+        let distance_to_sprite = |x: usize, y: usize, sprite: &[u8]| -> u64 {
+            // Images are drawn top to bottom but grids are stored bottom to top
+            let top = (height - 1 - y) * cell_px;
+            (0..cell_px)
+                .flat_map(|row| {
+                    let start = (top + row) * image_row_len + x * sprite_row_len;
+                    let cell_row = &pixels[start..start + sprite_row_len];
+                    let sprite_row = &sprite[row * sprite_row_len..(row + 1) * sprite_row_len];
+                    cell_row.iter().zip(sprite_row.iter())
+                })
+                .map(|(&a, &b)| (a as i64 - b as i64).unsigned_abs())
+                .sum()
+        };
+
+        let cells = new_xy_iter(
             width,
             height,
-            cells,
-        }
+            GridIterDirectionX::LeftToRight,
+            GridIterDirectionY::BottomToTop,
+        )
+        .map(|(x, y)| {
+            sprites
+                .iter()
+                .min_by_key(|(_, sprite)| distance_to_sprite(x, y, sprite))
+                .map(|(cell, _)| *cell)
+                .unwrap()
+        })
+        .collect();
+
+        Ok(Self::from_parts(width, height, cells))
     }
 
     pub fn as_chars(&self) -> CharGrid {
@@ -96,6 +350,33 @@ impl Grid {
             .collect()
     }
 
+    /// Draw the grid as a framed board with box-drawing borders around every cell. Fragments are
+    /// drawn with the same symbols as `as_chars` and empty cells are left blank. Active cells
+    /// aren't marked, use `Display` if that matters.
+    pub fn to_ascii_art(&self) -> String {
+        let border = |left: char, middle: char, right: char| {
+            let segments = vec!["─"; self.width].join(&middle.to_string());
+            format!("{}{}{}\n", left, segments, right)
+        };
+
+        let mut art = border('┌', '┬', '┐');
+        for (y, row) in self.rows_render_order().enumerate() {
+            if y > 0 {
+                art.push_str(&border('├', '┼', '┤'));
+            }
+
+            art.push('│');
+            for cell in row {
+                art.push(if cell.is_empty() { ' ' } else { cell.to_char() });
+                art.push('│');
+            }
+            art.push('\n');
+        }
+        art.push_str(&border('└', '┴', '┘'));
+
+        art
+    }
+
     pub fn as_active_bitmask(&self) -> Bitmask {
         // im::Vector doesn't support chunks in the way I'd expect
         let cells: Vec<_> = self.cells.iter().cloned().collect();
@@ -106,6 +387,119 @@ impl Grid {
             .collect()
     }
 
+    /// Whether `as_active_bitmask` would return `expected`, without building the bitmask. Rows
+    /// run top to bottom like `as_active_bitmask`, so a bitmask of the wrong size never matches.
+    pub fn active_bitmask_matches(&self, expected: &Bitmask) -> bool {
+        expected.len() == self.height
+            && expected.iter().enumerate().all(|(row_from_top, bits)| {
+                let y = self.height - 1 - row_from_top;
+                bits.len() == self.width
+                    && bits
+                        .iter()
+                        .zip(self.row(y).iter())
+                        .all(|(bit, cell)| *bit == cell.is_active() as u8)
+            })
+    }
+
+    /// What `as_active_bitmask` would return after swapping `a` and `b`, leaving this grid
+    /// untouched. The swap happens on a copy but cells are shared until they change, so only the
+    /// parts of the grid the swap touches are actually copied.
+    pub fn simulate_swap_bitmask(&self, a: GridPos, b: GridPos) -> Result<Bitmask, GunpeyLibError> {
+        let mut grid = self.clone();
+        grid.swap_cells(a, b)?;
+
+        Ok(grid.as_active_bitmask())
+    }
+
+    /// Iterate over the rows of the grid from bottom to top, the same order they're stored in.
+    /// Rows share structure with the grid so nothing is copied.
+    pub fn rows(&self) -> impl Iterator<Item = Vector<Cell>> + '_ {
+        (0..self.height).map(move |y| self.row(y))
+    }
+
+    /// Iterate over the rows of the grid from top to bottom, the order they're drawn in.
+    pub fn rows_render_order(&self) -> impl Iterator<Item = Vector<Cell>> + '_ {
+        (0..self.height).rev().map(move |y| self.row(y))
+    }
+
+    /// Iterate over every cell in the order they're drawn, top row first and left to right
+    /// within a row, so they can be changed in place.
+    ///
+    /// Active flags aren't updated as cells change. Once you're done editing, call
+    /// `recalculate_active_cells` or the grid will keep showing chains that no longer exist.
+    pub fn iter_render_mut(&mut self) -> impl Iterator<Item = (GridPos, &mut Cell)> + '_ {
+        let width = self.width;
+        let mut cells: Vec<_> = self
+            .cells
+            .iter_mut()
+            .enumerate()
+            .map(|(index, cell)| (get_pos_from_index(index, width), cell))
+            .collect();
+        cells.sort_by_key(|(pos, _)| (std::cmp::Reverse(pos.y), pos.x));
+
+        cells.into_iter()
+    }
+
+    /// Returns true if every cell in row `y` is empty. Rows outside the grid are never empty.
+    pub fn row_is_empty(&self, y: usize) -> bool {
+        y < self.height && self.row(y).iter().all(Cell::is_empty)
+    }
+
+    /// Returns true if every cell in row `y` holds a fragment, so a row with a blocked cell is
+    /// never full. Rows outside the grid are never full.
+    pub fn row_is_full(&self, y: usize) -> bool {
+        y < self.height && self.row(y).iter().all(Cell::is_filled)
+    }
+
+    /// Returns true once any fragment reaches the top row. Pushing another row would push that
+    /// fragment off the grid, so this is when the game is lost.
+    pub fn is_topped_out(&self) -> bool {
+        // Blocked cells never move so they don't count
+        self.row(self.height - 1).iter().any(Cell::is_filled)
+    }
+
+    /// The fraction of cells in row `y` holding a fragment, from 0.0 to 1.0. Rows outside the
+    /// grid return NaN.
+    pub fn row_fill_ratio(&self, y: usize) -> f32 {
+        if y >= self.height {
+            return f32::NAN;
+        }
+
+        let filled = self.row(y).iter().filter(|cell| cell.is_filled()).count();
+        filled as f32 / self.width as f32
+    }
+
+    fn row(&self, y: usize) -> Vector<Cell> {
+        self.cells.skip(y * self.width).take(self.width)
+    }
+
+    /// The cells of row `y` from left to right, or `None` if the row is outside the grid.
+    pub fn get_row(&self, y: usize) -> Option<Vector<Cell>> {
+        (y < self.height).then(|| self.row(y))
+    }
+
+    /// Replace row `y` with `row`, listed from left to right, then recalculate. Errors without
+    /// changing anything if the row is outside the grid or isn't exactly as wide as the grid.
+    pub fn set_row(&mut self, y: usize, row: Vector<Cell>) -> Result<(), GunpeyLibError> {
+        if y >= self.height {
+            return Err(GunpeyLibError::RowOutOfBounds {
+                y,
+                height: self.height,
+            });
+        }
+        if row.len() != self.width {
+            return Err(GunpeyLibError::InvalidRowLength(row.len(), self.width));
+        }
+
+        let start_of_row = y * self.width;
+        for (x, cell) in row.into_iter().enumerate() {
+            self.cells[start_of_row + x] = cell;
+        }
+        self.recalculate_active_cells();
+
+        Ok(())
+    }
+
     pub fn cell_rows_in_render_order(&self) -> Vec<Vec<Cell>> {
         let cells: Vec<_> = self.cells.iter().cloned().collect();
         cells
@@ -115,6 +509,41 @@ impl Grid {
             .collect()
     }
 
+    /// The cells of each column from left to right, with each column listed bottom to top.
+    pub fn cell_columns(&self) -> Vec<Vec<Cell>> {
+        let mut columns = vec![Vec::with_capacity(self.height); self.width];
+        for (pos, cell) in self.cells_in_order(
+            GridIterDirectionX::LeftToRight,
+            GridIterDirectionY::BottomToTop,
+        ) {
+            columns[pos.x as usize].push(*cell);
+        }
+
+        columns
+    }
+
+    /// Every cell along with its position, a row at a time. `direction_y` picks which end rows
+    /// start from and `direction_x` which end of each row. Bottom to top is the order things
+    /// fall in, top to bottom is the order rows are drawn in.
+    pub fn cells_in_order(
+        &self,
+        direction_x: GridIterDirectionX,
+        direction_y: GridIterDirectionY,
+    ) -> impl Iterator<Item = (GridPos, &Cell)> + '_ {
+        new_index_iter(self.width, self.height, direction_x, direction_y)
+            .map(move |index| (self.get_pos_from_index(index), &self.cells[index]))
+    }
+
+    /// Swap two cells and recalculate active cells. The recalculation is skipped when the swap
+    /// can't change which cells are active:
+    /// - both cells are empty or hold the same kind of fragment, in which case nothing moves
+    /// - neither cell has a filled neighbor other than each other, so whatever moves is isolated
+    ///   both before and after the swap. This doesn't hold on a grid one cell wide where a lone
+    ///   fragment touches both sides.
+    ///
+    /// Swapping a fragment with an empty cell just moves the fragment, leaving the gap where it
+    /// was. So swapping a fragment with the empty cell below it looks like the fragment fell, see
+    /// `drop_cell`.
     pub fn swap_cells(
         &mut self,
         cell_pos_a: GridPos,
@@ -129,11 +558,50 @@ impl Grid {
             self.get_index_from_pos(&cell_pos_b),
         ) {
             (Some(cell_index_a), Some(cell_index_b)) => {
-                let res = self.swap_cells_by_index(cell_index_a, cell_index_b);
-                if res.is_ok() {
-                    self.recalculate_active_cells()
+                for (cell_pos, cell_index) in
+                    [(cell_pos_a, cell_index_a), (cell_pos_b, cell_index_b)]
+                {
+                    if self.cells[cell_index].is_blocked() {
+                        return Err(GunpeyLibError::CantSwapBlocked(cell_pos));
+                    }
+                }
+
+                if self.protect_active
+                    && (self.cells[cell_index_a].is_active()
+                        || self.cells[cell_index_b].is_active())
+                {
+                    return Err(GunpeyLibError::SwapProtectedActive(cell_pos_a, cell_pos_b));
+                }
+
+                if self.swap_is_noop(cell_pos_a, cell_pos_b) {
+                    trace!(
+                        "skipping swap of identical cells at {} and {}",
+                        cell_pos_a,
+                        cell_pos_b
+                    );
+                    return Ok(());
+                }
+
+                let can_change_active_cells =
+                    self.swap_can_change_active_cells(cell_pos_a, cell_pos_b);
+                self.swap_cells_by_index(cell_index_a, cell_index_b)?;
+
+                if !can_change_active_cells {
+                    trace!(
+                        "skipping recalculation after swapping isolated cells at {} and {}",
+                        cell_pos_a,
+                        cell_pos_b
+                    );
+                    return Ok(());
                 }
-                res
+
+                // Put the cells back rather than leave the grid half updated
+                if let Err(err) = self.try_recalculate_active_cells() {
+                    self.cells.swap(cell_index_a, cell_index_b);
+                    return Err(err);
+                }
+
+                Ok(())
             }
             _ => Err(GunpeyLibError::CantSwapBadPosition {
                 a: cell_pos_a,
@@ -144,1084 +612,5288 @@ impl Grid {
         }
     }
 
-    pub fn set_cell(&mut self, grid_pos: &GridPos, cell: Cell) {
-        if let Some(current_cell) = self
-            .get_index_from_pos(grid_pos)
-            .and_then(|i| self.cells.get_mut(i))
-        {
-            *current_cell = cell;
-            self.recalculate_active_cells();
-        }
-    }
-
-    pub fn get_cell_at_pos(&self, grid_pos: &GridPos) -> Option<&Cell> {
-        self.get_index_from_pos(grid_pos)
-            .and_then(|index| self.cells.get(index))
-    }
-
-    pub fn get_mut_cell_at_pos(&mut self, grid_pos: &GridPos) -> Option<&mut Cell> {
-        if let Some(index) = self.get_index_from_pos(grid_pos) {
-            self.cells.get_mut(index)
+    /// Like `swap_cells` but returns a token that `revert` can use to put the grid back the way
+    /// it was. Meant for searching through moves without cloning the whole grid each step.
+    ///
+    /// Only the chains running through the swapped cells, before or after the swap, are
+    /// remembered, so the active flags have to be up to date beforehand for `revert` to restore
+    /// them exactly.
+    pub fn swap_with_token(
+        &mut self,
+        cell_pos_a: GridPos,
+        cell_pos_b: GridPos,
+    ) -> Result<SwapToken, GunpeyLibError> {
+        // swap_cells reports a bad position below
+        let prior_active = if self.is_in_bounds(&cell_pos_a) && self.is_in_bounds(&cell_pos_b) {
+            let mut touched = self.fragments_touched_by_swap(cell_pos_a, cell_pos_b);
+            touched.insert(cell_pos_a);
+            touched.insert(cell_pos_b);
+            touched
+                .into_iter()
+                .map(|cell_pos| {
+                    let index = self.get_index_from_pos(&cell_pos).unwrap();
+                    (index, self.cells[index].is_active())
+                })
+                .collect()
         } else {
-            None
-        }
-    }
+            Vec::new()
+        };
 
-    pub fn get_index_from_pos(&self, GridPos { x, y }: &GridPos) -> Option<usize> {
-        let i = x + self.width as isize * y;
+        self.swap_cells(cell_pos_a, cell_pos_b)?;
 
-        (0..self.cells.len() as isize)
-            .contains(&i)
-            .then(|| i as usize)
-    }
+        // swap_cells already checked both positions
+        let index_a = self.get_index_from_pos(&cell_pos_a).unwrap();
+        let index_b = self.get_index_from_pos(&cell_pos_b).unwrap();
 
-    pub fn get_pos_from_index(&self, index: usize) -> GridPos {
-        get_pos_from_index(index, self.width)
+        Ok(SwapToken {
+            indices: (index_a, index_b),
+            prior_active,
+        })
     }
 
-    pub fn recalculate_active_cells_old(&mut self) {
-        let edges = self.edges();
+    /// Every fragment sharing a chain with the cells at `cell_pos_a` or `cell_pos_b`, either as
+    /// they are or once they've been swapped. No other fragment can change when they swap.
+    fn fragments_touched_by_swap(
+        &mut self,
+        cell_pos_a: GridPos,
+        cell_pos_b: GridPos,
+    ) -> HashSet<GridPos> {
+        let index_a = self.get_index_from_pos(&cell_pos_a).unwrap();
+        let index_b = self.get_index_from_pos(&cell_pos_b).unwrap();
 
-        trace!("recalculation active cells from edges\n{:?}", edges);
+        let mut touched = self.fragments_sharing_corners(vec![cell_pos_a, cell_pos_b]);
+        self.cells.swap(index_a, index_b);
+        touched.extend(self.fragments_sharing_corners(vec![cell_pos_a, cell_pos_b]));
+        self.cells.swap(index_a, index_b);
 
-        let mut is_connected_to_left_edge: HashSet<GridPos> = (0..self.height)
-            .map(|y| GridPos {
-                x: 0,
-                y: y as isize,
-            })
-            .filter(|gp| !self.is_cell_empty(gp))
-            .collect();
+        touched
+    }
 
-        let mut i = 0;
-        loop {
-            trace!("loop #{}", i);
-            i += 1;
-            let mut change = false;
-
-            for &connected_gp in is_connected_to_left_edge.clone().iter() {
-                for (gp1, gp2) in edges.iter() {
-                    if *gp1 == connected_gp {
-                        if is_connected_to_left_edge.insert(*gp2) {
-                            change = true;
-                        }
-                    } else if *gp2 == connected_gp && is_connected_to_left_edge.insert(*gp1) {
-                        change = true;
-                    }
-                }
-            }
+    /// Undo the swap that produced `token`. Tokens have to be reverted in the opposite order to
+    /// the swaps that made them.
+    pub fn revert(&mut self, token: SwapToken) {
+        let (index_a, index_b) = token.indices;
+        self.cells.swap(index_a, index_b);
 
-            if !change {
-                break;
+        for (index, was_active) in token.prior_active {
+            let cell = &mut self.cells[index];
+            if was_active {
+                cell.activate();
+            } else {
+                cell.deactivate();
             }
         }
+    }
 
-        trace!("is_connected_to_left_edge loop exited");
+    fn swap_can_change_active_cells(&self, cell_pos_a: GridPos, cell_pos_b: GridPos) -> bool {
+        // On a board this narrow the two cells can reach both sides without any neighbors
+        if self.width <= 2 {
+            return true;
+        }
 
-        let mut is_connected_to_right_edge: HashSet<GridPos> = (0..self.height)
-            .map(|y| GridPos {
-                x: self.width as isize - 1,
-                y: y as isize,
-            })
-            .filter(|gp| !self.is_cell_empty(gp))
-            .collect();
+        [cell_pos_a, cell_pos_b].iter().any(|&cell_pos| {
+            self.neighbors_where(cell_pos, |_, cell| cell.is_filled())
+                .into_iter()
+                .any(|neighboring_pos| {
+                    neighboring_pos != cell_pos_a && neighboring_pos != cell_pos_b
+                })
+        })
+    }
 
-        let mut i = 0;
-        loop {
-            trace!("loop #{}", i);
-            i += 1;
-            let mut change = false;
-
-            for &connected_gp in is_connected_to_right_edge.clone().iter() {
-                for (gp1, gp2) in edges.iter() {
-                    if *gp1 == connected_gp {
-                        if is_connected_to_right_edge.insert(*gp2) {
-                            change = true;
-                        }
-                    } else if *gp2 == connected_gp && is_connected_to_right_edge.insert(*gp1) {
-                        change = true;
-                    }
-                }
-            }
+    /// `swap_cells` for callers that have raw coordinates rather than `GridPos`es
+    pub fn swap_xy(
+        &mut self,
+        ax: isize,
+        ay: isize,
+        bx: isize,
+        by: isize,
+    ) -> Result<(), GunpeyLibError> {
+        self.swap_cells(gp(ax, ay), gp(bx, by))
+    }
 
-            if !change {
-                break;
-            }
+    /// Move the fragment at `pos` down one row if the cell below it is empty, recalculating if
+    /// anything moved. Returns false and leaves the grid alone if `pos` is empty, on the bottom
+    /// row or resting on another fragment.
+    pub fn drop_cell(&mut self, pos: GridPos) -> bool {
+        let below = match self.below(pos) {
+            Some(below) => below,
+            None => return false,
+        };
+        if self.is_cell_empty(&pos) || !self.is_cell_empty(&below) {
+            return false;
         }
 
-        trace!("is_connected_to_right_edge loop exited");
-
-        trace!("is_connected_to_left_edge\n{:?}", is_connected_to_left_edge);
-        trace!(
-            "is_connected_to_right_edge\n{:?}",
-            is_connected_to_right_edge
-        );
+        self.swap_cells(pos, below).is_ok()
+    }
 
-        let active_grid_positions: HashSet<_> = is_connected_to_left_edge
-            .intersection(&is_connected_to_right_edge)
-            .collect();
+    /// Returns true if swapping the cells at `a` and `b` wouldn't change the grid, e.g. because
+    /// both are empty or both hold the same kind of fragment. Active flags are ignored since
+    /// they're derived from the layout. Returns false if either position is out of bounds.
+    pub fn swap_is_noop(&self, a: GridPos, b: GridPos) -> bool {
+        match (self.get_cell_at_pos(&a), self.get_cell_at_pos(&b)) {
+            (Some(cell_a), Some(cell_b)) => {
+                cell_a.kind() == cell_b.kind() && cell_a.is_blocked() == cell_b.is_blocked()
+            }
+            _ => false,
+        }
+    }
 
-        // loop {
-        //     let adjacency = crate::grid_algorithms::Adjacency::from_grid(self);
-        //     let corner_nodes_with_one_edge = adjacency.corner_nodes_with_one_edge();
+    /// Make the swap only if it leaves more cells active than there are now. Returns whether the
+    /// swap was kept. Swaps that fail, or don't add to the active cells, leave the grid as it was.
+    pub fn swap_if_improving(&mut self, cell_pos_a: GridPos, cell_pos_b: GridPos) -> bool {
+        let active_count_before = self.count_active_cells();
 
-        //     if corner_nodes_with_one_edge.is_empty() {
-        //         break;
-        //     }
+        // Cloning is cheap since the cells share structure with the original
+        let mut swapped = self.clone();
+        if swapped.swap_cells(cell_pos_a, cell_pos_b).is_err()
+            || swapped.count_active_cells() <= active_count_before
+        {
+            return false;
+        }
 
-        //     for active_gp in active_grid_positions {
-        //         let cell = self.get_cell_at_pos(active_gp).unwrap();
-        //         let (cn_a, cn_b) = grid_pos_to_corner_nodes(active_gp, cell.);
-        //     }
-        // }
+        *self = swapped;
+        true
+    }
 
-        // debug!(
-        //     "corner_nodes_with_one_edge: {:?}",
-        //     corner_nodes_with_one_edge
-        // );
+    /// How many cells are currently active
+    pub fn count_active_cells(&self) -> usize {
+        self.cells.iter().filter(|cell| cell.is_active()).count()
+    }
 
-        let width = self.width;
+    /// The position of every active cell, in the same bottom to top order the cells are stored
+    pub fn active_positions(&self) -> Vec<GridPos> {
         self.cells
-            .iter_mut()
-            // must enumerate before we ignore empty cells because otherwise the indexes will be wrong
+            .iter()
             .enumerate()
-            // Empty cells have no edges so we ignore them
-            .filter(|(_, &mut c)| !c.is_empty())
-            .for_each(|(index, cell)| {
-                let gp1 = get_pos_from_index(index, width);
+            .filter(|(_, cell)| cell.is_active())
+            .map(|(index, _)| self.get_pos_from_index(index))
+            .collect()
+    }
 
-                if active_grid_positions.contains(&gp1) {
-                    cell.activate()
-                } else {
-                    cell.deactivate()
-                }
-            });
+    /// Count the active cells in the rectangle whose bottom left cell is at `x`, `y`. Any part of
+    /// the rectangle that falls outside the grid is ignored.
+    pub fn active_count_in(&self, x: usize, y: usize, w: usize, h: usize) -> usize {
+        let x_range = x.min(self.width)..x.saturating_add(w).min(self.width);
+        let y_range = y.min(self.height)..y.saturating_add(h).min(self.height);
 
-        trace!(
-            "state of grid after recalculation of active cells:\n{}",
-            self
-        );
+        y_range
+            .flat_map(|y| x_range.clone().map(move |x| x + self.width * y))
+            .filter(|&index| self.cells[index].is_active())
+            .count()
     }
 
-    // Node - a corner in a grid of cells
-    // Cell - a line between two corners or an empty space
-    // Neighbor - a node connected to another node
-
-    pub fn recalculate_active_cells(&mut self) {
-        let mut nodes: HashSet<_> = new_xy_iter(
-            self.width + 1,
-            self.height + 1,
+    /// Every swap the player could make that would change the grid. The cursor always covers two
+    /// cells in the same column so each swap is a cell and its `swap_partner`. Every pair is only
+    /// listed once, from the lower cell.
+    pub fn legal_swaps(&self) -> Vec<(GridPos, GridPos)> {
+        new_xy_iter(
+            self.width,
+            self.height - 1,
             GridIterDirectionX::LeftToRight,
             GridIterDirectionY::BottomToTop,
         )
-        .map(|(x, y)| gp(x as isize, y as isize))
-        .collect();
-
-        'nodes: loop {
-            for node in nodes.clone().iter() {
-                let mut neighbor_count = 0;
-
-                let other_cell = *node;
-                if self.node_connects_across_cell(&other_cell, node, &nodes) {
-                    neighbor_count += 1;
-                }
+        .filter_map(|(x, y)| {
+            let a = gp(x as isize, y as isize);
+            self.swap_partner(a).map(|b| (a, b))
+        })
+        .filter(|&(a, b)| {
+            !self.swap_is_noop(a, b) && !self.is_cell_blocked(&a) && !self.is_cell_blocked(&b)
+        })
+        .collect()
+    }
 
-                let other_cell = *node - gp(0, 1);
-                if self.node_connects_across_cell(&other_cell, node, &nodes) {
-                    neighbor_count += 1;
-                }
+    /// The cell that gets swapped with `pos` when the cursor is over it: the cell above, or the
+    /// cell below for the top row. `None` if `pos` is outside the grid.
+    pub fn swap_partner(&self, pos: GridPos) -> Option<GridPos> {
+        if !self.is_in_bounds(&pos) {
+            None
+        } else if pos.y == self.height as isize - 1 {
+            self.below(pos)
+        } else {
+            self.above(pos)
+        }
+    }
 
-                let other_cell = *node - gp(1, 1);
-                if self.node_connects_across_cell(&other_cell, node, &nodes) {
-                    neighbor_count += 1;
-                }
+    /// Pick one of the `legal_swaps` at random and make it. Returns the swapped positions, or
+    /// `None` if there's nothing worth swapping. The same `rng` state always picks the same swap.
+    pub fn random_legal_swap(&mut self, rng: &mut impl Rng) -> Option<(GridPos, GridPos)> {
+        let (a, b) = *self.legal_swaps().choose(rng)?;
+        self.swap_cells(a, b)
+            .expect("legal swaps are always in bounds and between different cells");
 
-                let other_cell = *node - gp(1, 0);
-                if self.node_connects_across_cell(&other_cell, node, &nodes) {
-                    neighbor_count += 1;
-                }
+        Some((a, b))
+    }
 
-                if neighbor_count < 2 {
-                    nodes.remove(&node);
-                    continue 'nodes;
-                }
-            }
-            break 'nodes;
-        }
-
-        let mut cell_statuses: HashMap<GridPos, RefCell<CellStatus>> = HashMap::new();
-
-        'cells: for (index, cell) in self.cells.iter_mut().enumerate() {
-            let cell_pos = get_pos_from_index(index, self.width);
-            if cell.is_empty() {
-                cell_statuses.insert(
-                    cell_pos,
-                    RefCell::new(CellStatus {
-                        is_connected_to_left_edge: false,
-                        is_connected_to_right_edge: false,
-                        is_part_of_a_chain: false,
-                        _cell_index: index,
-                    }),
-                );
+    /// Replace the cell at `grid_pos` and recalculate. Errors without changing anything if the
+    /// position is outside the grid.
+    pub fn set_cell(&mut self, grid_pos: &GridPos, cell: Cell) -> Result<(), GunpeyLibError> {
+        match self
+            .get_index_from_pos(grid_pos)
+            .and_then(|i| self.cells.get_mut(i))
+        {
+            Some(current_cell) => {
+                *current_cell = cell;
+                self.recalculate_active_cells();
 
-                continue 'cells;
+                Ok(())
             }
+            None => Err(GunpeyLibError::OutOfBounds {
+                pos: *grid_pos,
+                width: self.width,
+                height: self.height,
+            }),
+        }
+    }
 
-            let corner_nodes = cell.corner_nodes(&cell_pos);
-            for node in corner_nodes {
-                if !nodes.contains(&node) {
-                    cell.deactivate();
-                    cell_statuses.insert(
-                        cell_pos,
-                        RefCell::new(CellStatus {
-                            is_connected_to_left_edge: false,
-                            is_connected_to_right_edge: false,
-                            is_part_of_a_chain: false,
-                            _cell_index: index,
-                        }),
-                    );
+    /// Make a batch of changes to the grid, recalculating active cells once when `f` returns
+    /// rather than after every change. Edits that fail their bounds checks are skipped and the
+    /// errors are returned together, but every valid edit is still applied.
+    pub fn edit(&mut self, f: impl FnOnce(&mut GridEditor)) -> Result<(), Vec<GunpeyLibError>> {
+        let mut editor = GridEditor {
+            grid: self,
+            errors: Vec::new(),
+        };
+        f(&mut editor);
 
-                    continue 'cells;
-                }
-            }
+        let errors = editor.errors;
+        self.recalculate_active_cells();
 
-            cell_statuses.insert(
-                cell_pos,
-                RefCell::new(CellStatus {
-                    is_connected_to_left_edge: false,
-                    is_connected_to_right_edge: false,
-                    is_part_of_a_chain: true,
-                    _cell_index: index,
-                }),
-            );
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
         }
+    }
 
-        trace!("cell_statuses={:#?}", cell_statuses);
+    pub fn get_cell_at_pos(&self, grid_pos: &GridPos) -> Option<&Cell> {
+        self.get_index_from_pos(grid_pos)
+            .and_then(|index| self.cells.get(index))
+    }
 
-        // Edge-checking loop
-        loop {
-            let mut had_changes = false;
-            for (cell_pos, cell_status) in cell_statuses.iter() {
-                if !cell_status.borrow().is_part_of_a_chain {
-                    continue;
-                }
+    pub fn get_mut_cell_at_pos(&mut self, grid_pos: &GridPos) -> Option<&mut Cell> {
+        if let Some(index) = self.get_index_from_pos(grid_pos) {
+            self.cells.get_mut(index)
+        } else {
+            None
+        }
+    }
 
-                let cell = self.get_cell_at_pos(cell_pos).unwrap();
-                let neighbors = if cell_pos.x == 0 {
-                    vec![
-                        self.above_right(*cell_pos),
-                        self.right(*cell_pos),
-                        self.below_right(*cell_pos),
-                    ]
-                } else if cell_pos.x == (self.width - 1) as isize {
-                    vec![
-                        self.left(*cell_pos),
-                        self.above_left(*cell_pos),
-                        self.below_left(*cell_pos),
-                    ]
-                } else {
-                    vec![
-                        self.left(*cell_pos),
-                        self.above_left(*cell_pos),
-                        self.above(*cell_pos),
-                        self.above_right(*cell_pos),
-                        self.right(*cell_pos),
-                        self.below_right(*cell_pos),
-                        self.below(*cell_pos),
-                        self.below_left(*cell_pos),
-                    ]
-                };
+    fn is_in_bounds(&self, GridPos { x, y }: &GridPos) -> bool {
+        (0..self.width as isize).contains(x) && (0..self.height as isize).contains(y)
+    }
 
-                let connected_neighbors: Vec<_> = neighbors
-                    .into_iter()
-                    .filter_map(|neighboring_pos| neighboring_pos)
-                    .filter_map(|neighboring_pos| {
-                        if let Some(neighboring_cell) = self.get_cell_at_pos(&neighboring_pos) {
-                            Some((neighboring_pos, neighboring_cell))
-                        } else {
-                            None
-                        }
-                    })
-                    .filter(|(neighboring_pos, neighboring_cell)| {
-                        let adjacency = adjacency_of_grid_positions(*cell_pos, *neighboring_pos);
+    /// The index of the cell at `grid_pos` in `cells`. Both coordinates are bounds checked so a
+    /// position past the end of a row doesn't wrap around to the start of the next one.
+    pub fn get_index_from_pos(&self, grid_pos: &GridPos) -> Option<usize> {
+        self.is_in_bounds(grid_pos)
+            .then(|| grid_pos.x as usize + self.width * grid_pos.y as usize)
+    }
 
-                        cell.is_connected_to(neighboring_cell, adjacency)
-                    })
-                    .collect();
+    pub fn get_pos_from_index(&self, index: usize) -> GridPos {
+        get_pos_from_index(index, self.width)
+    }
 
-                // where "cell.connected_neighbors" is all neighbor cells that share a vertex,
-                // UNLESS that vertex is on the left or right borders
-                if !cell_status.borrow().is_connected_to_left_edge {
-                    if connected_neighbors.iter().any(|(neighboring_pos, _)| {
-                        cell_statuses
-                            .get(neighboring_pos)
-                            .map(|status| status.borrow().is_connected_to_left_edge)
-                            .unwrap_or_default()
-                    }) || cell_pos.x == 0
-                    {
-                        cell_status.borrow_mut().is_connected_to_left_edge = true;
-                        had_changes = true;
-                    }
-                }
+    /// The positions of every cell that differs between `old` and `new`, including cells that
+    /// only became active or inactive. If the grids are different sizes then every position in
+    /// `new` is reported.
+    pub fn changed_positions(old: &Grid, new: &Grid) -> Vec<GridPos> {
+        if old.width != new.width || old.height != new.height {
+            return (0..new.cells.len())
+                .map(|index| new.get_pos_from_index(index))
+                .collect();
+        }
 
-                if !cell_status.borrow().is_connected_to_right_edge {
-                    if connected_neighbors.iter().any(|(neighboring_pos, _)| {
-                        cell_statuses
-                            .get(neighboring_pos)
-                            .map(|status| status.borrow().is_connected_to_right_edge)
-                            .unwrap_or_default()
-                    }) || cell_pos.x == (self.width - 1) as isize
-                    {
-                        cell_status.borrow_mut().is_connected_to_right_edge = true;
-                        had_changes = true;
-                    }
-                }
-            }
+        old.cells
+            .iter()
+            .zip(new.cells.iter())
+            .enumerate()
+            .filter(|(_, (old_cell, new_cell))| old_cell != new_cell)
+            .map(|(index, _)| new.get_pos_from_index(index))
+            .collect()
+    }
 
-            if !had_changes {
-                break;
-            }
-        }
+    /// The positions whose active flag differs between `old` and `new` along with the flag in
+    /// `new`. Only active flags are compared so both grids should have the same fragments in the
+    /// same places, e.g. before and after recalculating.
+    pub fn active_delta(old: &Grid, new: &Grid) -> Vec<(GridPos, bool)> {
+        old.cells
+            .iter()
+            .zip(new.cells.iter())
+            .enumerate()
+            .filter(|(_, (old_cell, new_cell))| old_cell.is_active() != new_cell.is_active())
+            .map(|(index, (_, new_cell))| (new.get_pos_from_index(index), new_cell.is_active()))
+            .collect()
+    }
 
-        for (cell_pos, cell_status) in cell_statuses.iter() {
-            let cell = self.get_mut_cell_at_pos(cell_pos).unwrap();
-            let cell_status = cell_status.borrow();
+    /// Set the active flags listed in a delta from `active_delta`. Nothing is changed if any
+    /// position in the delta is outside the grid.
+    pub fn apply_active_delta(&mut self, delta: &[(GridPos, bool)]) -> Result<(), GunpeyLibError> {
+        if let Some((pos, _)) = delta.iter().find(|(pos, _)| !self.is_in_bounds(pos)) {
+            return Err(GunpeyLibError::OutOfBounds {
+                pos: *pos,
+                width: self.width,
+                height: self.height,
+            });
+        }
 
-            if cell_status.is_connected_to_left_edge && cell_status.is_connected_to_right_edge {
+        for (pos, is_active) in delta {
+            let cell = self.get_mut_cell_at_pos(pos).unwrap();
+            if *is_active {
                 cell.activate();
             } else {
                 cell.deactivate();
             }
         }
+
+        Ok(())
     }
 
-    fn _cells_to_nodes(&self) -> HashMap<usize, Vector<GridPos>> {
-        let mut map: HashMap<usize, Vector<GridPos>> = HashMap::new();
+    /// A copy of the grid with its active cells recalculated, leaving this one untouched
+    pub fn recalculated(&self) -> Grid {
+        let mut grid = self.clone();
+        grid.recalculate_active_cells();
+
+        grid
+    }
 
-        // for every filled cell, add an entry into the adjacency list
-        // each cell is one "edge" between two "corners"
-        for index in 0..self.cells.len() {
-            // map.insert(index.to_string(), Vec::new());
-            match self.cells.get(index).unwrap() {
-                Cell::Filled(LineFragment { kind, .. }) => {
-                    let (grid_pos_a, grid_pos_b) = index_to_corner_nodes(index, &kind, self.width);
+    /// Recalculate active cells after checking that the grid is in a state recalculation can
+    /// handle. Nothing is changed if the check fails.
+    fn try_recalculate_active_cells(&mut self) -> Result<(), GunpeyLibError> {
+        if self.cells.len() != self.width * self.height {
+            return Err(GunpeyLibError::CellCountMismatch {
+                cells: self.cells.len(),
+                width: self.width,
+                height: self.height,
+            });
+        }
 
-                    let corners = map.entry(index).or_default();
-                    corners.push_back(grid_pos_a);
-                    corners.push_back(grid_pos_b);
-                }
-                // empty cells have no edges
-                Cell::Empty => (),
+        self.recalculate_active_cells();
+
+        Ok(())
+    }
+
+    // Node - a corner in a grid of cells
+    // Cell - a line between two corners or an empty space
+    // Neighbor - a node connected to another node
+
+    pub fn recalculate_active_cells(&mut self) {
+        #[cfg(test)]
+        RECALCULATION_COUNT.with(|count| count.set(count.get() + 1));
+
+        self.mark_active_cells();
+
+        if !self.auto_clear_on_recalc {
+            return;
+        }
+
+        // Falling cells can complete more chains so keep going until the grid settles
+        while self.count_active_cells() > 0 {
+            for cell in self.cells.iter_mut().filter(|cell| cell.is_active()) {
+                *cell = Cell::Empty;
             }
+            self.settle_columns();
+            self.mark_active_cells();
         }
+    }
+
+    fn mark_active_cells(&mut self) {
+        let nodes = self.prune_dangling_nodes();
+        let everything: HashSet<_> = (0..self.cells.len())
+            .map(|index| get_pos_from_index(index, self.width))
+            .collect();
 
-        map
+        self.mark_active_cells_among(&everything, &nodes);
     }
 
-    // #region hide
+    /// Like `recalculate_active_cells` but only looks at the fragments in `region`, leaving the
+    /// flags everywhere else alone. Chains never cross a corner that isn't shared, so this is
+    /// only the same as a full recalculation if `region` comes from `fragments_sharing_corners`.
+    fn recalculate_active_cells_among(&mut self, region: &HashSet<GridPos>) {
+        let candidates = region
+            .iter()
+            .filter_map(|pos| self.get_cell_at_pos(pos).map(|cell| cell.corner_nodes(pos)))
+            .flatten()
+            .collect();
+        let nodes = self.prune_dangling_nodes_from(candidates);
 
-    fn edges(&self) -> Vec<(GridPos, GridPos)> {
-        let mut edges: Vec<_> = self
-            .cells
+        self.mark_active_cells_among(region, &nodes);
+    }
+
+    /// Set the active flag of every cell in `region` given the corner `nodes` that survived
+    /// pruning
+    fn mark_active_cells_among(&mut self, region: &HashSet<GridPos>, nodes: &HashSet<GridPos>) {
+        // A line has to pass through at least two corners of a cell. For most fragments that
+        // means all of their corners but a wild fragment can leave some of them dangling.
+        let in_chain: HashSet<GridPos> = region
             .iter()
-            // must enumerate before we ignore empty cells because otherwise the indexes will be wrong
-            .enumerate()
-            // Empty cells have no edges so we ignore them
-            .filter(|(_, c)| !c.is_empty())
-            .flat_map(|(index, cell)| match cell {
-                Cell::Filled(lf) => {
-                    let gp1 = self.get_pos_from_index(index);
-                    trace!(
-                        "encountered filled cell at {} while building edge list, calculating edges",
-                        gp1
-                    );
-                    let possible_edges = match lf.kind {
-                        LineFragmentKind::Caret => {
-                            vec![
-                                self.left(gp1),
-                                self.below_left(gp1),
-                                self.below(gp1),
-                                self.below_right(gp1),
-                                self.right(gp1),
-                            ]
-                        }
-                        LineFragmentKind::InvertedCaret => {
-                            vec![
-                                self.left(gp1),
-                                self.above_left(gp1),
-                                self.above(gp1),
-                                self.above_right(gp1),
-                                self.right(gp1),
-                            ]
-                        }
-                        LineFragmentKind::LeftSlash => {
-                            vec![
-                                self.left(gp1),
-                                self.above_left(gp1),
-                                self.above(gp1),
-                                self.right(gp1),
-                                self.below_right(gp1),
-                                self.below(gp1),
-                            ]
-                        }
-                        LineFragmentKind::RightSlash => {
-                            vec![
-                                self.left(gp1),
-                                self.below_left(gp1),
-                                self.below(gp1),
-                                self.above(gp1),
-                                self.above_right(gp1),
-                                self.right(gp1),
-                            ]
-                        }
-                    };
-
-                    possible_edges
-                        .into_iter()
-                        .filter_map(move |gp2| gp2)
-                        .map(move |gp2| (gp1, gp2))
-                }
-                // We already filtered out the empties so we good here
-                _ => unreachable!(),
+            .filter_map(|cell_pos| Some((*cell_pos, self.get_cell_at_pos(cell_pos)?)))
+            .filter(|(cell_pos, cell)| {
+                let surviving_corner_nodes = cell
+                    .corner_nodes(cell_pos)
+                    .into_iter()
+                    .filter(|node| nodes.contains(node))
+                    .count();
+                cell.is_filled() && surviving_corner_nodes >= 2
             })
+            .map(|(cell_pos, _)| cell_pos)
             .collect();
 
-        trace!("got possible edges, {:#?}", edges);
-
-        edges.retain(|edge| self.cells_are_connecting(edge));
-        trace!("after filtering edges for connections, {:#?}", edges);
+        let spanning_chains: Vec<_> = self
+            .chain_components(&in_chain)
+            .into_iter()
+            .filter(|component| self.component_spans_edges(component))
+            .collect();
+        trace!("spanning_chains={:?}", spanning_chains);
 
-        edges
+        for cell_pos in region {
+            self.get_mut_cell_at_pos(cell_pos).unwrap().deactivate();
+        }
+        for cell_pos in spanning_chains.iter().flatten() {
+            self.get_mut_cell_at_pos(cell_pos).unwrap().activate();
+        }
     }
 
-    fn cells_are_connecting(&self, (cell_a_pos, cell_b_pos): &(GridPos, GridPos)) -> bool {
-        let adjacency = cell_a_pos.adjacency(cell_b_pos);
+    /// Group the fragments at `in_chain` by which of them connect to each other. Fragments in
+    /// the left or right column don't connect to the fragments above or below them, since the
+    /// corner they'd share is on the side of the grid.
+    fn chain_components(&self, in_chain: &HashSet<GridPos>) -> Vec<Vec<GridPos>> {
+        let right_edge = self.width as isize - 1;
+        let mut visited = HashSet::new();
+        let mut components = Vec::new();
 
-        trace!(
-            "checking connection between {} and cell to the {} at {}",
-            cell_a_pos,
-            adjacency,
-            cell_b_pos
-        );
+        for &start in in_chain {
+            if !visited.insert(start) {
+                continue;
+            }
 
-        match (
-            self.get_index_from_pos(cell_a_pos),
-            self.get_index_from_pos(cell_b_pos),
-        ) {
-            (Some(cell_a_index), Some(cell_b_index)) => {
-                trace!("calculated indexes for both cells");
+            let mut component = Vec::new();
+            let mut to_visit = vec![start];
+            while let Some(cell_pos) = to_visit.pop() {
+                let cell = self.get_cell_at_pos(&cell_pos).unwrap();
+                let on_a_side = cell_pos.x == 0 || cell_pos.x == right_edge;
+                let connected_neighbors =
+                    self.neighbors_where(cell_pos, |adjacency, neighboring_cell| {
+                        let along_the_side =
+                            on_a_side && matches!(adjacency, Adjacency::Above | Adjacency::Below);
+                        !along_the_side && cell.is_connected_to(neighboring_cell, adjacency)
+                    });
+
+                for neighboring_pos in connected_neighbors {
+                    if in_chain.contains(&neighboring_pos) && visited.insert(neighboring_pos) {
+                        to_visit.push(neighboring_pos);
+                    }
+                }
 
-                let cell_a = self.cells.get(cell_a_index).unwrap();
-                let cell_b = self.cells.get(cell_b_index).unwrap();
+                component.push(cell_pos);
+            }
 
-                trace!(
-                    "checking connection between {:?} and cell to the {} at {:?}",
-                    cell_a,
-                    adjacency,
-                    cell_b
-                );
+            components.push(component);
+        }
+
+        components
+    }
+
+    /// Group the active cells into chains of connected fragments. Positions within each chain
+    /// are sorted and chains are ordered by their lowest position.
+    pub fn active_chains(&self) -> Vec<Vec<GridPos>> {
+        self.connected_components(Cell::is_active)
+    }
+
+    /// Returns true if a set of connected cells reaches both the left and right sides of the
+    /// grid, the same test `recalculate_active_cells` uses to decide whether a chain is active.
+    pub fn component_spans_edges(&self, component: &[GridPos]) -> bool {
+        let right_edge = self.width as isize - 1;
+
+        component.iter().any(|pos| pos.x == 0) && component.iter().any(|pos| pos.x == right_edge)
+    }
 
-                cell_a.is_connected_to(cell_b, adjacency)
+    /// Find every distinct path of connected fragments that runs from the left side of the grid
+    /// to the right side, listing the fragments in each path from left to right. A path starts
+    /// at a corner on the left side, never visits the same corner twice and finishes as soon as
+    /// it reaches a corner on the right side. At most `MAX_COMPLETE_SPANS` paths are returned
+    /// and the search stops after `MAX_SPAN_SEARCH_STEPS` steps, so a huge board may not have
+    /// all of its spans listed.
+    pub fn complete_spans(&self) -> Vec<Vec<GridPos>> {
+        // Each fragment is an edge between two of its corners
+        let mut edges: HashMap<GridPos, Vec<(GridPos, GridPos)>> = HashMap::new();
+        for (index, cell) in self.cells.iter().enumerate() {
+            let cell_pos = self.get_pos_from_index(index);
+            let corner_nodes = cell.corner_nodes(&cell_pos);
+            for &node_a in corner_nodes.iter() {
+                for &node_b in corner_nodes.iter().filter(|&&node_b| node_b != node_a) {
+                    edges.entry(node_a).or_default().push((cell_pos, node_b));
+                }
             }
-            // TODO would it be better to unwrap instead of matching? This should only ever be called with valid grid positions
-            _ => false,
         }
+
+        let reaches_right = self.nodes_reaching_right_side(&edges);
+        let mut steps_left = MAX_SPAN_SEARCH_STEPS;
+        let mut spans = Vec::new();
+        for y in 0..=self.height as isize {
+            let start = gp(0, y);
+            let mut visited = HashSet::from([start]);
+            self.extend_span(
+                start,
+                &edges,
+                &reaches_right,
+                &mut visited,
+                &mut Vec::new(),
+                &mut spans,
+                &mut steps_left,
+            );
+        }
+
+        spans
     }
 
-    fn swap_cells_by_index(
-        &mut self,
-        cell_index_a: usize,
-        cell_index_b: usize,
-    ) -> Result<(), GunpeyLibError> {
-        if cell_index_a == cell_index_b {
-            return Err(GunpeyLibError::CantSwapSameIndex(
-                cell_index_a,
-                cell_index_b,
-            ));
+    /// Every corner that has some path to the right side without touching the left side. It
+    /// ignores which corners a path has already been through, so it only rules corners out.
+    fn nodes_reaching_right_side(
+        &self,
+        edges: &HashMap<GridPos, Vec<(GridPos, GridPos)>>,
+    ) -> HashSet<GridPos> {
+        let right_side = self.width as isize;
+        let mut reached: HashSet<GridPos> = edges
+            .keys()
+            .copied()
+            .filter(|node| node.x == right_side)
+            .collect();
+        let mut to_visit: Vec<GridPos> = reached.iter().copied().collect();
+
+        // Fragments connect their corners both ways so walking forward from the right side
+        // finds everything that can walk back to it
+        while let Some(node) = to_visit.pop() {
+            for &(_, next_node) in edges.get(&node).into_iter().flatten() {
+                if next_node.x != 0 && reached.insert(next_node) {
+                    to_visit.push(next_node);
+                }
+            }
         }
 
-        let valid_indexes = 0..self.cells.len();
+        reached
+    }
 
-        if valid_indexes.contains(&cell_index_a) && valid_indexes.contains(&cell_index_b) {
-            self.cells.swap(cell_index_a, cell_index_b);
+    #[allow(clippy::too_many_arguments)]
+    fn extend_span(
+        &self,
+        node: GridPos,
+        edges: &HashMap<GridPos, Vec<(GridPos, GridPos)>>,
+        reaches_right: &HashSet<GridPos>,
+        visited: &mut HashSet<GridPos>,
+        path: &mut Vec<GridPos>,
+        spans: &mut Vec<Vec<GridPos>>,
+        steps_left: &mut usize,
+    ) {
+        for &(cell_pos, next_node) in edges.get(&node).into_iter().flatten() {
+            if spans.len() >= MAX_COMPLETE_SPANS || *steps_left == 0 {
+                return;
+            }
+            // Wandering back to the left side would just be a span that started later
+            if next_node.x == 0
+                || visited.contains(&next_node)
+                || !reaches_right.contains(&next_node)
+            {
+                continue;
+            }
+            *steps_left -= 1;
 
-            Ok(())
-        } else {
-            Err(GunpeyLibError::CantSwapBadIndex {
-                a: cell_index_a,
-                b: cell_index_b,
-                length: self.cells.len(),
-            })
+            path.push(cell_pos);
+            if next_node.x == self.width as isize {
+                spans.push(path.clone());
+            } else {
+                visited.insert(next_node);
+                self.extend_span(
+                    next_node,
+                    edges,
+                    reaches_right,
+                    visited,
+                    path,
+                    spans,
+                    steps_left,
+                );
+                visited.remove(&next_node);
+            }
+            path.pop();
         }
     }
 
-    fn node_connects_across_cell(
-        &self,
-        cell_pos: &GridPos,
-        node_pos: &GridPos,
-        nodes: &HashSet<GridPos>,
-    ) -> bool {
-        if !(0..self.width as isize).contains(&cell_pos.x) {
-            return true;
-        };
-        if !(0..self.height as isize).contains(&cell_pos.y) {
-            return false;
-        };
+    /// The biggest group of connected, inactive fragments that touches exactly one side of the
+    /// grid. That's the unfinished line closest to being completed, so it's a good hint to show
+    /// the player. Ties go to the group with the lowest position.
+    pub fn biggest_near_miss(&self) -> Option<Vec<GridPos>> {
+        let right_edge = self.width as isize - 1;
 
-        let cell = self.get_cell_at_pos(cell_pos).unwrap();
-        if !cell.has_corner_node(cell_pos, node_pos) {
-            return false;
-        };
-        for node in cell.corner_nodes(cell_pos) {
-            if node == *node_pos {
+        self.connected_components(|cell| !cell.is_active())
+            .into_iter()
+            .filter(|component| {
+                let touches_a_side = component
+                    .iter()
+                    .any(|pos| pos.x == 0 || pos.x == right_edge);
+                touches_a_side && !self.component_spans_edges(component)
+            })
+            // max_by_key keeps the last of equal sizes so reverse to keep the lowest instead
+            .rev()
+            .max_by_key(Vec::len)
+    }
+
+    /// Several measurements of the board at once. The cell counts share a single pass over the
+    /// grid, while `near_miss` and `chains` still cost a `biggest_near_miss` and an
+    /// `active_chains` call.
+    pub fn summary(&self) -> BoardSummary {
+        let mut filled = 0;
+        let mut active = 0;
+        let mut max_column_height = 0;
+        for (index, cell) in self.cells.iter().enumerate() {
+            if !cell.is_filled() {
                 continue;
             }
-            if nodes.contains(&node) {
-                return true;
-            };
+
+            filled += 1;
+            if cell.is_active() {
+                active += 1;
+            }
+            // Cells are stored bottom to top so the last filled cell has the highest row
+            max_column_height = index / self.width + 1;
         }
 
-        false
+        BoardSummary {
+            filled,
+            active,
+            near_miss: self.biggest_near_miss(),
+            max_column_height,
+            fill_fraction: filled as f32 / self.cells.len() as f32,
+            chains: self.active_chains().len(),
+        }
     }
 
-    pub fn pop_top_row(&mut self) -> Vector<Cell> {
-        trace!("removing top row from grid");
-        let y = self.height - 1;
-        let start_of_last_row = y * self.width;
-        let end_of_last_row = self.cells.len();
-        let popped_row = self.cells.slice(start_of_last_row..end_of_last_row);
+    /// Count how many active chains there are of each size, keyed by the number of cells in the
+    /// chain.
+    pub fn active_chain_size_histogram(&self) -> BTreeMap<usize, usize> {
+        let mut histogram = BTreeMap::new();
+        for chain in self.active_chains() {
+            *histogram.entry(chain.len()).or_default() += 1;
+        }
 
-        popped_row
+        histogram
     }
 
-    pub fn push_bottom_row(&mut self, mut new_row: Vector<Cell>) -> Result<(), GunpeyLibError> {
-        if new_row.len() != self.width {
-            return Err(GunpeyLibError::InvalidRowLength(new_row.len(), self.width));
+    /// The Shannon entropy, in bits, of the mix of fragment kinds on the board. An even mix of the
+    /// four regular kinds scores 2 and a board holding a single kind scores 0, so a random row
+    /// generator that scores well below 2 over many rows is probably biased. Empty boards score 0.
+    pub fn kind_entropy(&self) -> f32 {
+        let mut kind_counts: HashMap<LineFragmentKind, usize> = HashMap::new();
+        for kind in self.cells.iter().filter_map(Cell::kind) {
+            *kind_counts.entry(kind).or_default() += 1;
         }
 
-        trace!("pushing new row to bottom of grid");
-        new_row.append(self.cells.clone());
-        self.cells = new_row;
+        let filled_cells: usize = kind_counts.values().sum();
+        kind_counts
+            .values()
+            .map(|&count| {
+                let p = count as f32 / filled_cells as f32;
+                -p * p.log2()
+            })
+            .sum()
+    }
+
+    /// Empty every active cell and return where they were, in index order. Fragments that were
+    /// only active because of a cleared cell are deactivated by the recalculation afterwards.
+    pub fn clear_active_cells(&mut self) -> Vec<GridPos> {
+        let cleared: Vec<_> = self
+            .cells
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, cell)| cell.is_active())
+            .map(|(index, cell)| {
+                *cell = Cell::Empty;
+                get_pos_from_index(index, self.width)
+            })
+            .collect();
+
+        if cleared.is_empty() {
+            return cleared;
+        }
+        debug!("cleared {} active cells", cleared.len());
 
         self.recalculate_active_cells();
 
-        Ok(())
+        cleared
     }
 
-    pub fn is_cell_active(&self, cell_pos: &GridPos) -> bool {
-        let cell_index = self
-            .get_index_from_pos(cell_pos)
-            .expect("bad grid pos, can't get cell::is_active");
-        self.cells
-            .get(cell_index)
-            .map(|cell| match cell {
-                Cell::Filled(LineFragment { is_active, .. }) => *is_active,
-                Cell::Empty => false,
-            })
-            .unwrap_or_default()
+    /// Clear active cells and let everything above them fall until no more lines form. Each
+    /// clear counts as one link in the chain, so a result with a `chain_length` of 3 means two
+    /// more lines formed as fragments fell into the gaps left by the first.
+    pub fn resolve_board(&mut self) -> ChainResult {
+        let mut result = ChainResult::default();
+        self.recalculate_active_cells();
+
+        loop {
+            let cleared = self.clear_active_cells();
+            if cleared.is_empty() {
+                break;
+            }
+
+            result.total_cleared += cleared.len();
+            result.chain_length += 1;
+            self.apply_gravity();
+        }
+        debug!("resolved board with {:?}", result);
+
+        result
     }
 
-    pub fn is_cell_empty(&self, cell_pos: &GridPos) -> bool {
-        let cell_index = self
-            .get_index_from_pos(cell_pos)
-            .unwrap_or_else(|| panic!("bad grid pos {}, can't get cell::is_empty", cell_pos));
-        self.cells
-            .get(cell_index)
-            .map(Cell::is_empty)
-            .unwrap_or_default()
+    /// Remove every active chain from the grid, then recalculate. Each removed chain is
+    /// returned along with the kinds of fragment it was made of, so a caller can reward chains
+    /// made up of only one kind. Does nothing if no cells are active.
+    pub fn clear_active_chains(&mut self) -> Vec<ClearedChain> {
+        let cleared_chains: Vec<_> = self
+            .active_chains()
+            .into_iter()
+            .map(|positions| {
+                let kinds = positions
+                    .iter()
+                    .filter_map(|pos| self.get_cell_at_pos(pos).and_then(Cell::kind))
+                    .collect();
+
+                ClearedChain { positions, kinds }
+            })
+            .collect();
+
+        if cleared_chains.is_empty() {
+            return cleared_chains;
+        }
+
+        for pos in cleared_chains
+            .iter()
+            .flat_map(|chain| chain.positions.iter())
+        {
+            if let Some(cell) = self.get_mut_cell_at_pos(pos) {
+                *cell = Cell::Empty;
+            }
+        }
+        debug!("cleared {} chains", cleared_chains.len());
+
+        self.recalculate_active_cells();
+
+        cleared_chains
     }
 
-    pub fn below(&self, grid_pos: GridPos) -> Option<GridPos> {
-        if grid_pos.y == 0 {
-            None
-        } else {
-            Some(GridPos {
-                y: grid_pos.y - 1,
-                ..grid_pos
+    /// `clear_active_chains` for the variant where filling a whole row with a chain earns a
+    /// bonus. Every cell in a fully active row belongs to a chain, so clearing the chains clears
+    /// the row too, but the row is also reported on its own so the caller can pay out the bonus.
+    pub fn clear_active_chains_with_row_bonus(&mut self) -> ClearSummary {
+        let full_rows: Vec<_> = (0..self.height)
+            .filter(|&y| self.row(y).iter().all(Cell::is_active))
+            .collect();
+        // A chain running through several full rows must still only pay out each cell once
+        let bonus_cells: BTreeSet<GridPos> = full_rows
+            .iter()
+            .flat_map(|&y| (0..self.width).map(move |x| gp(x as isize, y as isize)))
+            .collect();
+
+        ClearSummary {
+            chains: self.clear_active_chains(),
+            full_rows,
+            bonus_cells: bonus_cells.into_iter().collect(),
+        }
+    }
+
+    /// Group filled cells matching `include` into sets of connected cells.
+    fn connected_components(&self, include: impl Fn(&Cell) -> bool) -> Vec<Vec<GridPos>> {
+        let is_included = |cell: &Cell| cell.is_filled() && include(cell);
+        let mut visited = HashSet::new();
+        let mut components = Vec::new();
+
+        for (index, cell) in self.cells.iter().enumerate() {
+            let start = self.get_pos_from_index(index);
+            if !is_included(cell) || visited.contains(&start) {
+                continue;
+            }
+
+            let mut component = Vec::new();
+            let mut to_visit = vec![start];
+            visited.insert(start);
+
+            while let Some(cell_pos) = to_visit.pop() {
+                let cell = self.get_cell_at_pos(&cell_pos).unwrap();
+                let connected_neighbors =
+                    self.neighbors_where(cell_pos, |adjacency, neighboring_cell| {
+                        is_included(neighboring_cell)
+                            && cell.is_connected_to(neighboring_cell, adjacency)
+                    });
+
+                for neighboring_pos in connected_neighbors {
+                    if visited.insert(neighboring_pos) {
+                        to_visit.push(neighboring_pos);
+                    }
+                }
+
+                component.push(cell_pos);
+            }
+
+            component.sort();
+            components.push(component);
+        }
+
+        components
+    }
+
+    /// Whether the fragments at `a` and `b` are part of the same chain of connected fragments,
+    /// active or not. Empty and out of bounds positions are never connected to anything.
+    pub fn are_cells_connected(&self, a: GridPos, b: GridPos) -> bool {
+        let is_filled = |pos: &GridPos| {
+            self.get_cell_at_pos(pos)
+                .map(Cell::is_filled)
+                .unwrap_or_default()
+        };
+        if !is_filled(&a) || !is_filled(&b) {
+            return false;
+        }
+
+        let mut visited = HashSet::from([a]);
+        let mut to_visit = vec![a];
+        while let Some(cell_pos) = to_visit.pop() {
+            if cell_pos == b {
+                return true;
+            }
+
+            let cell = self.get_cell_at_pos(&cell_pos).unwrap();
+            let connected_neighbors = self
+                .neighbors_where(cell_pos, |adjacency, neighboring_cell| {
+                    cell.is_connected_to(neighboring_cell, adjacency)
+                });
+            for neighboring_pos in connected_neighbors {
+                if visited.insert(neighboring_pos) {
+                    to_visit.push(neighboring_pos);
+                }
+            }
+        }
+
+        false
+    }
+
+    /// The positions surrounding `grid_pos` whose cell satisfies `predicate`. The predicate is also
+    /// given the neighbor's position relative to `grid_pos`.
+    pub fn neighbors_where(
+        &self,
+        grid_pos: GridPos,
+        predicate: impl Fn(Adjacency, &Cell) -> bool,
+    ) -> Vec<GridPos> {
+        self.neighbors(grid_pos)
+            .into_iter()
+            .filter(|neighboring_pos| {
+                let adjacency = adjacency_of_grid_positions(grid_pos, *neighboring_pos);
+                self.get_cell_at_pos(neighboring_pos)
+                    .map(|neighboring_cell| predicate(adjacency, neighboring_cell))
+                    .unwrap_or_default()
             })
+            .collect()
+    }
+
+    /// Describe how the cell at `pos` connects to the cells around it, one fact per line: what
+    /// the cell holds, its corner nodes, whether each neighbor connects to it and why, and
+    /// whether its group of connected fragments reaches each side of the grid. Meant for
+    /// debugging boards that don't behave the way you'd expect.
+    pub fn explain_cell(&self, pos: GridPos) -> String {
+        use std::fmt::Write;
+
+        let cell = match self.get_cell_at_pos(&pos) {
+            Some(cell) => cell,
+            None => return format!("{} is outside the {}x{} grid", pos, self.width, self.height),
+        };
+        let describe = |cell: &Cell| match cell.kind() {
+            Some(kind) => format!("'{}' {}", cell, kind),
+            None if cell.is_blocked() => "a blocked cell".to_owned(),
+            None => "empty".to_owned(),
+        };
+
+        let mut explanation = String::new();
+        let status = if cell.is_active() {
+            "active"
+        } else {
+            "inactive"
+        };
+        writeln!(
+            explanation,
+            "{} holds {} and is {}",
+            pos,
+            describe(cell),
+            status
+        )
+        .unwrap();
+
+        let corner_nodes = cell.corner_nodes(&pos);
+        let corner_list: Vec<_> = corner_nodes.iter().map(ToString::to_string).collect();
+        if corner_list.is_empty() {
+            writeln!(explanation, "corner nodes: none").unwrap();
+        } else {
+            writeln!(explanation, "corner nodes: {}", corner_list.join(", ")).unwrap();
+        }
+
+        for neighboring_pos in self.neighbors(pos) {
+            let neighboring_cell = self.get_cell_at_pos(&neighboring_pos).unwrap();
+            let adjacency = adjacency_of_grid_positions(pos, neighboring_pos);
+            let reason = if !cell.is_filled() || !neighboring_cell.is_filled() {
+                "doesn't connect, fragments only connect to other fragments".to_owned()
+            } else if cell.is_connected_to(neighboring_cell, adjacency) {
+                let shared: Vec<_> = neighboring_cell
+                    .corner_nodes(&neighboring_pos)
+                    .into_iter()
+                    .filter(|node| corner_nodes.contains(node))
+                    .map(|node| node.to_string())
+                    .collect();
+                format!("connects, the lines meet at {}", shared.join(" and "))
+            } else {
+                "doesn't connect, the lines don't meet at any corner".to_owned()
+            };
+            writeln!(
+                explanation,
+                "{} neighbor at {} holds {}: {}",
+                adjacency,
+                neighboring_pos,
+                describe(neighboring_cell),
+                reason
+            )
+            .unwrap();
         }
+
+        let component = self
+            .connected_components(|_| true)
+            .into_iter()
+            .find(|component| component.contains(&pos))
+            .unwrap_or_default();
+        let yes_or_no = |reaches: bool| if reaches { "yes" } else { "no" };
+        let right_edge = self.width as isize - 1;
+        writeln!(
+            explanation,
+            "reaches left edge: {}",
+            yes_or_no(component.iter().any(|pos| pos.x == 0))
+        )
+        .unwrap();
+        write!(
+            explanation,
+            "reaches right edge: {}",
+            yes_or_no(component.iter().any(|pos| pos.x == right_edge))
+        )
+        .unwrap();
+
+        explanation
     }
 
-    pub fn above(&self, grid_pos: GridPos) -> Option<GridPos> {
-        if grid_pos.y == self.height as isize - 1 {
-            None
+    /// How many neighbors each fragment is connected to, from 0 for isolated fragments up to 8.
+    /// Empty cells aren't included.
+    pub fn connection_degrees(&self) -> HashMap<GridPos, usize> {
+        self.cells
+            .iter()
+            .enumerate()
+            .filter(|(_, cell)| cell.is_filled())
+            .map(|(index, cell)| {
+                let cell_pos = self.get_pos_from_index(index);
+                let connected_neighbors = self
+                    .neighbors_where(cell_pos, |adjacency, neighboring_cell| {
+                        cell.is_connected_to(neighboring_cell, adjacency)
+                    });
+
+                (cell_pos, connected_neighbors.len())
+            })
+            .collect()
+    }
+
+    /// Every in-bounds position surrounding `grid_pos`
+    fn neighbors(&self, grid_pos: GridPos) -> Vec<GridPos> {
+        grid_pos
+            .neighbors()
+            .into_iter()
+            .filter(|pos| self.is_in_bounds(pos))
+            .collect()
+    }
+
+    fn swap_cells_by_index(
+        &mut self,
+        cell_index_a: usize,
+        cell_index_b: usize,
+    ) -> Result<(), GunpeyLibError> {
+        if cell_index_a == cell_index_b {
+            return Err(GunpeyLibError::CantSwapSameIndex(
+                cell_index_a,
+                cell_index_b,
+            ));
+        }
+
+        let valid_indexes = 0..self.cells.len();
+
+        if valid_indexes.contains(&cell_index_a) && valid_indexes.contains(&cell_index_b) {
+            self.cells.swap(cell_index_a, cell_index_b);
+
+            Ok(())
         } else {
-            Some(GridPos {
-                y: grid_pos.y + 1,
-                ..grid_pos
+            Err(GunpeyLibError::CantSwapBadIndex {
+                a: cell_index_a,
+                b: cell_index_b,
+                length: self.cells.len(),
             })
         }
     }
 
-    pub fn right(&self, grid_pos: GridPos) -> Option<GridPos> {
-        if grid_pos.x == self.width as isize - 1 {
-            None
-        } else {
-            Some(GridPos {
-                x: grid_pos.x + 1,
-                ..grid_pos
-            })
-        }
+    /// Returns the set of nodes that can still be part of a left-to-right line.
+    ///
+    /// Every node starts out as a candidate. A node that isn't on the left or right border and
+    /// has fewer than two edges is a dead end, so it gets removed. Removing it can turn its
+    /// neighbor into a new dead end, so this repeats until nothing else can be removed. Any
+    /// fragment with a corner node that didn't survive is part of a dangling branch and can't
+    /// be active.
+    fn prune_dangling_nodes(&self) -> HashSet<GridPos> {
+        let nodes = new_xy_iter(
+            self.width + 1,
+            self.height + 1,
+            GridIterDirectionX::LeftToRight,
+            GridIterDirectionY::BottomToTop,
+        )
+        .map(|(x, y)| gp(x as isize, y as isize))
+        .collect();
+
+        self.prune_dangling_nodes_from(nodes)
+    }
+
+    /// `prune_dangling_nodes` starting from just the `nodes` given instead of every node. Every
+    /// corner of a fragment touching one of them has to be included for the result to match.
+    fn prune_dangling_nodes_from(&self, mut nodes: HashSet<GridPos>) -> HashSet<GridPos> {
+        // Pruning a node can only leave its neighbors with fewer edges, so only the neighbors
+        // need checking again. Every node sharing a cell with it is at most one step away.
+        let mut to_check: VecDeque<_> = nodes.iter().copied().collect();
+        while let Some(node) = to_check.pop_front() {
+            if !nodes.contains(&node) || self.node_edge_count(&node, &nodes) >= 2 {
+                continue;
+            }
+
+            trace!("pruning dangling node {}", node);
+            nodes.remove(&node);
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    let neighbor = node + gp(dx, dy);
+                    if nodes.contains(&neighbor) {
+                        to_check.push_back(neighbor);
+                    }
+                }
+            }
+        }
+
+        nodes
+    }
+
+    /// Count the edges leading away from a node. Every node touches up to four cells and each of
+    /// those cells may connect it to another node that hasn't been pruned yet. Nodes on the left
+    /// and right borders treat the wall itself as an edge.
+    fn node_edge_count(&self, node: &GridPos, nodes: &HashSet<GridPos>) -> usize {
+        [gp(0, 0), gp(0, 1), gp(1, 1), gp(1, 0)]
+            .iter()
+            .filter(|&&offset| self.node_connects_across_cell(&(*node - offset), node, nodes))
+            .count()
+    }
+
+    fn node_connects_across_cell(
+        &self,
+        cell_pos: &GridPos,
+        node_pos: &GridPos,
+        nodes: &HashSet<GridPos>,
+    ) -> bool {
+        if !(0..self.width as isize).contains(&cell_pos.x) {
+            return true;
+        };
+        if !(0..self.height as isize).contains(&cell_pos.y) {
+            return false;
+        };
+
+        let cell = self.get_cell_at_pos(cell_pos).unwrap();
+        if !cell.has_corner_node(cell_pos, node_pos) {
+            return false;
+        };
+        for node in cell.corner_nodes(cell_pos) {
+            if node == *node_pos {
+                continue;
+            }
+            if nodes.contains(&node) {
+                return true;
+            };
+        }
+
+        false
+    }
+
+    /// Let every fragment fall as far as it can within its column and return whether anything
+    /// moved. Fragments keep their order within a column and the space left above them is
+    /// filled with empty cells.
+    ///
+    /// Cells that don't move keep their active flags. Fragments that do move have their flags
+    /// cleared since those flags describe a position they no longer occupy. Only the fragments
+    /// sharing corners with the columns that moved, before or after the fall, are recalculated,
+    /// since no chain through those columns reaches any further. If nothing moved then nothing
+    /// can have changed and the recalculation is skipped entirely.
+    ///
+    /// Gravity only ever sees the grid after a clear. `clear_active_chains` replaces cleared
+    /// fragments with `Cell::Empty` straight away, so anything the front-ends show fading out
+    /// is purely visual and can't hold up the cells above it.
+    pub fn apply_gravity(&mut self) -> bool {
+        !self.apply_gravity_with_moves().is_empty()
+    }
+
+    /// Like `apply_gravity` but returns the `(from, to)` position of every fragment that moved,
+    /// which can be passed to `drop_distance` to award a bonus for falling cells.
+    pub fn apply_gravity_with_moves(&mut self) -> Vec<(GridPos, GridPos)> {
+        let before = self.clone();
+        let moves = self.settle_columns();
+        if moves.is_empty() {
+            return moves;
+        }
+        trace!("gravity moved {} cells", moves.len());
+
+        if self.auto_clear_on_recalc {
+            // Clearing can make anything fall, so there's nothing to gain from narrowing it down
+            self.recalculate_active_cells();
+            return moves;
+        }
+
+        // Chains that ran through the columns that moved may have broken and new ones may have
+        // formed through them. Nothing else can have changed.
+        let moved_columns: HashSet<_> = moves.iter().map(|(from, _)| from.x).collect();
+        let in_moved_columns = |grid: &Grid| -> Vec<GridPos> {
+            moved_columns
+                .iter()
+                .flat_map(|&x| (0..grid.height as isize).map(move |y| gp(x, y)))
+                .collect()
+        };
+        let mut region = before.fragments_sharing_corners(in_moved_columns(&before));
+        region.extend(self.fragments_sharing_corners(in_moved_columns(self)));
+        // Cells that moved out of the region are empty now but may still be flagged
+        region.extend(in_moved_columns(self));
+
+        #[cfg(test)]
+        RECALCULATION_COUNT.with(|count| count.set(count.get() + 1));
+        self.recalculate_active_cells_among(&region);
+
+        moves
+    }
+
+    /// Every fragment that can be reached from the fragments at `start` by stepping between
+    /// fragments that share a corner. A chain can't pass between fragments any other way, so
+    /// the result holds every chain running through `start`.
+    fn fragments_sharing_corners(&self, start: Vec<GridPos>) -> HashSet<GridPos> {
+        let mut reached = HashSet::new();
+        let mut to_visit: Vec<_> = start
+            .into_iter()
+            .filter(|pos| self.get_cell_at_pos(pos).is_some_and(Cell::is_filled))
+            .collect();
+
+        while let Some(cell_pos) = to_visit.pop() {
+            if !reached.insert(cell_pos) {
+                continue;
+            }
+
+            let cell = self.get_cell_at_pos(&cell_pos).unwrap();
+            for node in cell.corner_nodes(&cell_pos) {
+                for offset in [gp(0, 0), gp(0, 1), gp(1, 1), gp(1, 0)] {
+                    let other_pos = node - offset;
+                    let shares_node = self
+                        .get_cell_at_pos(&other_pos)
+                        .is_some_and(|other| other.has_corner_node(&other_pos, &node));
+                    if shares_node && !reached.contains(&other_pos) {
+                        to_visit.push(other_pos);
+                    }
+                }
+            }
+        }
+
+        reached
+    }
+
+    /// Like `apply_gravity_with_moves` but staggers the moves for animating. Within each column
+    /// the lowest falling fragment starts first and each one above it waits one more step, so
+    /// sprites never pass through each other on the way down.
+    pub fn apply_gravity_animation_plan(&mut self) -> Vec<AnimatedMove> {
+        let mut moves = self.apply_gravity_with_moves();
+        moves.sort_by_key(|(from, _)| (from.x, from.y));
+
+        let mut animated_moves: Vec<AnimatedMove> = Vec::with_capacity(moves.len());
+        for (from, to) in moves {
+            let delay = match animated_moves.last() {
+                Some(previous) if previous.from.x == from.x => previous.delay + 1,
+                _ => 0,
+            };
+            animated_moves.push(AnimatedMove { from, to, delay });
+        }
+
+        animated_moves
+    }
+
+    /// Drop every fragment to the bottom of its column so the board has no gaps, then
+    /// recalculate. Unlike `apply_gravity` this always recalculates, which makes it a good
+    /// one-off cleanup for hand-written boards that have never been recalculated.
+    pub fn compact_all(&mut self) {
+        let moves = self.settle_columns();
+        debug!("compacting grid moved {} cells", moves.len());
+
+        self.recalculate_active_cells();
+    }
+
+    /// Compact every column towards `y == 0` without recalculating, returning the `(from, to)`
+    /// position of every fragment that moved. Blocked cells stay put and fragments above them
+    /// land on top of them.
+    fn settle_columns(&mut self) -> Vec<(GridPos, GridPos)> {
+        let mut moves = Vec::new();
+
+        for x in 0..self.width as isize {
+            // Everything below the landing spot is already filled
+            let mut landing_y = 0;
+
+            for y in 0..self.height as isize {
+                let from = gp(x, y);
+                let from_index = self.get_index_from_pos(&from).unwrap();
+                if self.cells[from_index].is_empty() {
+                    continue;
+                }
+                if self.cells[from_index].is_blocked() {
+                    landing_y = y + 1;
+                    continue;
+                }
+
+                if y != landing_y {
+                    let to = gp(x, landing_y);
+                    let to_index = self.get_index_from_pos(&to).unwrap();
+                    // Everything between the landing spot and here is empty, so swapping moves
+                    // the fragment down and the empty cell up
+                    self.cells.swap(from_index, to_index);
+                    self.cells[to_index].deactivate();
+                    moves.push((from, to));
+                }
+
+                landing_y += 1;
+            }
+        }
+
+        moves
+    }
+
+    /// Shift every cell in column `x` up by `by` rows, or down if `by` is negative, then
+    /// recalculate. The shift is cyclic so cells pushed off the top of the column wrap around to
+    /// the bottom and vice versa, nothing is ever lost.
+    pub fn shift_column(&mut self, x: usize, by: isize) -> Result<(), GunpeyLibError> {
+        if x >= self.width {
+            return Err(GunpeyLibError::ColumnOutOfBounds {
+                x,
+                width: self.width,
+            });
+        }
+
+        let by = by.rem_euclid(self.height as isize) as usize;
+        if by == 0 {
+            return Ok(());
+        }
+
+        let column_indexes: Vec<_> = (0..self.height).map(|y| x + self.width * y).collect();
+        let mut column: Vec<_> = column_indexes.iter().map(|&i| self.cells[i]).collect();
+        column.rotate_right(by);
+
+        for (index, cell) in column_indexes.into_iter().zip(column) {
+            self.cells[index] = cell;
+        }
+        self.recalculate_active_cells();
+
+        Ok(())
+    }
+
+    /// Change the size of the grid, keeping every cell that still fits where it was relative to the
+    /// bottom left corner. New space is filled with empty cells and cells that no longer fit are
+    /// dropped. Panics on the same sizes that `Grid::new` panics on.
+    pub fn resize(&mut self, new_width: usize, new_height: usize) {
+        assert!(
+            new_width > 0,
+            "width of resized Grid must be greater than 0!"
+        );
+        assert!(
+            new_height > 1,
+            "height of resized Grid must be greater than 1!"
+        );
+
+        let mut cells = Vector::new();
+        for y in 0..new_height {
+            for x in 0..new_width {
+                let cell = if x < self.width && y < self.height {
+                    self.cells[x + self.width * y]
+                } else {
+                    Cell::Empty
+                };
+                cells.push_back(cell);
+            }
+        }
+
+        debug!(
+            "resizing grid from {}x{} to {}x{}",
+            self.width, self.height, new_width, new_height
+        );
+        self.width = new_width;
+        self.height = new_height;
+        self.cells = cells;
+        self.recalculate_active_cells();
+    }
+
+    /// The row that `pop_top_row` would remove next time the rows cycle, without removing it.
+    pub fn peek_discarded_on_cycle(&self) -> Vector<Cell> {
+        self.row(self.height - 1)
+    }
+
+    /// Take the top row off the grid, leaving empty cells in its place so the grid is still
+    /// `width` by `height`. Rows cycle by calling this and then `push_bottom_row`, which pushes
+    /// the emptied row off the top.
+    pub fn pop_top_row(&mut self) -> Vector<Cell> {
+        trace!("removing top row from grid");
+        let y = self.height - 1;
+        let popped_row = self.row(y);
+
+        let start_of_last_row = y * self.width;
+        for cell in self.cells.iter_mut().skip(start_of_last_row) {
+            *cell = Cell::Empty;
+        }
+        debug_assert_eq!(self.width * self.height, self.cells.len());
+
+        // Only a line running through the top row can be broken by emptying it
+        if popped_row.iter().any(Cell::is_active) {
+            self.recalculate_active_cells();
+        }
+
+        popped_row
+    }
+
+    /// Add `new_row` at the bottom of the grid and move every other row up one. Whatever is in
+    /// the top row is pushed off the grid and lost, `pop_top_row` first to keep it.
+    pub fn push_bottom_row(&mut self, mut new_row: Vector<Cell>) -> Result<(), GunpeyLibError> {
+        if new_row.len() != self.width {
+            return Err(GunpeyLibError::InvalidRowLength(new_row.len(), self.width));
+        }
+
+        trace!("pushing new row to bottom of grid");
+        new_row.append(self.cells.clone());
+        new_row.truncate(self.width * self.height);
+        self.cells = new_row;
+        debug_assert_eq!(self.width * self.height, self.cells.len());
+
+        self.recalculate_active_cells();
+
+        Ok(())
+    }
+
+    pub fn is_cell_active(&self, cell_pos: &GridPos) -> bool {
+        let cell_index = self
+            .get_index_from_pos(cell_pos)
+            .expect("bad grid pos, can't get cell::is_active");
+        self.cells
+            .get(cell_index)
+            .map(Cell::is_active)
+            .unwrap_or_default()
+    }
+
+    /// Returns false for positions outside the grid
+    pub fn is_cell_blocked(&self, cell_pos: &GridPos) -> bool {
+        self.get_cell_at_pos(cell_pos)
+            .map(Cell::is_blocked)
+            .unwrap_or_default()
+    }
+
+    pub fn is_cell_empty(&self, cell_pos: &GridPos) -> bool {
+        let cell_index = self
+            .get_index_from_pos(cell_pos)
+            .unwrap_or_else(|| panic!("bad grid pos {}, can't get cell::is_empty", cell_pos));
+        self.cells
+            .get(cell_index)
+            .map(Cell::is_empty)
+            .unwrap_or_default()
+    }
+
+    pub fn below(&self, grid_pos: GridPos) -> Option<GridPos> {
+        if grid_pos.y == 0 {
+            None
+        } else {
+            Some(GridPos {
+                y: grid_pos.y - 1,
+                ..grid_pos
+            })
+        }
+    }
+
+    pub fn above(&self, grid_pos: GridPos) -> Option<GridPos> {
+        if grid_pos.y == self.height as isize - 1 {
+            None
+        } else {
+            Some(GridPos {
+                y: grid_pos.y + 1,
+                ..grid_pos
+            })
+        }
+    }
+
+    pub fn right(&self, grid_pos: GridPos) -> Option<GridPos> {
+        if grid_pos.x == self.width as isize - 1 {
+            None
+        } else {
+            Some(GridPos {
+                x: grid_pos.x + 1,
+                ..grid_pos
+            })
+        }
+    }
+
+    pub fn left(&self, grid_pos: GridPos) -> Option<GridPos> {
+        if grid_pos.x == 0 {
+            None
+        } else {
+            Some(GridPos {
+                x: grid_pos.x - 1,
+                ..grid_pos
+            })
+        }
+    }
+
+    pub fn above_right(&self, grid_pos: GridPos) -> Option<GridPos> {
+        self.above(grid_pos).and_then(|gp| self.right(gp))
+    }
+
+    pub fn below_right(&self, grid_pos: GridPos) -> Option<GridPos> {
+        self.below(grid_pos).and_then(|gp| self.right(gp))
+    }
+
+    pub fn above_left(&self, grid_pos: GridPos) -> Option<GridPos> {
+        self.above(grid_pos).and_then(|gp| self.left(gp))
+    }
+
+    pub fn below_left(&self, grid_pos: GridPos) -> Option<GridPos> {
+        self.below(grid_pos).and_then(|gp| self.left(gp))
+    }
+
+    /// Move a cursor at `pos` by `delta`, stopping at the edges of the grid instead of leaving it
+    pub fn clamp_cursor(&self, pos: GridPos, delta: GridPos) -> GridPos {
+        let moved = pos + delta;
+
+        gp(
+            moved.x.clamp(0, self.width as isize - 1),
+            moved.y.clamp(0, self.height as isize - 1),
+        )
+    }
+}
+
+/// Prints the grid top row first in the letters `new_from_str` reads, uppercase for active
+/// fragments and lowercase for inactive ones, so the output can be pasted straight back in.
+impl Display for Grid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for row in self.cell_rows_in_render_order() {
+            for cell in row {
+                write!(f, "{}", cell)?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A cell written with the letters `new_from_str` uses, uppercase for active fragments
+fn cell_from_letter(c: char) -> Result<Cell, GunpeyLibError> {
+    match c {
+        '.' => Ok(Cell::Empty),
+        '#' => Ok(Cell::Blocked),
+        _ => LineFragment::try_from_str(c.encode_utf8(&mut [0; 4]))
+            .map(Cell::Filled)
+            .ok_or(GunpeyLibError::InvalidCellChar(c)),
+    }
+}
+
+pub fn get_pos_from_index(index: usize, width: usize) -> GridPos {
+    let x = (index % width) as isize;
+    let y = (index / width) as isize;
+
+    GridPos { x, y }
+}
+
+/// Makes changes to a grid without recalculating active cells, see `Grid::edit`
+pub struct GridEditor<'a> {
+    grid: &'a mut Grid,
+    errors: Vec<GunpeyLibError>,
+}
+
+impl GridEditor<'_> {
+    pub fn set(&mut self, grid_pos: GridPos, cell: Cell) {
+        if let Err(err) = self.check_bounds(grid_pos) {
+            self.errors.push(err);
+            return;
+        }
+
+        if let Some(current_cell) = self.grid.get_mut_cell_at_pos(&grid_pos) {
+            *current_cell = cell;
+        }
+    }
+
+    pub fn clear(&mut self, grid_pos: GridPos) {
+        self.set(grid_pos, Cell::Empty);
+    }
+
+    pub fn swap(&mut self, cell_pos_a: GridPos, cell_pos_b: GridPos) {
+        if cell_pos_a == cell_pos_b {
+            self.errors
+                .push(GunpeyLibError::CantSwapSamePositon(cell_pos_a, cell_pos_b));
+            return;
+        }
+
+        if !(self.grid.is_in_bounds(&cell_pos_a) && self.grid.is_in_bounds(&cell_pos_b)) {
+            self.errors.push(GunpeyLibError::CantSwapBadPosition {
+                a: cell_pos_a,
+                b: cell_pos_b,
+                width: self.grid.width,
+                height: self.grid.height,
+            });
+            return;
+        }
+
+        let index_a = self.grid.get_index_from_pos(&cell_pos_a).unwrap();
+        let index_b = self.grid.get_index_from_pos(&cell_pos_b).unwrap();
+        for (cell_pos, index) in [(cell_pos_a, index_a), (cell_pos_b, index_b)] {
+            if self.grid.cells[index].is_blocked() {
+                self.errors.push(GunpeyLibError::CantSwapBlocked(cell_pos));
+                return;
+            }
+        }
+
+        if let Err(err) = self.grid.swap_cells_by_index(index_a, index_b) {
+            self.errors.push(err);
+        }
+    }
+
+    fn check_bounds(&self, grid_pos: GridPos) -> Result<(), GunpeyLibError> {
+        if self.grid.is_in_bounds(&grid_pos) {
+            Ok(())
+        } else {
+            Err(GunpeyLibError::OutOfBounds {
+                pos: grid_pos,
+                width: self.grid.width,
+                height: self.grid.height,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    // Lets tests check how many times a grid was recalculated
+    pub(crate) static RECALCULATION_COUNT: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// The total number of rows fallen by every fragment moved by `Grid::apply_gravity_with_moves`
+pub fn drop_distance(moves: &[(GridPos, GridPos)]) -> usize {
+    moves
+        .iter()
+        .map(|(from, to)| (from.y - to.y).unsigned_abs())
+        .sum()
+}
+
+/// A fragment falling from one cell to another, see `Grid::apply_gravity_animation_plan`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnimatedMove {
+    pub from: GridPos,
+    pub to: GridPos,
+    /// How many animation steps to wait before this fragment starts falling
+    pub delay: usize,
+}
+
+/// A chain of fragments removed by `Grid::clear_active_chains`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClearedChain {
+    /// Where each fragment in the chain was before it was cleared
+    pub positions: Vec<GridPos>,
+    /// Every kind of fragment that was part of the chain
+    pub kinds: BTreeSet<LineFragmentKind>,
+}
+
+impl ClearedChain {
+    /// Returns true if the chain was made up of only one kind of fragment
+    pub fn is_pure(&self) -> bool {
+        self.kinds.len() == 1
+    }
+}
+
+/// What happened while `Grid::resolve_board` was clearing lines
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChainResult {
+    pub total_cleared: usize,
+    /// How many times lines were cleared, 0 if nothing was active
+    pub chain_length: u32,
+}
+
+/// Everything removed by `Grid::clear_active_chains_with_row_bonus`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClearSummary {
+    pub chains: Vec<ClearedChain>,
+    /// Rows that were completely active when they were cleared
+    pub full_rows: Vec<usize>,
+    /// Every position in `full_rows`, each listed once and in sorted order
+    pub bonus_cells: Vec<GridPos>,
+}
+
+/// Measurements of a board returned by `Grid::summary`
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoardSummary {
+    /// How many cells hold a fragment
+    pub filled: usize,
+    /// How many cells are part of an active chain
+    pub active: usize,
+    /// The same group of cells `Grid::biggest_near_miss` finds
+    pub near_miss: Option<Vec<GridPos>>,
+    /// How many rows tall the tallest column is, counting any gaps below its top fragment
+    pub max_column_height: usize,
+    /// The fraction of the grid that's filled, from 0.0 to 1.0
+    pub fill_fraction: f32,
+    /// How many separate active chains there are
+    pub chains: usize,
+}
+
+/// Everything `Grid::revert` needs to undo a swap made with `Grid::swap_with_token`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SwapToken {
+    indices: (usize, usize),
+    /// Whether each cell touched by the swap was active beforehand
+    prior_active: Vec<(usize, bool)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::line_fragment::LineFragment;
+    use druid::im::vector;
+    use pretty_assertions::assert_eq;
+
+    fn new_2x2_grid() -> Grid {
+        Grid::new_from_chars(vec![vec!['.', '.'], vec!['.', '.']])
+    }
+
+    /// Parse a bitmask written top row first like `Grid::new_from_str`, one row of `0`s and `1`s
+    /// per line. Blank lines and indentation are ignored.
+    fn bitmask_from_str(bitmask_str: &str) -> Bitmask {
+        bitmask_str
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                line.chars()
+                    .map(|c| match c {
+                        '0' => 0,
+                        '1' => 1,
+                        _ => panic!("'{}' isn't a bit in bitmask row \"{}\"", c, line),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Render two bitmasks side by side, followed by a column marking every cell where they
+    /// differ with an `x`.
+    fn pretty_diff(expected: &Bitmask, actual: &Bitmask) -> String {
+        let render_row = |row: Option<&Vec<u8>>| -> String {
+            row.map(|row| row.iter().map(u8::to_string).collect())
+                .unwrap_or_default()
+        };
+        let row_len = expected
+            .iter()
+            .chain(actual.iter())
+            .map(Vec::len)
+            .max()
+            .unwrap_or_default();
+        let column_width = row_len.max("expected".len());
+
+        let mut diff = format!(
+            "{:w$} | {:w$} | mismatches\n",
+            "expected",
+            "actual",
+            w = column_width
+        );
+        for y in 0..expected.len().max(actual.len()) {
+            let expected_row = expected.get(y);
+            let actual_row = actual.get(y);
+            let mismatches: String = (0..row_len)
+                .map(|x| {
+                    if expected_row.and_then(|row| row.get(x))
+                        == actual_row.and_then(|row| row.get(x))
+                    {
+                        '.'
+                    } else {
+                        'x'
+                    }
+                })
+                .collect();
+
+            diff.push_str(&format!(
+                "{:w$} | {:w$} | {}\n",
+                render_row(expected_row),
+                render_row(actual_row),
+                mismatches,
+                w = column_width
+            ));
+        }
+
+        diff
+    }
+
+    /// Like `assert_eq!` for bitmasks but a failure prints `pretty_diff` of the two
+    macro_rules! assert_bitmask_eq {
+        ($expected:expr, $actual:expr) => {{
+            let expected: &Bitmask = &$expected;
+            let actual: &Bitmask = &$actual;
+            if expected != actual {
+                panic!("bitmasks differ\n{}", pretty_diff(expected, actual));
+            }
+        }};
+    }
+
+    #[test]
+    fn test_get_pos_from_index() {
+        let width = 3;
+
+        let expected = GridPos { x: 0, y: 0 };
+        let actual = get_pos_from_index(0, width);
+
+        assert_eq!(expected, actual);
+
+        let expected = GridPos { x: 2, y: 0 };
+        let actual = get_pos_from_index(2, width);
+
+        assert_eq!(expected, actual);
+
+        let expected = GridPos { x: 2, y: 1 };
+        let actual = get_pos_from_index(5, width);
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn testget_cell_at_pos() {
+        let grid = Grid::new_from_str(
+            r#"
+                .c.
+                r.i
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(".", grid.get_cell_at_pos(&gp(0, 1)).unwrap().to_str());
+        assert_eq!("c", grid.get_cell_at_pos(&gp(1, 1)).unwrap().to_str());
+        assert_eq!(".", grid.get_cell_at_pos(&gp(2, 1)).unwrap().to_str());
+        assert_eq!("r", grid.get_cell_at_pos(&gp(0, 0)).unwrap().to_str());
+        assert_eq!(".", grid.get_cell_at_pos(&gp(1, 0)).unwrap().to_str());
+        assert_eq!("i", grid.get_cell_at_pos(&gp(2, 0)).unwrap().to_str());
+    }
+
+    //   0,0 0,1
+    //   1,0 1,1
+
+    #[test]
+    fn test_grid_pos_above_some() {
+        let grid = new_2x2_grid();
+        let starting_grid_pos = GridPos { x: 0, y: 0 };
+        let expected = Some(GridPos { x: 0, y: 1 });
+        let actual = grid.above(starting_grid_pos);
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_grid_pos_above_none() {
+        let grid = new_2x2_grid();
+        let starting_grid_pos = GridPos { x: 0, y: 1 };
+        let expected = None;
+        let actual = grid.above(starting_grid_pos);
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_grid_pos_above_right_some() {
+        let grid = new_2x2_grid();
+        let starting_grid_pos = GridPos { x: 0, y: 0 };
+        let expected = Some(GridPos { x: 1, y: 1 });
+        let actual = grid.above_right(starting_grid_pos);
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_grid_pos_above_right_none() {
+        let grid = new_2x2_grid();
+        let starting_grid_pos = GridPos { x: 1, y: 1 };
+        let expected = None;
+        let actual = grid.above_right(starting_grid_pos);
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_grid_pos_right_some() {
+        let grid = new_2x2_grid();
+        let starting_grid_pos = GridPos { x: 0, y: 0 };
+        let expected = Some(GridPos { x: 1, y: 0 });
+        let actual = grid.right(starting_grid_pos);
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_grid_pos_right_none() {
+        let grid = new_2x2_grid();
+        let starting_grid_pos = GridPos { x: 1, y: 0 };
+        let expected = None;
+        let actual = grid.right(starting_grid_pos);
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_grid_pos_below_right_some() {
+        let grid = new_2x2_grid();
+        let starting_grid_pos = GridPos { x: 0, y: 1 };
+        let expected = Some(GridPos { x: 1, y: 0 });
+        let actual = grid.below_right(starting_grid_pos);
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_grid_pos_below_right_none() {
+        let grid = new_2x2_grid();
+        let starting_grid_pos = GridPos { x: 1, y: 0 };
+        let expected = None;
+        let actual = grid.below_right(starting_grid_pos);
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_grid_pos_below_some() {
+        let grid = new_2x2_grid();
+        let starting_grid_pos = GridPos { x: 0, y: 1 };
+        let expected = Some(GridPos { x: 0, y: 0 });
+        let actual = grid.below(starting_grid_pos);
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_grid_pos_below_none() {
+        let grid = new_2x2_grid();
+        let starting_grid_pos = GridPos { x: 0, y: 0 };
+        let expected = None;
+        let actual = grid.below(starting_grid_pos);
+
+        assert_eq!(expected, actual);
+    }
+
+    /// Every pair of neighboring cells that connect, in both directions
+    fn connected_pairs(grid: &Grid) -> Vec<(GridPos, GridPos)> {
+        (0..grid.cells.len())
+            .map(|index| grid.get_pos_from_index(index))
+            .flat_map(|cell_pos| {
+                let cell = grid.get_cell_at_pos(&cell_pos).unwrap();
+                grid.neighbors_where(cell_pos, |adjacency, neighboring_cell| {
+                    cell.is_connected_to(neighboring_cell, adjacency)
+                })
+                .into_iter()
+                .map(move |neighboring_pos| (cell_pos, neighboring_pos))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_edges_should_be_detected_1() {
+        #[rustfmt::skip]
+        let chars: CharGrid = vec![
+            vec!['/','/'],
+            vec!['/','/'],
+        ];
+        let grid = Grid::new_from_chars(chars);
+        let mut expected: Vec<(GridPos, GridPos)> = vec![
+            (GridPos::new(0, 0), GridPos::new(1, 1)),
+            (GridPos::new(1, 1), GridPos::new(0, 0)),
+        ];
+        expected.sort();
+
+        let mut actual = connected_pairs(&grid);
+        actual.sort();
+
+        assert_eq!(expected, actual)
+    }
+
+    #[test]
+    fn test_edges_should_be_detected_2() {
+        #[rustfmt::skip]
+        let chars: CharGrid = vec![
+            vec!['∨','∨'],
+            vec!['∧','∧'],
+        ];
+        let grid = Grid::new_from_chars(chars);
+        let expected: Vec<(GridPos, GridPos)> = vec![
+            (GridPos::new(0, 0), GridPos::new(1, 0)),
+            (GridPos::new(1, 0), GridPos::new(0, 0)),
+            (GridPos::new(0, 1), GridPos::new(1, 1)),
+            (GridPos::new(1, 1), GridPos::new(0, 1)),
+        ];
+        let actual = connected_pairs(&grid);
+
+        assert_eq!(expected, actual)
+    }
+
+    #[test]
+    fn test_edges_should_not_be_detected() {
+        #[rustfmt::skip]
+        let chars: CharGrid = vec![
+            vec!['∨','/'],
+            vec!['∧','.'],
+        ];
+        let grid = Grid::new_from_chars(chars);
+        let expected: Vec<(GridPos, GridPos)> = vec![];
+        let actual = connected_pairs(&grid);
+
+        assert_eq!(expected, actual)
+    }
+
+    #[test]
+    fn test_recalculate_active_cells_are_active_1() {
+        #[rustfmt::skip]
+        let chars: CharGrid = vec![
+            vec!['.','∧','.'],
+            vec!['/','.','∨'],
+        ];
+        #[rustfmt::skip]
+        let expected_active: Bitmask = vec![
+            vec![0, 0, 0],
+            vec![0, 0, 0],
+        ];
+        let mut grid = Grid::new_from_chars(chars);
+
+        // None should be active before recalculation
+        let actual_active = grid.as_active_bitmask();
+        assert_bitmask_eq!(expected_active, actual_active);
+
+        grid.recalculate_active_cells();
+
+        #[rustfmt::skip]
+        let expected_active: Bitmask = vec![
+            vec![0, 1, 0],
+            vec![1, 0, 1],
+        ];
+
+        // All should be active after recalculation
+        let actual_active = grid.as_active_bitmask();
+        assert_bitmask_eq!(expected_active, actual_active);
+    }
+
+    #[test]
+    fn test_recalculate_active_cells_are_active_2() {
+        #[rustfmt::skip]
+        let chars: CharGrid = vec![
+            vec!['.','.','.','∧','.'],
+            vec!['∧','∧','/','.','\\'],
+            vec!['.','\\','\\','/','∨'],
+            vec!['.','.','∨','\\','∧'],
+            vec!['.','.','.','.','∨'],
+        ];
+        #[rustfmt::skip]
+        let expected_active: Bitmask = vec![
+            vec![0, 0, 0, 0, 0],
+            vec![0, 0, 0, 0, 0],
+            vec![0, 0, 0, 0, 0],
+            vec![0, 0, 0, 0, 0],
+            vec![0, 0, 0, 0, 0],
+        ];
+        let mut grid = Grid::new_from_chars(chars);
+
+        // None should be active before recalculation
+        let actual_active = grid.as_active_bitmask();
+        assert_bitmask_eq!(expected_active, actual_active);
+
+        grid.recalculate_active_cells();
+
+        #[rustfmt::skip]
+        let expected_active: Bitmask = vec![
+            vec![0, 0, 0, 1, 0],
+            vec![1, 1, 1, 0, 1],
+            vec![0, 1, 1, 1, 1],
+            vec![0, 0, 1, 1, 1],
+            vec![0, 0, 0, 0, 1],
+        ];
+
+        // All should be active after recalculation
+        let actual_active = grid.as_active_bitmask();
+        assert_bitmask_eq!(expected_active, actual_active);
+    }
+
+    #[test]
+    fn test_recalculate_active_cells_are_active_3() {
+        #[rustfmt::skip]
+        let grid = r#"
+            .rc
+            ri.
+        "#;
+        let expected_active = bitmask_from_str(
+            r#"
+            000
+            000
+            "#,
+        );
+        let mut grid = Grid::new_from_str(grid).unwrap();
+
+        // None should be active before recalculation
+        let actual_active = grid.as_active_bitmask();
+        assert_bitmask_eq!(expected_active, actual_active);
+
+        grid.recalculate_active_cells();
+
+        let expected_active = bitmask_from_str(
+            r#"
+            001
+            110
+            "#,
+        );
+
+        // Connected cells should be active after recalculation
+        let actual_active = grid.as_active_bitmask();
+        assert_bitmask_eq!(expected_active, actual_active);
+    }
+
+    #[test]
+    fn test_recalculate_active_cells_all_inactive() {
+        #[rustfmt::skip]
+        let chars: CharGrid = vec![
+            vec!['/','.'],
+            vec!['.','/'],
+        ];
+        #[rustfmt::skip]
+        let expected_active: Bitmask = vec![
+            vec![0,0],
+            vec![0,0],
+        ];
+
+        let mut grid = Grid::new_from_chars(chars);
+
+        // None should be active before recalculation
+        let actual_active = grid.as_active_bitmask();
+        assert_bitmask_eq!(expected_active, actual_active);
+
+        grid.recalculate_active_cells();
+
+        // None should be active after recalculation either
+        let actual_active = grid.as_active_bitmask();
+        assert_bitmask_eq!(expected_active, actual_active);
+    }
+
+    #[test]
+    fn test_recalculate_active_cells_dead_end_branch_is_inactive() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            .r..
+            iiii
+            "#,
+        )
+        .unwrap();
+
+        grid.recalculate_active_cells();
+
+        // The slash only touches the line at one end, the other end leads nowhere
+        let expected_active = bitmask_from_str(
+            r#"
+            0000
+            1111
+            "#,
+        );
+        let actual_active = grid.as_active_bitmask();
+        assert_bitmask_eq!(expected_active, actual_active);
+    }
+
+    #[test]
+    fn test_recalculate_active_cells_long_dead_end_branch_is_inactive() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            ..r.
+            .r..
+            iiii
+            "#,
+        )
+        .unwrap();
+
+        grid.recalculate_active_cells();
+
+        // Pruning the end of the branch leaves the next fragment dangling, so it's pruned too
+        let expected_active = bitmask_from_str(
+            r#"
+            0000
+            0000
+            1111
+            "#,
+        );
+        let actual_active = grid.as_active_bitmask();
+        assert_bitmask_eq!(expected_active, actual_active);
+    }
+
+    #[test]
+    fn test_prune_dangling_nodes() {
+        let grid = Grid::new_from_str(
+            r#"
+            .r..
+            iiii
+            "#,
+        )
+        .unwrap();
+
+        let nodes = grid.prune_dangling_nodes();
+
+        // Every node on the line survives
+        for x in 0..=4 {
+            assert!(nodes.contains(&gp(x, 1)), "node ({}, 1) was pruned", x);
+        }
+        // The loose end of the branch does not
+        assert!(!nodes.contains(&gp(2, 2)));
+    }
+
+    #[test]
+    fn test_recalculate_active_cells_1() {
+        let mut grid = Grid::new_from_str(
+            r#"
+        .....
+        .....
+        .....
+        .....
+        .....
+        .....
+        .....
+        .....
+        .crlr
+        ic...
+        "#,
+        )
+        .unwrap();
+
+        let expected_active = bitmask_from_str(
+            r#"
+        00000
+        00000
+        00000
+        00000
+        00000
+        00000
+        00000
+        00000
+        00000
+        00000
+        "#,
+        );
+
+        // None should be active before recalculation
+        let actual_active = grid.as_active_bitmask();
+        assert_bitmask_eq!(expected_active, actual_active);
+
+        grid.recalculate_active_cells();
+
+        let expected_active = bitmask_from_str(
+            r#"
+        00000
+        00000
+        00000
+        00000
+        00000
+        00000
+        00000
+        00000
+        01111
+        10000
+        "#,
+        );
+
+        // The bottom two rows make a line from wall to wall
+        let actual_active = grid.as_active_bitmask();
+        assert_bitmask_eq!(expected_active, actual_active);
+        assert!(grid.active_bitmask_matches(&expected_active));
+    }
+
+    #[test]
+    fn test_pop_top_row() {
+        let mut grid = Grid::new_from_str(
+            r#"
+        .cc
+        .l.
+        ..l
+        "#,
+        )
+        .unwrap();
+
+        let expected_popped_row = vector![
+            Cell::Empty,
+            Cell::Filled(LineFragment::from_str("c")),
+            Cell::Filled(LineFragment::from_str("c"))
+        ];
+        let actual_popped_row = grid.pop_top_row();
+
+        assert_eq!(expected_popped_row, actual_popped_row);
+    }
+
+    #[test]
+    fn test_pop_top_row_keeps_grid_size() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            .cc
+            .l.
+            ..l
+            "#,
+        )
+        .unwrap();
+
+        grid.pop_top_row();
+
+        assert_eq!(9, grid.cells.len());
+        let expected = Grid::new_from_str(
+            r#"
+            ...
+            .l.
+            ..l
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected.as_chars(), grid.as_chars());
+
+        // Popping again takes the now empty row
+        assert_eq!(Vector::from(vec![Cell::Empty; 3]), grid.pop_top_row());
+        assert_eq!(9, grid.cells.len());
+    }
+
+    #[test]
+    fn test_pop_top_row_breaks_line_through_it() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            rl
+            ..
+            "#,
+        )
+        .unwrap();
+        grid.recalculate_active_cells();
+        assert_eq!(2, grid.count_active_cells());
+
+        let popped = grid.pop_top_row();
+
+        assert!(popped.iter().all(Cell::is_active));
+        assert_eq!(0, grid.count_active_cells());
+    }
+
+    #[test]
+    fn test_push_bottom_row_without_pop_keeps_grid_size() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            c..
+            .i.
+            "#,
+        )
+        .unwrap();
+
+        grid.push_bottom_row(vector![Cell::Empty, Cell::Empty, Cell::from_str("l")])
+            .unwrap();
+
+        assert_eq!(6, grid.cells.len());
+        let expected = Grid::new_from_str(
+            r#"
+            .i.
+            ..l
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected.as_chars(), grid.as_chars());
+    }
+
+    #[test]
+    fn test_apply_gravity_moves_fragments_down() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            c.
+            ..
+            i.
+            .r
+            "#,
+        )
+        .unwrap();
+
+        assert!(grid.apply_gravity());
+
+        let expected = Grid::new_from_str(
+            r#"
+            ..
+            ..
+            c.
+            ir
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected.as_chars(), grid.as_chars());
+    }
+
+    #[test]
+    fn test_apply_gravity_preserves_flags_when_nothing_moves() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            ...
+            ccc
+            "#,
+        )
+        .unwrap();
+        grid.recalculate_active_cells();
+        let expected_active = grid.as_active_bitmask();
+
+        assert!(!grid.apply_gravity());
+
+        let actual_active = grid.as_active_bitmask();
+        assert_bitmask_eq!(expected_active, actual_active);
+    }
+
+    #[test]
+    fn test_apply_gravity_matches_full_recalculation() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(0x6775_6e70_6579);
+
+        let mut moved = 0;
+        for _ in 0..1000 {
+            let mut grid = Grid::new(rng.gen_range(1..8), rng.gen_range(2..10));
+            let fill = rng.gen_range(0.2..0.9);
+            for cell in grid.cells.iter_mut() {
+                if rng.gen_bool(0.05) {
+                    *cell = Cell::Blocked;
+                } else if rng.gen_bool(fill) {
+                    *cell = Cell::Filled(rng.gen());
+                }
+            }
+            grid.recalculate_active_cells();
+
+            if grid.apply_gravity() {
+                moved += 1;
+            }
+
+            // Start from a clean slate so no flags survive from before gravity
+            let mut expected = grid.clone();
+            expected.cells.iter_mut().for_each(Cell::deactivate);
+            expected.recalculate_active_cells();
+
+            assert_eq!(expected, grid, "gravity diverged from a full recalculation");
+        }
+        assert!(moved > 500);
+    }
+
+    #[test]
+    fn test_seed_from_patterns() {
+        let mut grid = Grid::new(4, 3);
+
+        grid.seed_from_patterns(&["cccc", ".r.."]).unwrap();
+
+        let expected = Grid::new_from_str(
+            r#"
+            ....
+            .r..
+            cccc
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected.as_chars(), grid.as_chars());
+
+        #[rustfmt::skip]
+        let expected_active: Bitmask = vec![
+            vec![0, 0, 0, 0],
+            vec![0, 0, 0, 0],
+            vec![1, 1, 1, 1],
+        ];
+        let actual_active = grid.as_active_bitmask();
+        assert_bitmask_eq!(expected_active, actual_active);
+    }
+
+    #[test]
+    fn test_seed_from_patterns_rejects_bad_row_length() {
+        let mut grid = Grid::new(4, 3);
+
+        let res = grid.seed_from_patterns(&["cccc", "cc"]);
+
+        assert!(matches!(res, Err(GunpeyLibError::InvalidRowLength(2, 4))));
+        assert_eq!(Grid::new(4, 3), grid);
+    }
+
+    #[test]
+    fn test_seed_from_patterns_rejects_too_many_rows() {
+        let mut grid = Grid::new(2, 2);
+
+        let res = grid.seed_from_patterns(&["cc", "cc", "cc"]);
+
+        assert!(matches!(res, Err(GunpeyLibError::TooManyRows(3, 2))));
+    }
+
+    #[test]
+    fn test_swap_is_noop_two_empties() {
+        let grid = Grid::new_from_str(
+            r#"
+            .c
+            ..
+            "#,
+        )
+        .unwrap();
+
+        assert!(grid.swap_is_noop(gp(0, 0), gp(0, 1)));
+    }
+
+    #[test]
+    fn test_swap_is_noop_two_equal_carets() {
+        let grid = Grid::new_from_str(
+            r#"
+            cc
+            Cc
+            "#,
+        )
+        .unwrap();
+
+        // One of these is active and one isn't but they're still the same fragment
+        assert!(grid.get_cell_at_pos(&gp(0, 0)).unwrap().is_active());
+        assert!(!grid.get_cell_at_pos(&gp(0, 1)).unwrap().is_active());
+        assert!(grid.swap_is_noop(gp(0, 0), gp(0, 1)));
+    }
+
+    #[test]
+    fn test_swap_is_not_noop_two_different_cells() {
+        let grid = Grid::new_from_str(
+            r#"
+            ci
+            ..
+            "#,
+        )
+        .unwrap();
+
+        assert!(!grid.swap_is_noop(gp(0, 1), gp(1, 1)));
+        assert!(!grid.swap_is_noop(gp(0, 0), gp(0, 1)));
+    }
+
+    #[test]
+    fn test_swap_cells_noop_leaves_grid_unchanged() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            c.
+            c.
+            "#,
+        )
+        .unwrap();
+        let expected = grid.clone();
+
+        grid.swap_cells(gp(0, 0), gp(0, 1)).unwrap();
+
+        assert_eq!(expected, grid);
+    }
+
+    #[test]
+    fn test_compact_string_round_trip() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            .rc
+            ri.
+            "#,
+        )
+        .unwrap();
+        grid.recalculate_active_cells();
+
+        let compact = grid.to_compact_string();
+        let actual = Grid::from_compact_string(&compact).unwrap();
+
+        assert_eq!(grid, actual);
+    }
+
+    #[test]
+    fn test_compact_string_starts_with_dimensions() {
+        let grid = Grid::new(5, 10);
+
+        let compact = grid.to_compact_string();
+
+        assert!(compact.starts_with("5x10:"), "got {}", compact);
+        assert!(!compact.contains('\n'));
+    }
+
+    #[test]
+    fn test_from_compact_string_rejects_bad_input() {
+        assert!(Grid::from_compact_string("..../....").is_err());
+        assert!(Grid::from_compact_string("2x3:../..").is_err());
+        assert!(matches!(
+            Grid::from_compact_string("2x2:../..."),
+            Err(GunpeyLibError::InvalidRowLength(3, 2))
+        ));
+        assert!(matches!(
+            Grid::from_compact_string("2x2:zz/.."),
+            Err(GunpeyLibError::InvalidCellChar('z'))
+        ));
+        assert!(matches!(
+            Grid::from_compact_string("2x2:../.∧"),
+            Err(GunpeyLibError::InvalidCellChar('∧'))
+        ));
+    }
+
+    #[test]
+    fn test_clear_active_chains_single_kind() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            ...
+            ccc
+            "#,
+        )
+        .unwrap();
+        grid.recalculate_active_cells();
+
+        let cleared_chains = grid.clear_active_chains();
+
+        assert_eq!(1, cleared_chains.len());
+        assert_eq!(
+            vec![gp(0, 0), gp(1, 0), gp(2, 0)],
+            cleared_chains[0].positions
+        );
+        assert_eq!(1, cleared_chains[0].kinds.len());
+        assert!(cleared_chains[0].is_pure());
+        assert_eq!(Grid::new(3, 2), grid);
+    }
+
+    #[test]
+    fn test_clear_active_chains_mixed_kinds() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            .rc
+            ri.
+            "#,
+        )
+        .unwrap();
+        grid.recalculate_active_cells();
+
+        let cleared_chains = grid.clear_active_chains();
+
+        assert_eq!(1, cleared_chains.len());
+        let expected_kinds: BTreeSet<_> = vec![
+            LineFragmentKind::Caret,
+            LineFragmentKind::InvertedCaret,
+            LineFragmentKind::RightSlash,
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expected_kinds, cleared_chains[0].kinds);
+        assert!(!cleared_chains[0].is_pure());
+
+        // The inactive slash isn't part of the chain so it stays behind
+        let expected = Grid::new_from_str(
+            r#"
+            .r.
+            ...
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected, grid);
+    }
+
+    #[test]
+    fn test_clear_active_chains_reports_each_chain() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            ccc
+            ...
+            ccc
+            "#,
+        )
+        .unwrap();
+        grid.recalculate_active_cells();
+
+        let cleared_chains = grid.clear_active_chains();
+
+        assert_eq!(2, cleared_chains.len());
+        assert_eq!(
+            vec![gp(0, 0), gp(1, 0), gp(2, 0)],
+            cleared_chains[0].positions
+        );
+        assert_eq!(
+            vec![gp(0, 2), gp(1, 2), gp(2, 2)],
+            cleared_chains[1].positions
+        );
+    }
+
+    #[test]
+    fn test_clear_active_chains_does_nothing_without_active_cells() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            .r.
+            ...
+            "#,
+        )
+        .unwrap();
+        let expected = grid.clone();
+
+        let cleared_chains = grid.clear_active_chains();
+
+        assert!(cleared_chains.is_empty());
+        assert_eq!(expected, grid);
+    }
+
+    #[test]
+    fn test_rows_yields_bottom_row_first() {
+        let grid = Grid::new_from_str(
+            r#"
+            ..r
+            cil
+            "#,
+        )
+        .unwrap();
+
+        let rows: Vec<_> = grid.rows().collect();
+
+        assert_eq!(2, rows.len());
+        assert_eq!(
+            vector![
+                Cell::from_str("c"),
+                Cell::from_str("i"),
+                Cell::from_str("l")
+            ],
+            rows[0]
+        );
+        assert_eq!(
+            vector![Cell::Empty, Cell::Empty, Cell::from_str("r")],
+            rows[1]
+        );
+    }
+
+    #[test]
+    fn test_rows_render_order_matches_cell_rows_in_render_order() {
+        let grid = Grid::new_from_str(
+            r#"
+            ..r
+            cil
+            "#,
+        )
+        .unwrap();
+
+        let rows: Vec<Vec<_>> = grid
+            .rows_render_order()
+            .map(|row| row.into_iter().collect())
+            .collect();
+
+        assert_eq!(grid.cell_rows_in_render_order(), rows);
+    }
+
+    #[test]
+    fn test_edit_recalculates_once() {
+        let mut grid = Grid::new(3, 3);
+        let recalculations_before = RECALCULATION_COUNT.with(|count| count.get());
+
+        let res = grid.edit(|editor| {
+            editor.set(gp(0, 0), Cell::from_str("c"));
+            editor.set(gp(1, 0), Cell::from_str("c"));
+            editor.set(gp(2, 1), Cell::from_str("c"));
+            editor.swap(gp(2, 1), gp(2, 0));
+            editor.set(gp(1, 2), Cell::from_str("r"));
+        });
+
+        assert!(res.is_ok());
+        assert_eq!(
+            1,
+            RECALCULATION_COUNT.with(|count| count.get()) - recalculations_before
+        );
+        let expected = Grid::new_from_str(
+            r#"
+            .r.
+            ...
+            CCC
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected, grid);
+    }
+
+    #[test]
+    fn test_edit_cant_swap_blocked_cells() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            #.
+            cc
+            "#,
+        )
+        .unwrap();
+        let original = grid.clone();
+
+        let errors = grid
+            .edit(|editor| editor.swap(gp(0, 1), gp(1, 1)))
+            .unwrap_err();
+
+        assert_eq!(1, errors.len());
+        assert!(matches!(
+            errors[0],
+            GunpeyLibError::CantSwapBlocked(pos) if pos == gp(0, 1)
+        ));
+        assert_eq!(original.recalculated(), grid);
+    }
+
+    #[test]
+    fn test_edit_accumulates_errors_and_keeps_valid_edits() {
+        let mut grid = Grid::new(3, 2);
+
+        let errors = grid
+            .edit(|editor| {
+                editor.set(gp(3, 0), Cell::from_str("c"));
+                editor.set(gp(0, 0), Cell::from_str("c"));
+                editor.clear(gp(0, -1));
+                editor.swap(gp(0, 0), gp(0, 0));
+                editor.swap(gp(0, 0), gp(0, 2));
+            })
+            .unwrap_err();
+
+        assert_eq!(4, errors.len());
+        assert!(matches!(errors[0], GunpeyLibError::OutOfBounds { .. }));
+        assert!(matches!(errors[1], GunpeyLibError::OutOfBounds { .. }));
+        assert!(matches!(
+            errors[2],
+            GunpeyLibError::CantSwapSamePositon(_, _)
+        ));
+        assert!(matches!(
+            errors[3],
+            GunpeyLibError::CantSwapBadPosition { .. }
+        ));
+        let expected = Grid::new_from_str(
+            r#"
+            ...
+            c..
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected, grid);
+    }
+
+    #[test]
+    fn test_wild_bridges_incompatible_fragments() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            ...
+            cwi
+            "#,
+        )
+        .unwrap();
+        grid.recalculate_active_cells();
+
+        let expected = Grid::new_from_str(
+            r#"
+            ...
+            CWI
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected, grid);
+        assert_eq!(1, grid.active_chains().len());
+    }
+
+    #[test]
+    fn test_fragments_dont_connect_without_wild() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            ...
+            cci
+            "#,
+        )
+        .unwrap();
+        grid.recalculate_active_cells();
+
+        let expected = Grid::new_from_str(
+            r#"
+            ...
+            cci
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected, grid);
+    }
+
+    #[test]
+    fn test_wild_dead_end_stays_inactive() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            .w.
+            ccc
+            "#,
+        )
+        .unwrap();
+        grid.recalculate_active_cells();
+
+        let expected = Grid::new_from_str(
+            r#"
+            .w.
+            CCC
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected, grid);
+    }
+
+    #[test]
+    fn test_shift_column_up_wraps_top_cell_to_bottom() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            r..
+            i..
+            c..
+            "#,
+        )
+        .unwrap();
+
+        grid.shift_column(0, 1).unwrap();
+
+        let expected = Grid::new_from_str(
+            r#"
+            i..
+            c..
+            r..
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected, grid);
+    }
+
+    #[test]
+    fn test_shift_column_down_and_recalculate() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            .c.
+            c.c
+            "#,
+        )
+        .unwrap();
+
+        grid.shift_column(1, -1).unwrap();
+
+        let expected = Grid::new_from_str(
+            r#"
+            ...
+            CCC
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected, grid);
+    }
+
+    #[test]
+    fn test_shift_column_out_of_bounds() {
+        let mut grid = Grid::new(3, 2);
+
+        assert!(matches!(
+            grid.shift_column(3, 1),
+            Err(GunpeyLibError::ColumnOutOfBounds { x: 3, width: 3 })
+        ));
+    }
+
+    #[test]
+    fn test_from_annotated_str_ignores_comments() {
+        let grid = Grid::from_annotated_str(
+            r#"
+            # A caret line with a slash waiting above it
+            .r. # the slash can't reach either wall
+            # the bottom row spans the whole grid
+            ccc # complete line
+            "#,
+        )
+        .unwrap();
+
+        let expected = Grid::new_from_str(
+            r#"
+            .r.
+            ccc
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected, grid);
+    }
+
+    #[test]
+    fn test_apply_gravity_after_clear_falls_into_cleared_cells() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            ri.
+            ccc
+            .l.
+            "#,
+        )
+        .unwrap();
+        grid.recalculate_active_cells();
+
+        grid.clear_active_chains();
+        assert!(grid.apply_gravity());
+
+        let expected = Grid::new_from_str(
+            r#"
+            ...
+            .i.
+            rl.
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected, grid);
+    }
+
+    #[test]
+    fn test_active_chain_size_histogram() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            cc
+            ..
+            lr
+            rl
+            cc
+            "#,
+        )
+        .unwrap();
+        grid.recalculate_active_cells();
+
+        let expected: BTreeMap<_, _> = vec![(2, 2), (4, 1)].into_iter().collect();
+        assert_eq!(expected, grid.active_chain_size_histogram());
+    }
+
+    #[test]
+    fn test_component_spans_edges() {
+        let grid = Grid::new(3, 2);
+
+        assert!(grid.component_spans_edges(&[gp(0, 0), gp(1, 0), gp(2, 1)]));
+        assert!(!grid.component_spans_edges(&[gp(0, 0), gp(1, 0), gp(1, 1)]));
+        assert!(!grid.component_spans_edges(&[gp(1, 0), gp(2, 0)]));
+        assert!(!grid.component_spans_edges(&[]));
+    }
+
+    #[test]
+    fn test_to_ascii_art() {
+        let grid = Grid::new_from_str(
+            r#"
+            .ri
+            cl.
+            "#,
+        )
+        .unwrap();
+
+        let expected = "\
+┌─┬─┬─┐
+│ │/│∨│
+├─┼─┼─┤
+│∧│\\│ │
+└─┴─┴─┘
+";
+        assert_eq!(expected, grid.to_ascii_art());
+    }
+
+    #[test]
+    fn test_to_ascii_art_dimensions() {
+        let grid = Grid::new(5, 10);
+        let art = grid.to_ascii_art();
+        let lines: Vec<_> = art.lines().collect();
+
+        // A border above, below and between every row
+        assert_eq!(2 * 10 + 1, lines.len());
+        // A border to the left, right and between every column
+        assert!(lines.iter().all(|line| line.chars().count() == 2 * 5 + 1));
+    }
+
+    #[test]
+    fn test_legal_swaps_skip_noops() {
+        let grid = Grid::new_from_str(
+            r#"
+            c..
+            c.r
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(vec![(gp(2, 0), gp(2, 1))], grid.legal_swaps());
+        assert!(Grid::new(5, 10).legal_swaps().is_empty());
+    }
+
+    #[test]
+    fn test_legal_swaps_on_a_5x10_grid() {
+        let mut grid = Grid::new(5, 10);
+        for y in 0..10 {
+            let kind = if y % 2 == 0 { "c" } else { "i" };
+            for x in 0..5 {
+                grid.set_cell(&gp(x, y), Cell::from_str(kind)).unwrap();
+            }
+        }
+
+        // Every cell below the top row paired with the cell above it
+        let swaps = grid.legal_swaps();
+        assert_eq!(45, swaps.len());
+        assert_eq!((gp(0, 0), gp(0, 1)), swaps[0]);
+        assert_eq!((gp(4, 8), gp(4, 9)), swaps[44]);
+    }
+
+    #[test]
+    fn test_swap_partner() {
+        let grid = Grid::new(5, 10);
+
+        assert_eq!(Some(gp(0, 1)), grid.swap_partner(gp(0, 0)));
+        assert_eq!(Some(gp(3, 9)), grid.swap_partner(gp(3, 8)));
+        // The top row swaps with the row below it instead
+        assert_eq!(Some(gp(3, 8)), grid.swap_partner(gp(3, 9)));
+        assert_eq!(None, grid.swap_partner(gp(5, 0)));
+        assert_eq!(None, grid.swap_partner(gp(0, 10)));
+        assert_eq!(None, grid.swap_partner(gp(0, -1)));
+    }
+
+    #[test]
+    fn test_random_legal_swap() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let mut grid = Grid::new_from_str(
+            r#"
+            ic.
+            cir
+            "#,
+        )
+        .unwrap();
+        let mut rng = StdRng::seed_from_u64(7);
+
+        let swapped = grid.random_legal_swap(&mut rng);
+
+        assert_eq!(Some((gp(1, 0), gp(1, 1))), swapped);
+        let expected = Grid::new_from_str(
+            r#"
+            ii.
+            CCR
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected, grid);
+    }
+
+    #[test]
+    fn test_random_legal_swap_without_legal_swaps() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let mut grid = Grid::new(3, 2);
+        let mut rng = StdRng::seed_from_u64(7);
+
+        assert_eq!(None, grid.random_legal_swap(&mut rng));
+    }
+
+    #[test]
+    fn test_kind_entropy_of_single_kind_board() {
+        let grid = Grid::new_from_str(
+            r#"
+            c.c
+            ccc
+            "#,
+        )
+        .unwrap();
+
+        assert!(grid.kind_entropy().abs() < 0.0001);
+        assert!(Grid::new(3, 2).kind_entropy().abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_kind_entropy_of_even_board() {
+        let grid = Grid::new_from_str(
+            r#"
+            lr..
+            cilr
+            ci..
+            "#,
+        )
+        .unwrap();
+
+        assert!((grid.kind_entropy() - 2.0).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_swap_cells_rolls_back_when_recalculation_fails() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            i..
+            ccc
+            "#,
+        )
+        .unwrap();
+        grid.recalculate_active_cells();
+        // Leave the grid with a stray cell so recalculation refuses to run
+        grid.cells.push_back(Cell::Empty);
+        let expected = grid.clone();
+
+        let res = grid.swap_cells(gp(0, 0), gp(0, 1));
+
+        assert!(matches!(
+            res,
+            Err(GunpeyLibError::CellCountMismatch { cells: 7, .. })
+        ));
+        assert_eq!(expected, grid);
+    }
+
+    #[test]
+    fn test_compact_all() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            c.i
+            ..r
+            .c.
+            c..
+            "#,
+        )
+        .unwrap();
+
+        grid.compact_all();
+
+        let expected = Grid::new_from_str(
+            r#"
+            ...
+            ...
+            c.i
+            CCR
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected, grid);
+    }
+
+    #[test]
+    fn test_complete_spans_single_span() {
+        let grid = Grid::new_from_str(
+            r#"
+            .r.
+            ccc
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![vec![gp(0, 0), gp(1, 0), gp(2, 0)]],
+            grid.complete_spans()
+        );
+    }
+
+    #[test]
+    fn test_complete_spans_branch() {
+        let grid = Grid::new_from_str(
+            r#"
+            ...
+            ccc
+            ..i
+            "#,
+        )
+        .unwrap();
+
+        let expected = vec![
+            vec![gp(0, 1), gp(1, 1), gp(2, 0)],
+            vec![gp(0, 1), gp(1, 1), gp(2, 1)],
+        ];
+        assert_eq!(expected, grid.complete_spans());
+    }
+
+    #[test]
+    fn test_complete_spans_is_capped() {
+        let mut grid = Grid::new(5, 10);
+        grid.edit(|editor| {
+            for (x, y) in new_xy_iter(
+                5,
+                10,
+                GridIterDirectionX::LeftToRight,
+                GridIterDirectionY::BottomToTop,
+            ) {
+                editor.set(gp(x as isize, y as isize), Cell::from_str("w"));
+            }
+        })
+        .unwrap();
+
+        assert_eq!(MAX_COMPLETE_SPANS, grid.complete_spans().len());
+    }
+
+    #[test]
+    fn test_complete_spans_gives_up_quickly_without_spans() {
+        use std::time::{Duration, Instant};
+
+        // Wild fragments everywhere but the right column, so every corner connects to every
+        // other one and none of the paths finish. Searching every path takes minutes.
+        let wild_board = |right_column: &str| {
+            let mut grid = Grid::new(6, 5);
+            grid.edit(|editor| {
+                for (x, y) in new_xy_iter(
+                    6,
+                    5,
+                    GridIterDirectionX::LeftToRight,
+                    GridIterDirectionY::BottomToTop,
+                ) {
+                    let cell = if x == 5 { right_column } else { "w" };
+                    editor.set(gp(x as isize, y as isize), Cell::from_str(cell));
+                }
+            })
+            .unwrap();
+            grid
+        };
+
+        let start = Instant::now();
+        assert!(wild_board(".").complete_spans().is_empty());
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        // A single way out of the right side leaves every corner able to reach it
+        let mut grid = wild_board(".");
+        grid.set_cell(&gp(5, 4), Cell::from_str("w")).unwrap();
+        let start = Instant::now();
+        let spans = grid.complete_spans();
+        assert!(!spans.is_empty() && spans.len() <= MAX_COMPLETE_SPANS);
+        assert!(spans.iter().all(|span| span.last() == Some(&gp(5, 4))));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_swap_if_improving_keeps_improving_swap() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            .c.
+            c.c
+            "#,
+        )
+        .unwrap();
+
+        assert!(grid.swap_if_improving(gp(1, 0), gp(1, 1)));
+
+        let expected = Grid::new_from_str(
+            r#"
+            ...
+            CCC
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected, grid);
+    }
+
+    #[test]
+    fn test_swap_if_improving_reverts_neutral_swap() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            .r.
+            CCC
+            "#,
+        )
+        .unwrap();
+        let expected = grid.clone();
+
+        assert!(!grid.swap_if_improving(gp(1, 1), gp(2, 1)));
+        assert_eq!(expected, grid);
+    }
+
+    #[test]
+    fn test_swap_cells_rejects_active_cells_when_protected() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            .r.
+            ccc
+            "#,
+        )
+        .unwrap();
+        grid.recalculate_active_cells();
+        grid.protect_active = true;
+        let expected = grid.clone();
+
+        assert!(matches!(
+            grid.swap_cells(gp(1, 0), gp(1, 1)),
+            Err(GunpeyLibError::SwapProtectedActive(_, _))
+        ));
+        assert_eq!(expected, grid);
+
+        grid.swap_cells(gp(0, 1), gp(1, 1)).unwrap();
+    }
+
+    #[test]
+    fn test_swap_cells_moves_active_cells_when_unprotected() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            .r.
+            ccc
+            "#,
+        )
+        .unwrap();
+        grid.recalculate_active_cells();
+        assert!(!grid.protect_active);
+
+        grid.swap_cells(gp(1, 0), gp(1, 1)).unwrap();
+
+        let expected = Grid::new_from_str(
+            r#"
+            .c.
+            crc
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected, grid);
+    }
+
+    #[test]
+    fn test_row_is_empty_and_row_is_full() {
+        let grid = Grid::new_from_str(
+            r#"
+            ...
+            .r.
+            cil
+            "#,
+        )
+        .unwrap();
+
+        assert!(grid.row_is_full(0));
+        assert!(!grid.row_is_empty(0));
+        assert!(!grid.row_is_full(1));
+        assert!(!grid.row_is_empty(1));
+        assert!(grid.row_is_empty(2));
+        assert!(!grid.row_is_full(2));
+        assert!(!grid.row_is_empty(3));
+        assert!(!grid.row_is_full(3));
+    }
+
+    #[test]
+    fn test_row_with_blocked_cells_is_not_full() {
+        let grid = Grid::new_from_str(
+            r#"
+            ###
+            #c#
+            "#,
+        )
+        .unwrap();
+
+        assert!(!grid.row_is_full(0));
+        assert!(!grid.row_is_empty(0));
+        assert!((grid.row_fill_ratio(0) - 1.0 / 3.0).abs() < f32::EPSILON);
+        assert!(!grid.row_is_full(1));
+        assert!(!grid.row_is_empty(1));
+    }
+
+    #[test]
+    fn test_peek_discarded_on_cycle() {
+        let grid = Grid::new_from_str(
+            r#"
+            .cc
+            ...
+            ...
+            "#,
+        )
+        .unwrap();
+        let expected_grid = grid.clone();
+
+        let discarded = grid.peek_discarded_on_cycle();
+
+        assert_eq!(
+            vector![Cell::Empty, Cell::from_str("c"), Cell::from_str("c")],
+            discarded
+        );
+        assert_eq!(expected_grid, grid);
+        assert_eq!(grid.clone().pop_top_row(), discarded);
+    }
+
+    #[test]
+    fn test_apply_gravity_with_moves_drop_distance() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            c..
+            ..r
+            .i.
+            ..l
+            "#,
+        )
+        .unwrap();
+
+        let moves = grid.apply_gravity_with_moves();
+
+        assert_eq!(
+            vec![
+                (gp(0, 3), gp(0, 0)),
+                (gp(1, 1), gp(1, 0)),
+                (gp(2, 2), gp(2, 1))
+            ],
+            moves
+        );
+        // 3 rows for the caret, 1 for the inverted caret and 1 for the slash on top
+        assert_eq!(5, drop_distance(&moves));
+        assert!(grid.apply_gravity_with_moves().is_empty());
+    }
+
+    #[test]
+    fn test_neighbors_where() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            c.r
+            cic
+            ccc
+            "#,
+        )
+        .unwrap();
+        grid.recalculate_active_cells();
+
+        let mut carets = grid.neighbors_where(gp(1, 1), |_, cell| {
+            cell.kind() == Some(LineFragmentKind::Caret)
+        });
+        carets.sort();
+        assert_eq!(
+            vec![gp(0, 0), gp(1, 0), gp(2, 0), gp(0, 1), gp(2, 1), gp(0, 2)],
+            carets
+        );
+
+        let mut active = grid.neighbors_where(gp(1, 1), |_, cell| cell.is_active());
+        active.sort();
+        assert_eq!(
+            vec![gp(0, 0), gp(1, 0), gp(2, 0), gp(0, 2), gp(2, 2)],
+            active
+        );
+
+        let above = grid.neighbors_where(gp(1, 1), |adjacency, cell| {
+            adjacency == Adjacency::AboveRight && !cell.is_empty()
+        });
+        assert_eq!(vec![gp(2, 2)], above);
+    }
+
+    #[test]
+    fn test_swap_xy_matches_swap_cells() {
+        let grid = Grid::new_from_str(
+            r#"
+            .c.
+            c.c
+            "#,
+        )
+        .unwrap();
+
+        let mut swapped_xy = grid.clone();
+        let mut swapped_gp = grid.clone();
+        swapped_xy.swap_xy(1, 0, 1, 1).unwrap();
+        swapped_gp.swap_cells(gp(1, 0), gp(1, 1)).unwrap();
+        assert_eq!(swapped_gp, swapped_xy);
+
+        let mut swapped_xy = grid.clone();
+        let res = swapped_xy.swap_xy(1, 1, 1, 2);
+        assert!(matches!(
+            res,
+            Err(GunpeyLibError::CantSwapBadPosition { .. })
+        ));
+        assert_eq!(grid, swapped_xy);
+    }
+
+    #[test]
+    fn test_recalculated() {
+        // new_from_str doesn't recalculate so nothing starts out active
+        let grid = Grid::new_from_str(
+            r#"
+            .r.
+            ccc
+            "#,
+        )
+        .unwrap();
+
+        let recalculated = grid.recalculated();
+
+        let expected = Grid::new_from_str(
+            r#"
+            .r.
+            CCC
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected, recalculated);
+        assert_eq!(0, grid.count_active_cells());
+    }
+
+    #[test]
+    fn test_changed_positions() {
+        let old = Grid::new_from_str(
+            r#"
+            .r.
+            c.c
+            "#,
+        )
+        .unwrap();
+        let mut new = old.clone();
+        new.set_cell(&gp(2, 1), Cell::from_str("i")).unwrap();
+
+        assert_eq!(vec![gp(2, 1)], Grid::changed_positions(&old, &new));
+        assert!(Grid::changed_positions(&old, &old).is_empty());
+        assert_eq!(6, Grid::changed_positions(&Grid::new(2, 2), &old).len());
+    }
+
+    #[test]
+    fn test_changed_positions_includes_activation() {
+        let old = Grid::new_from_str(
+            r#"
+            ...
+            ccc
+            "#,
+        )
+        .unwrap();
+        let new = old.recalculated();
+
+        assert_eq!(
+            vec![gp(0, 0), gp(1, 0), gp(2, 0)],
+            Grid::changed_positions(&old, &new)
+        );
+    }
+
+    #[test]
+    fn test_swap_cells_on_two_wide_grid_recalculates() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            ..
+            rc
+            "#,
+        )
+        .unwrap();
+        grid.recalculate_active_cells();
+
+        // The swapped pair has no other neighbors but is a whole line on its own
+        grid.swap_cells(gp(0, 0), gp(1, 0)).unwrap();
+
+        assert_eq!(grid.clone().recalculated(), grid);
+        assert_eq!(2, grid.count_active_cells());
+    }
+
+    #[test]
+    fn test_swap_cells_skips_recalculation_for_isolated_cells() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            ....
+            ...r
+            ....
+            CCCC
+            "#,
+        )
+        .unwrap();
+        let recalculations_before = RECALCULATION_COUNT.with(|count| count.get());
+
+        // The slash has no neighbors and moving it up doesn't give it any
+        grid.swap_cells(gp(3, 2), gp(3, 3)).unwrap();
+        // Two empty cells
+        grid.swap_cells(gp(0, 2), gp(0, 3)).unwrap();
+        // Two identical cells
+        grid.swap_cells(gp(0, 0), gp(1, 0)).unwrap();
+
+        assert_eq!(
+            0,
+            RECALCULATION_COUNT.with(|count| count.get()) - recalculations_before
+        );
+        let expected = Grid::new_from_str(
+            r#"
+            ...r
+            ....
+            ....
+            CCCC
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected, grid);
+        assert_bitmask_eq!(
+            grid.recalculated().as_active_bitmask(),
+            grid.as_active_bitmask()
+        );
+    }
+
+    #[test]
+    fn test_swap_cells_recalculates_when_cells_have_neighbors() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            ....
+            ...r
+            ....
+            CCCC
+            "#,
+        )
+        .unwrap();
+        let recalculations_before = RECALCULATION_COUNT.with(|count| count.get());
+
+        // Moving the slash down puts it next to the carets
+        grid.swap_cells(gp(3, 2), gp(3, 1)).unwrap();
+
+        assert_eq!(
+            1,
+            RECALCULATION_COUNT.with(|count| count.get()) - recalculations_before
+        );
+        assert_bitmask_eq!(
+            grid.recalculated().as_active_bitmask(),
+            grid.as_active_bitmask()
+        );
+    }
+
+    #[test]
+    fn test_swap_cells_recalculates_on_one_cell_wide_grids() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            c
+            .
+            "#,
+        )
+        .unwrap();
+
+        grid.swap_cells(gp(0, 0), gp(0, 1)).unwrap();
+
+        let expected = Grid::new_from_str(
+            r#"
+            .
+            C
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected, grid);
+    }
+
+    #[test]
+    fn test_pretty_diff_marks_mismatches() {
+        let expected = vec![vec![0, 0, 0], vec![1, 1, 1]];
+        let actual = vec![vec![0, 1, 0], vec![1, 1, 0]];
+
+        let diff = pretty_diff(&expected, &actual);
+        let lines: Vec<_> = diff.lines().collect();
+
+        assert_eq!("expected | actual   | mismatches", lines[0]);
+        assert_eq!("000      | 010      | .x.", lines[1]);
+        assert_eq!("111      | 110      | ..x", lines[2]);
+    }
+
+    #[test]
+    fn test_pretty_diff_marks_missing_rows() {
+        let expected = vec![vec![0, 1], vec![1, 1]];
+        let actual = vec![vec![0, 1]];
+
+        let diff = pretty_diff(&expected, &actual);
+
+        assert_eq!(Some("11       |          | xx"), diff.lines().nth(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "bitmasks differ")]
+    fn test_assert_bitmask_eq_panics_on_mismatch() {
+        assert_bitmask_eq!(vec![vec![0, 1]], vec![vec![1, 1]]);
+    }
+
+    #[test]
+    fn test_apply_gravity_is_idempotent_on_settled_board() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            ...
+            .i.
+            crl
+            "#,
+        )
+        .unwrap();
+        grid.recalculate_active_cells();
+        let expected = grid.clone();
+
+        assert!(!grid.apply_gravity());
+        assert!(grid.apply_gravity_with_moves().is_empty());
+        assert_eq!(expected, grid);
+    }
+
+    #[test]
+    fn test_apply_gravity_settles_in_one_call() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            c.r
+            ..i
+            .l.
+            c..
+            "#,
+        )
+        .unwrap();
+
+        assert!(!grid.apply_gravity_with_moves().is_empty());
+        let settled = grid.clone();
+
+        assert!(grid.apply_gravity_with_moves().is_empty());
+        assert!(!grid.apply_gravity());
+        assert_eq!(settled, grid);
+    }
+
+    #[test]
+    fn test_swap_cells_same_position_error() {
+        let mut grid = new_2x2_grid();
+
+        assert!(matches!(
+            grid.swap_cells(gp(1, 1), gp(1, 1)),
+            Err(GunpeyLibError::CantSwapSamePositon(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_swap_cells_by_index_same_index_error() {
+        let mut grid = new_2x2_grid();
+
+        assert!(matches!(
+            grid.swap_cells_by_index(3, 3),
+            Err(GunpeyLibError::CantSwapSameIndex(3, 3))
+        ));
+    }
+
+    #[test]
+    fn test_swap_cells_past_end_of_row_is_a_position_error() {
+        let mut grid = new_2x2_grid();
+
+        // (2, 0) used to wrap around to the same index as (0, 1)
+        assert!(matches!(
+            grid.swap_cells(gp(2, 0), gp(0, 1)),
+            Err(GunpeyLibError::CantSwapBadPosition { .. })
+        ));
+        assert_eq!(None, grid.get_index_from_pos(&gp(2, 0)));
+        assert_eq!(None, grid.get_cell_at_pos(&gp(-1, 1)));
+    }
+
+    #[test]
+    fn test_clear_active_chains_with_row_bonus() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            .i..
+            cccc
+            "#,
+        )
+        .unwrap();
+        grid.recalculate_active_cells();
+
+        let summary = grid.clear_active_chains_with_row_bonus();
+
+        assert_eq!(vec![0], summary.full_rows);
+        assert_eq!(
+            vec![gp(0, 0), gp(1, 0), gp(2, 0), gp(3, 0)],
+            summary.bonus_cells
+        );
+        assert_eq!(1, summary.chains.len());
+        assert_eq!(4, summary.chains[0].positions.len());
+        let expected = Grid::new_from_str(
+            r#"
+            .i..
+            ....
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected, grid);
+    }
+
+    #[test]
+    fn test_clear_active_chains_with_row_bonus_overlapping_chains() {
+        // A single chain fills both rows, so both rows pay out for the same chain
+        let mut grid = Grid::new_from_str(
+            r#"
+            cccc
+            iiii
+            "#,
+        )
+        .unwrap();
+        grid.recalculate_active_cells();
+
+        let summary = grid.clear_active_chains_with_row_bonus();
+
+        assert_eq!(vec![0, 1], summary.full_rows);
+        let unique: BTreeSet<_> = summary.bonus_cells.iter().copied().collect();
+        assert_eq!(8, summary.bonus_cells.len());
+        assert_eq!(unique.into_iter().collect::<Vec<_>>(), summary.bonus_cells);
+        assert_eq!(1, summary.chains.len());
+        assert_eq!(8, summary.chains[0].positions.len());
+        assert_eq!(Grid::new(4, 2), grid);
+    }
+
+    #[test]
+    fn test_clear_active_chains_with_row_bonus_partial_row() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            .cc.
+            r..l
+            "#,
+        )
+        .unwrap();
+        grid.recalculate_active_cells();
+
+        let summary = grid.clear_active_chains_with_row_bonus();
+
+        assert!(summary.full_rows.is_empty());
+        assert!(summary.bonus_cells.is_empty());
+        assert_eq!(1, summary.chains.len());
+        assert_eq!(Grid::new(4, 2), grid);
+    }
+
+    /// Stub sprites: a solid block per kind of cell, brighter when active
+    fn stub_sprites(cell_px: usize) -> Vec<(Cell, Vec<u8>)> {
+        [".", "c", "C", "i", "I", "l", "L", "r", "R"]
+            .iter()
+            .enumerate()
+            .map(|(i, cell_str)| {
+                let shade = (i * 25) as u8;
+                let sprite = [shade, shade, 255 - shade, 255].repeat(cell_px * cell_px);
+                (Cell::from_str(cell_str), sprite)
+            })
+            .collect()
+    }
+
+    /// Draw a grid with the stub sprites, top row first
+    fn render_rgba(grid: &Grid, cell_px: usize, sprites: &[(Cell, Vec<u8>)]) -> Vec<u8> {
+        let mut pixels = Vec::new();
+        for row in grid.rows_render_order() {
+            for sprite_row in 0..cell_px {
+                for cell in row.iter() {
+                    let (_, sprite) = sprites.iter().find(|(c, _)| c == cell).unwrap();
+                    let row_len = cell_px * 4;
+                    pixels.extend_from_slice(
+                        &sprite[sprite_row * row_len..(sprite_row + 1) * row_len],
+                    );
+                }
+            }
+        }
+
+        pixels
+    }
+
+    #[test]
+    fn test_from_rgba_round_trip() {
+        let grid = Grid::new_from_str(
+            r#"
+            i..l
+            .r..
+            cccc
+            "#,
+        )
+        .unwrap()
+        .recalculated();
+        let cell_px = 3;
+        let sprites = stub_sprites(cell_px);
+        let mut pixels = render_rgba(&grid, cell_px, &sprites);
+        // A little noise shouldn't change which sprite is closest
+        for byte in pixels.iter_mut().step_by(7) {
+            *byte = byte.saturating_add(3);
+        }
+
+        let sprite_refs: Vec<_> = sprites
+            .iter()
+            .map(|(cell, sprite)| (*cell, sprite.as_slice()))
+            .collect();
+        let decoded = Grid::from_rgba(4, 3, cell_px, &pixels, &sprite_refs).unwrap();
+
+        assert_eq!(grid, decoded);
+    }
+
+    #[test]
+    fn test_from_rgba_rejects_bad_sizes() {
+        let sprites = stub_sprites(2);
+        let sprite_refs: Vec<_> = sprites
+            .iter()
+            .map(|(cell, sprite)| (*cell, sprite.as_slice()))
+            .collect();
+
+        assert!(matches!(
+            Grid::from_rgba(2, 2, 2, &[0; 10], &sprite_refs),
+            Err(GunpeyLibError::InvalidImageSize {
+                expected: 64,
+                actual: 10
+            })
+        ));
+        assert!(matches!(
+            Grid::from_rgba(2, 2, 2, &[0; 64], &[]),
+            Err(GunpeyLibError::NoSprites)
+        ));
+        assert!(matches!(
+            Grid::from_rgba(2, 2, 2, &[0; 64], &[(Cell::Empty, &[0; 4])]),
+            Err(GunpeyLibError::InvalidImageSize {
+                expected: 16,
+                actual: 4
+            })
+        ));
+    }
+
+    #[test]
+    fn test_swap_with_token_then_revert() {
+        let original = Grid::new_from_str(
+            r#"
+            i..l
+            .r.c
+            crcc
+            "#,
+        )
+        .unwrap()
+        .recalculated();
+        let mut grid = original.clone();
+        let swaps = [
+            (gp(1, 0), gp(1, 1)),
+            (gp(0, 0), gp(0, 1)),
+            (gp(3, 1), gp(3, 2)),
+            (gp(2, 0), gp(2, 1)),
+        ];
+
+        let mut tokens = Vec::new();
+        for (a, b) in swaps {
+            tokens.push(grid.swap_with_token(a, b).unwrap());
+        }
+        assert_ne!(original, grid);
+
+        while let Some(token) = tokens.pop() {
+            grid.revert(token);
+        }
+
+        assert_eq!(original, grid);
+        assert_eq!(original.to_compact_string(), grid.to_compact_string());
+    }
+
+    #[test]
+    fn test_swap_with_token_then_revert_restores_active_flags() {
+        let original = Grid::new_from_str(
+            r#"
+            ....
+            cccc
+            "#,
+        )
+        .unwrap()
+        .recalculated();
+        let mut grid = original.clone();
+
+        let token = grid.swap_with_token(gp(1, 0), gp(1, 1)).unwrap();
+        assert_eq!(grid.count_active_cells(), 0);
+
+        grid.revert(token);
+        assert_eq!(original, grid);
+        assert_eq!(grid.count_active_cells(), 4);
+    }
+
+    #[test]
+    fn test_swap_with_token_only_remembers_the_touched_chain() {
+        let original = Grid::new_from_str(
+            r#"
+            cccc
+            ....
+            ....
+            c.c.
+            "#,
+        )
+        .unwrap()
+        .recalculated();
+        assert_eq!(original.count_active_cells(), 4);
+        let mut grid = original.clone();
+
+        let token = grid.swap_with_token(gp(0, 0), gp(0, 1)).unwrap();
+        let remembered: Vec<GridPos> = token
+            .prior_active
+            .iter()
+            .map(|(index, _)| grid.get_pos_from_index(*index))
+            .collect();
+        assert!(remembered.contains(&gp(0, 0)));
+        assert!(remembered.contains(&gp(0, 1)));
+        assert!(remembered.iter().all(|pos| pos.y < 3));
+
+        grid.revert(token);
+        assert_eq!(original, grid);
+    }
+
+    #[test]
+    fn test_active_count_in() {
+        let grid = Grid::new_from_str(
+            r#"
+            C..C
+            .CC.
+            CCCC
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(grid.active_count_in(1, 0, 2, 2), 4);
+        assert_eq!(grid.active_count_in(0, 2, 4, 1), 2);
+        assert_eq!(grid.active_count_in(1, 2, 2, 1), 0);
+    }
+
+    #[test]
+    fn test_active_count_in_clamps_to_grid() {
+        let grid = Grid::new_from_str(
+            r#"
+            C..C
+            .CC.
+            CCCC
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(grid.active_count_in(0, 0, 100, 100), 8);
+        assert_eq!(grid.active_count_in(3, 1, 5, 5), 1);
+        assert_eq!(grid.active_count_in(10, 10, 2, 2), 0);
+        assert_eq!(grid.active_count_in(usize::MAX, 0, usize::MAX, 1), 0);
+    }
+
+    #[test]
+    fn test_try_from_str() {
+        let grid_str = r#"
+            .c.
+            r.I
+            "#;
+
+        assert_eq!(
+            Grid::try_from_str(grid_str).unwrap(),
+            Grid::new_from_str(grid_str).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_try_from_str_rejects_symbols() {
+        let err = Grid::try_from_str(
+            r#"
+            .c.
+            r/i
+            "#,
+        )
+        .unwrap_err();
+
+        assert!(matches!(
+            err,
+            GunpeyLibError::WrongGrammar {
+                character: '/',
+                row: 1,
+                col: 1,
+                grammar: Grammar::Letters,
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "'/' at row 1, column 1 is a symbol but this grid is written with letters"
+        );
+    }
+
+    #[test]
+    fn test_try_from_symbols_rejects_letters() {
+        let err = Grid::try_from_symbols(
+            r#"
+            ∧.∨
+            /c\
+            "#,
+        )
+        .unwrap_err();
+
+        assert!(matches!(
+            err,
+            GunpeyLibError::WrongGrammar {
+                character: 'c',
+                row: 1,
+                col: 1,
+                grammar: Grammar::Symbols,
+            }
+        ));
+    }
+
+    #[test]
+    fn test_try_from_str_rejects_unknown_characters() {
+        let err = Grid::try_from_str(
+            r#"
+            .c.
+            r.x
+            "#,
+        )
+        .unwrap_err();
+
+        assert!(matches!(
+            err,
+            GunpeyLibError::InvalidCharacter {
+                character: 'x',
+                row: 1,
+                col: 2,
+                grammar: Grammar::Letters,
+            }
+        ));
+        assert!(matches!(
+            Grid::try_from_str(".c.\nr."),
+            Err(GunpeyLibError::InvalidRowLength(2, 3))
+        ));
+    }
+
+    #[test]
+    fn test_try_from_str_rejects_too_small_grids() {
+        assert!(matches!(
+            Grid::try_from_str(""),
+            Err(GunpeyLibError::InvalidDimensions {
+                width: 0,
+                height: 0
+            })
+        ));
+        assert!(matches!(
+            Grid::try_from_str("ccc"),
+            Err(GunpeyLibError::InvalidDimensions {
+                width: 3,
+                height: 1
+            })
+        ));
+        assert!(matches!(
+            Grid::try_from_symbols("∧∧"),
+            Err(GunpeyLibError::InvalidDimensions {
+                width: 2,
+                height: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn test_auto_clear_on_recalc() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            .r..
+            .c..
+            c.cc
+            "#,
+        )
+        .unwrap();
+        grid.auto_clear_on_recalc = true;
+        grid.recalculate_active_cells();
+
+        // The bottom row isn't complete until the gap is filled
+        assert_eq!(grid.count_active_cells(), 0);
+        assert_eq!(grid.to_compact_string(), "4x3:.r../.c../c.cc");
+
+        grid.swap_cells(gp(1, 0), gp(1, 1)).unwrap();
+
+        // After the swap the bottom row clears, the r falls into the gap and nothing is left active
+        assert_eq!(grid.count_active_cells(), 0);
+        assert_eq!(grid.to_compact_string(), "4x3:..../..../.r..");
+    }
+
+    #[test]
+    fn test_auto_clear_on_recalc_is_off_by_default() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            ....
+            cccc
+            "#,
+        )
+        .unwrap();
+        grid.recalculate_active_cells();
+
+        assert!(!grid.auto_clear_on_recalc);
+        assert_eq!(grid.count_active_cells(), 4);
+    }
+
+    #[test]
+    fn test_clear_active_cells() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            .r..
+            cccc
+            "#,
+        )
+        .unwrap()
+        .recalculated();
+
+        let cleared = grid.clear_active_cells();
+
+        assert_eq!(cleared, vec![gp(0, 0), gp(1, 0), gp(2, 0), gp(3, 0)]);
+        assert_eq!(grid.to_compact_string(), "4x2:.r../....");
+    }
+
+    #[test]
+    fn test_clear_active_cells_with_nothing_active() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            .r..
+            c.cc
+            "#,
+        )
+        .unwrap()
+        .recalculated();
+        let before = grid.clone();
+
+        assert!(grid.clear_active_cells().is_empty());
+        assert_eq!(before, grid);
+    }
+
+    #[test]
+    fn test_active_delta_round_trip() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            .c..
+            cc.c
+            c.cc
+            "#,
+        )
+        .unwrap()
+        .recalculated();
+        assert_eq!(grid.count_active_cells(), 0);
+
+        // Both sides swap the same cells but only one of them recalculates
+        grid.cells.swap(1, 5);
+        let stale = grid.clone();
+        grid.recalculate_active_cells();
+
+        let delta = Grid::active_delta(&stale, &grid);
+        assert_eq!(delta.len(), 4);
+
+        let mut peer = stale.clone();
+        peer.apply_active_delta(&delta).unwrap();
+
+        assert_eq!(grid, peer);
+    }
+
+    #[test]
+    fn test_apply_active_delta_out_of_bounds() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            ....
+            cccc
+            "#,
+        )
+        .unwrap();
+        let before = grid.clone();
+
+        assert!(matches!(
+            grid.apply_active_delta(&[(gp(0, 0), true), (gp(4, 0), true)]),
+            Err(GunpeyLibError::OutOfBounds { .. })
+        ));
+        assert_eq!(before, grid);
+    }
+
+    #[test]
+    fn test_apply_gravity_collapses_gap_in_column() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            c
+            .
+            i
+            "#,
+        )
+        .unwrap();
+
+        assert!(grid.apply_gravity());
+
+        let expected = Grid::new_from_str(
+            r#"
+            .
+            c
+            i
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected.as_chars(), grid.as_chars());
+        assert!(!grid.apply_gravity());
+    }
+
+    #[test]
+    fn test_apply_gravity_recalculates_after_moving() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            .c.
+            ...
+            c.c
+            "#,
+        )
+        .unwrap();
+        grid.recalculate_active_cells();
+        assert_eq!(grid.count_active_cells(), 0);
+
+        assert!(grid.apply_gravity());
+
+        assert_eq!(grid.to_compact_string(), "3x3:.../.../CCC");
+    }
+
+    #[test]
+    fn test_clamp_cursor_inside_grid() {
+        let grid = Grid::new(4, 3);
+
+        assert_eq!(grid.clamp_cursor(gp(1, 1), gp(1, 0)), gp(2, 1));
+        assert_eq!(grid.clamp_cursor(gp(1, 1), gp(-1, 1)), gp(0, 2));
+    }
+
+    #[test]
+    fn test_clamp_cursor_off_each_edge() {
+        let grid = Grid::new(4, 3);
+
+        assert_eq!(grid.clamp_cursor(gp(0, 1), gp(-1, 0)), gp(0, 1));
+        assert_eq!(grid.clamp_cursor(gp(3, 1), gp(1, 0)), gp(3, 1));
+        assert_eq!(grid.clamp_cursor(gp(2, 0), gp(0, -1)), gp(2, 0));
+        assert_eq!(grid.clamp_cursor(gp(2, 2), gp(0, 1)), gp(2, 2));
+        assert_eq!(grid.clamp_cursor(gp(1, 1), gp(-10, 10)), gp(0, 2));
+        assert_eq!(grid.clamp_cursor(gp(1, 1), gp(10, -10)), gp(3, 0));
+    }
+
+    #[test]
+    fn test_resolve_board_without_active_cells() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            .r..
+            c.cc
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(grid.resolve_board(), ChainResult::default());
+        assert_eq!(grid.to_compact_string(), "4x2:.r../c.cc");
+    }
+
+    #[test]
+    fn test_resolve_board_single_clear() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            .r..
+            cccc
+            "#,
+        )
+        .unwrap();
+
+        let result = grid.resolve_board();
+
+        assert_eq!(result.total_cleared, 4);
+        assert_eq!(result.chain_length, 1);
+        assert_eq!(grid.to_compact_string(), "4x2:..../.r..");
+    }
+
+    #[test]
+    fn test_resolve_board_chain_reaction() {
+        // Clearing the bottom row drops the middle row to the bottom and the c above it falls into
+        // the gap, completing a second line
+        let mut grid = Grid::new_from_str(
+            r#"
+            .c..
+            c.cc
+            cccc
+            "#,
+        )
+        .unwrap();
+
+        let result = grid.resolve_board();
+
+        assert_eq!(result.total_cleared, 8);
+        assert_eq!(result.chain_length, 2);
+        assert_eq!(grid.count_active_cells(), 0);
+        assert!(grid.cells.iter().all(Cell::is_empty));
+    }
+
+    #[test]
+    fn test_is_topped_out() {
+        let grid = Grid::new_from_str(
+            r#"
+            rrrr
+            ....
+            cccc
+            "#,
+        )
+        .unwrap();
+
+        assert!(grid.is_topped_out());
+    }
+
+    #[test]
+    fn test_is_topped_out_with_one_fragment_in_top_row() {
+        let topped_out = Grid::new_from_str(
+            r#"
+            ..i.
+            ..c.
+            cccc
+            "#,
+        )
+        .unwrap();
+        let not_topped_out = Grid::new_from_str(
+            r#"
+            ....
+            rrrr
+            cccc
+            "#,
+        )
+        .unwrap();
+
+        assert!(topped_out.is_topped_out());
+        assert!(!not_topped_out.is_topped_out());
+    }
+
+    #[test]
+    fn test_connection_degrees() {
+        let grid = Grid::new_from_str(
+            r#"
+            .w.i
+            lrlr
+            "#,
+        )
+        .unwrap();
+
+        let expected = HashMap::from([
+            (gp(0, 0), 1),
+            (gp(1, 0), 3),
+            (gp(2, 0), 3),
+            (gp(3, 0), 1),
+            (gp(1, 1), 2),
+            (gp(3, 1), 0),
+        ]);
+        assert_eq!(expected, grid.connection_degrees());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_json_round_trip() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            .w.i
+            lrlr
+            cccc
+            "#,
+        )
+        .unwrap();
+        grid.recalculate_active_cells();
+        grid.protect_active = true;
+
+        let json = serde_json::to_string(&grid).unwrap();
+        let deserialized: Grid = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(grid, deserialized);
+    }
+
+    #[test]
+    fn test_apply_gravity_animation_plan() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            r..
+            ..c
+            i..
+            ...
+            c.c
+            "#,
+        )
+        .unwrap();
+
+        let plan = grid.apply_gravity_animation_plan();
+
+        let expected = vec![
+            AnimatedMove {
+                from: gp(0, 2),
+                to: gp(0, 1),
+                delay: 0,
+            },
+            AnimatedMove {
+                from: gp(0, 4),
+                to: gp(0, 2),
+                delay: 1,
+            },
+            AnimatedMove {
+                from: gp(2, 3),
+                to: gp(2, 1),
+                delay: 0,
+            },
+        ];
+        assert_eq!(expected, plan);
+        assert_eq!(grid.to_compact_string(), "3x5:.../.../r../i.c/c.c");
+    }
+
+    #[test]
+    fn test_apply_gravity_animation_plan_orders_each_column() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(1007);
+        let mut grid = Grid::new(5, 10);
+        for cell in grid.cells.iter_mut() {
+            if rng.gen_bool(0.4) {
+                *cell = Cell::Filled(rng.gen());
+            }
+        }
+
+        let plan = grid.apply_gravity_animation_plan();
+        assert!(!plan.is_empty());
+
+        for pair in plan.windows(2) {
+            let (lower, upper) = (pair[0], pair[1]);
+            if lower.from.x == upper.from.x {
+                assert!(
+                    lower.from.y < upper.from.y,
+                    "{:?} before {:?}",
+                    lower,
+                    upper
+                );
+                assert_eq!(lower.delay + 1, upper.delay);
+            } else {
+                assert!(lower.from.x < upper.from.x);
+                assert_eq!(upper.delay, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_new_from_str_rejects_unknown_characters() {
+        let result = Grid::new_from_str(
+            r#"
+            .c.
+            rxi
+            "#,
+        );
+
+        assert!(matches!(result, Err(GunpeyLibError::InvalidCellChar('x'))));
+    }
+
+    #[test]
+    fn test_new_from_str_rejects_ragged_rows() {
+        let result = Grid::new_from_str(
+            r#"
+            .c.
+            ri
+            "#,
+        );
+
+        assert!(matches!(
+            result,
+            Err(GunpeyLibError::InvalidRowLength(2, 3))
+        ));
+    }
+
+    #[test]
+    fn test_new_from_str_checks_rows_in_the_same_order_as_try_from_str() {
+        // The short row comes before the bad character reading from the top
+        let grid_str = "cc\nc\nzz";
+
+        assert!(matches!(
+            Grid::try_from_str(grid_str),
+            Err(GunpeyLibError::InvalidRowLength(1, 2))
+        ));
+        assert!(matches!(
+            Grid::new_from_str(grid_str),
+            Err(GunpeyLibError::InvalidRowLength(1, 2))
+        ));
     }
 
-    pub fn left(&self, grid_pos: GridPos) -> Option<GridPos> {
-        if grid_pos.x == 0 {
-            None
-        } else {
-            Some(GridPos {
-                x: grid_pos.x - 1,
-                ..grid_pos
+    #[test]
+    fn test_new_from_str_rejects_small_grids() {
+        assert!(matches!(
+            Grid::new_from_str("ccc"),
+            Err(GunpeyLibError::InvalidDimensions {
+                width: 3,
+                height: 1
             })
-        }
+        ));
+        assert!(matches!(
+            Grid::new_from_str(""),
+            Err(GunpeyLibError::InvalidDimensions {
+                width: 0,
+                height: 0
+            })
+        ));
     }
 
-    pub fn above_right(&self, grid_pos: GridPos) -> Option<GridPos> {
-        self.above(grid_pos).and_then(|gp| self.right(gp))
-    }
+    #[test]
+    fn test_simulate_swap_bitmask() {
+        let grid = Grid::new_from_str(
+            r#"
+            .c..
+            cr.c
+            c.cc
+            "#,
+        )
+        .unwrap()
+        .recalculated();
+        let before = grid.clone();
 
-    pub fn below_right(&self, grid_pos: GridPos) -> Option<GridPos> {
-        self.below(grid_pos).and_then(|gp| self.right(gp))
-    }
+        let simulated = grid.simulate_swap_bitmask(gp(1, 0), gp(1, 2)).unwrap();
+        assert_eq!(before, grid);
 
-    pub fn above_left(&self, grid_pos: GridPos) -> Option<GridPos> {
-        self.above(grid_pos).and_then(|gp| self.left(gp))
+        let mut swapped = grid.clone();
+        swapped.swap_cells(gp(1, 0), gp(1, 2)).unwrap();
+        assert_bitmask_eq!(swapped.as_active_bitmask(), simulated);
+        assert_eq!(swapped.count_active_cells(), 4);
     }
 
-    pub fn below_left(&self, grid_pos: GridPos) -> Option<GridPos> {
-        self.below(grid_pos).and_then(|gp| self.left(gp))
+    #[test]
+    fn test_simulate_swap_bitmask_bad_position() {
+        let grid = Grid::new(3, 3);
+
+        assert!(matches!(
+            grid.simulate_swap_bitmask(gp(0, 0), gp(0, 3)),
+            Err(GunpeyLibError::CantSwapBadPosition { .. })
+        ));
     }
-}
 
-impl Display for Grid {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for row in self.cell_rows_in_render_order() {
-            for cell in row {
-                write!(f, "{}", cell)?;
-            }
-            writeln!(f, "")?;
-        }
+    #[test]
+    fn test_iter_render_mut_order() {
+        let mut grid = Grid::new(2, 2);
 
-        Ok(())
+        let positions: Vec<_> = grid.iter_render_mut().map(|(pos, _)| pos).collect();
+
+        assert_eq!(positions, vec![gp(0, 1), gp(1, 1), gp(0, 0), gp(1, 0)]);
     }
-}
 
-pub fn get_pos_from_index(index: usize, width: usize) -> GridPos {
-    let x = (index % width) as isize;
-    let y = (index / width) as isize;
+    #[test]
+    fn test_iter_render_mut_then_recalculate() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            cccc
+            cccc
+            "#,
+        )
+        .unwrap()
+        .recalculated();
+        assert_eq!(grid.count_active_cells(), 8);
 
-    GridPos { x, y }
-}
+        // Knock out every other cell in the top row
+        for (_, cell) in grid.iter_render_mut().take(4).step_by(2) {
+            *cell = Cell::Empty;
+        }
+        // Nothing is recalculated until we ask for it
+        assert_eq!(grid.count_active_cells(), 6);
 
-#[derive(Debug, Clone, Copy)]
-struct CellStatus {
-    pub is_connected_to_left_edge: bool,
-    pub is_connected_to_right_edge: bool,
-    pub is_part_of_a_chain: bool,
-    pub _cell_index: usize,
-}
+        grid.recalculate_active_cells();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use druid::im::vector;
-    use pretty_assertions::assert_eq;
+        assert_eq!(grid.to_compact_string(), "4x2:.c.c/CCCC");
+    }
 
-    fn new_2x2_grid() -> Grid {
-        Grid::new_from_chars(vec![vec!['.', '.'], vec!['.', '.']])
+    #[test]
+    fn test_row_fill_ratio() {
+        let grid = Grid::new_from_str(
+            r#"
+            ....
+            c.r.
+            cccc
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(grid.row_fill_ratio(0), 1.0);
+        assert_eq!(grid.row_fill_ratio(1), 0.5);
+        assert_eq!(grid.row_fill_ratio(2), 0.0);
+        assert!(grid.row_fill_ratio(3).is_nan());
     }
 
-    fn bitmask_from_str(_bitmask_str: &str) -> Bitmask {
-        todo!()
+    #[test]
+    fn test_row_fill_ratio_of_generated_row() {
+        use crate::{new_random_row, seeded_rng, NewRowGenerationParams, DEFAULT_FILL_RATIO};
+
+        // Each cell is filled independently so the row needs to be wide for the ratio to land
+        // close to the configured band
+        let mut grid = Grid::new(1000, 2);
+        let params = NewRowGenerationParams {
+            width: grid.width,
+            fill_ratio: DEFAULT_FILL_RATIO,
+            kind_weights: None,
+            seed: None,
+            avoid_active_on: None,
+        };
+        let new_row = new_random_row(&mut seeded_rng(1010), params);
+        grid.pop_top_row();
+        grid.push_bottom_row(new_row).unwrap();
+
+        let ratio = grid.row_fill_ratio(0) * 100.0;
+        let band = (DEFAULT_FILL_RATIO.start - 5.0)..(DEFAULT_FILL_RATIO.end + 5.0);
+        assert!(band.contains(&ratio), "{}% of the row was filled", ratio);
     }
 
     #[test]
-    fn test_get_pos_from_index() {
-        let width = 3;
+    fn test_resize_grow_then_shrink() {
+        let original = Grid::new_from_str(
+            r#"
+            r.
+            cl
+            "#,
+        )
+        .unwrap();
+        let mut grid = original.clone();
 
-        let expected = GridPos { x: 0, y: 0 };
-        let actual = get_pos_from_index(0, width);
+        grid.resize(4, 4);
 
-        assert_eq!(expected, actual);
+        let expected = Grid::new_from_str(
+            r#"
+            ....
+            ....
+            r...
+            cl..
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected, grid);
 
-        let expected = GridPos { x: 2, y: 0 };
-        let actual = get_pos_from_index(2, width);
+        grid.resize(2, 2);
+        assert_eq!(original, grid);
+    }
 
-        assert_eq!(expected, actual);
+    #[test]
+    fn test_resize_drops_cells_and_recalculates() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            ccr
+            ccr
+            "#,
+        )
+        .unwrap();
 
-        let expected = GridPos { x: 2, y: 1 };
-        let actual = get_pos_from_index(5, width);
+        grid.resize(2, 2);
 
-        assert_eq!(expected, actual);
+        let expected = Grid::new_from_str(
+            r#"
+            CC
+            CC
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected, grid);
     }
 
     #[test]
-    fn testget_cell_at_pos() {
+    fn test_biggest_near_miss_prefers_larger_chain() {
         let grid = Grid::new_from_str(
             r#"
-                .c.
-                r.i
+            ....c
+            .....
+            ccc..
             "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            Some(vec![gp(0, 0), gp(1, 0), gp(2, 0)]),
+            grid.biggest_near_miss()
         );
+    }
 
-        assert_eq!(".", grid.get_cell_at_pos(&gp(0, 1)).unwrap().to_str());
-        assert_eq!("c", grid.get_cell_at_pos(&gp(1, 1)).unwrap().to_str());
-        assert_eq!(".", grid.get_cell_at_pos(&gp(2, 1)).unwrap().to_str());
-        assert_eq!("r", grid.get_cell_at_pos(&gp(0, 0)).unwrap().to_str());
-        assert_eq!(".", grid.get_cell_at_pos(&gp(1, 0)).unwrap().to_str());
-        assert_eq!("i", grid.get_cell_at_pos(&gp(2, 0)).unwrap().to_str());
+    #[test]
+    fn test_biggest_near_miss_ignores_completed_chains() {
+        let grid = Grid::new_from_str(
+            r#"
+            ...
+            CCC
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(None, grid.biggest_near_miss());
     }
 
-    //   0,0 0,1
-    //   1,0 1,1
+    #[test]
+    fn test_swap_fragment_with_empty_below_moves_it_down() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            .r.
+            ...
+            "#,
+        )
+        .unwrap();
+
+        grid.swap_cells(gp(1, 1), gp(1, 0)).unwrap();
+
+        let expected = Grid::new_from_str(
+            r#"
+            ...
+            .r.
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected, grid);
+    }
 
     #[test]
-    fn test_grid_pos_above_some() {
-        let grid = new_2x2_grid();
-        let starting_grid_pos = GridPos { x: 0, y: 0 };
-        let expected = Some(GridPos { x: 0, y: 1 });
-        let actual = grid.above(starting_grid_pos);
+    fn test_drop_cell_onto_empty() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            .c.
+            c.c
+            "#,
+        )
+        .unwrap();
 
-        assert_eq!(expected, actual);
+        assert!(grid.drop_cell(gp(1, 1)));
+
+        let expected = Grid::new_from_str(
+            r#"
+            ...
+            CCC
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected, grid);
     }
 
     #[test]
-    fn test_grid_pos_above_none() {
-        let grid = new_2x2_grid();
-        let starting_grid_pos = GridPos { x: 0, y: 1 };
-        let expected = None;
-        let actual = grid.above(starting_grid_pos);
+    fn test_drop_cell_resting_on_fragment_is_noop() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            .r.
+            .c.
+            "#,
+        )
+        .unwrap();
+        let before = grid.clone();
 
-        assert_eq!(expected, actual);
+        assert!(!grid.drop_cell(gp(1, 1)));
+        assert!(!grid.drop_cell(gp(1, 0)));
+        assert!(!grid.drop_cell(gp(0, 1)));
+        assert_eq!(before, grid);
     }
 
     #[test]
-    fn test_grid_pos_above_right_some() {
-        let grid = new_2x2_grid();
-        let starting_grid_pos = GridPos { x: 0, y: 0 };
-        let expected = Some(GridPos { x: 1, y: 1 });
-        let actual = grid.above_right(starting_grid_pos);
+    fn test_count_active_cells_and_active_positions() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            r..
+            ccc
+            "#,
+        )
+        .unwrap();
+        assert_eq!(0, grid.count_active_cells());
+        assert!(grid.active_positions().is_empty());
 
-        assert_eq!(expected, actual);
+        grid.recalculate_active_cells();
+
+        assert_eq!(3, grid.count_active_cells());
+        assert_eq!(vec![gp(0, 0), gp(1, 0), gp(2, 0)], grid.active_positions());
     }
 
     #[test]
-    fn test_grid_pos_above_right_none() {
-        let grid = new_2x2_grid();
-        let starting_grid_pos = GridPos { x: 1, y: 1 };
-        let expected = None;
-        let actual = grid.above_right(starting_grid_pos);
+    fn test_summary() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            ...r
+            cc..
+            cccc
+            "#,
+        )
+        .unwrap();
+        grid.recalculate_active_cells();
 
-        assert_eq!(expected, actual);
+        let summary = grid.summary();
+
+        assert_eq!(
+            BoardSummary {
+                filled: 7,
+                active: 4,
+                near_miss: Some(vec![gp(0, 1), gp(1, 1)]),
+                max_column_height: 3,
+                fill_fraction: 7.0 / 12.0,
+                chains: 1,
+            },
+            summary
+        );
     }
 
     #[test]
-    fn test_grid_pos_right_some() {
-        let grid = new_2x2_grid();
-        let starting_grid_pos = GridPos { x: 0, y: 0 };
-        let expected = Some(GridPos { x: 1, y: 0 });
-        let actual = grid.right(starting_grid_pos);
+    fn test_cell_columns() {
+        let grid = Grid::new_from_str(
+            r#"
+            r..
+            .i.
+            c.l
+            "#,
+        )
+        .unwrap();
 
-        assert_eq!(expected, actual);
+        let columns = grid.cell_columns();
+
+        assert_eq!(3, columns.len());
+        assert!(columns.iter().all(|column| column.len() == 3));
+        assert_eq!(
+            vec![Cell::from_str("c"), Cell::Empty, Cell::from_str("r")],
+            columns[0]
+        );
     }
 
     #[test]
-    fn test_grid_pos_right_none() {
-        let grid = new_2x2_grid();
-        let starting_grid_pos = GridPos { x: 1, y: 0 };
-        let expected = None;
-        let actual = grid.right(starting_grid_pos);
+    fn test_cells_in_order_top_to_bottom() {
+        let grid = Grid::new_from_str(
+            r#"
+            r..
+            .i#
+            c.l
+            "#,
+        )
+        .unwrap();
+
+        let cells: Vec<_> = grid
+            .cells_in_order(
+                GridIterDirectionX::RightToLeft,
+                GridIterDirectionY::TopToBottom,
+            )
+            .map(|(pos, cell)| (pos, cell.to_str()))
+            .collect();
 
-        assert_eq!(expected, actual);
+        assert_eq!(
+            vec![
+                (gp(2, 2), "."),
+                (gp(1, 2), "."),
+                (gp(0, 2), "r"),
+                (gp(2, 1), "#"),
+                (gp(1, 1), "i"),
+                (gp(0, 1), "."),
+                (gp(2, 0), "l"),
+                (gp(1, 0), "."),
+                (gp(0, 0), "c"),
+            ],
+            cells
+        );
     }
 
     #[test]
-    fn test_grid_pos_below_right_some() {
-        let grid = new_2x2_grid();
-        let starting_grid_pos = GridPos { x: 0, y: 1 };
-        let expected = Some(GridPos { x: 1, y: 0 });
-        let actual = grid.below_right(starting_grid_pos);
+    fn test_cells_in_order_covers_every_cell_once() {
+        let grid = Grid::new(4, 3);
 
-        assert_eq!(expected, actual);
+        for direction_x in [
+            GridIterDirectionX::LeftToRight,
+            GridIterDirectionX::RightToLeft,
+        ] {
+            for direction_y in [
+                GridIterDirectionY::BottomToTop,
+                GridIterDirectionY::TopToBottom,
+            ] {
+                let positions: HashSet<_> = grid
+                    .cells_in_order(direction_x, direction_y)
+                    .map(|(pos, _)| pos)
+                    .collect();
+
+                assert_eq!(12, positions.len());
+                assert!(positions
+                    .iter()
+                    .all(|pos| grid.get_cell_at_pos(pos).is_some()));
+            }
+        }
     }
 
     #[test]
-    fn test_grid_pos_below_right_none() {
-        let grid = new_2x2_grid();
-        let starting_grid_pos = GridPos { x: 1, y: 0 };
-        let expected = None;
-        let actual = grid.below_right(starting_grid_pos);
+    fn test_prune_dangling_nodes_matches_rescanning() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        // The straightforward version, rescanning every node after each one is pruned
+        fn prune_by_rescanning(grid: &Grid) -> HashSet<GridPos> {
+            let mut nodes: HashSet<_> = new_xy_iter(
+                grid.width + 1,
+                grid.height + 1,
+                GridIterDirectionX::LeftToRight,
+                GridIterDirectionY::BottomToTop,
+            )
+            .map(|(x, y)| gp(x as isize, y as isize))
+            .collect();
 
-        assert_eq!(expected, actual);
+            while let Some(node) = nodes
+                .iter()
+                .copied()
+                .find(|node| grid.node_edge_count(node, &nodes) < 2)
+            {
+                nodes.remove(&node);
+            }
+
+            nodes
+        }
+
+        let mut rng = StdRng::seed_from_u64(1015);
+
+        for _ in 0..100 {
+            let mut grid = Grid::new(6, 8);
+            for cell in grid.cells.iter_mut() {
+                if rng.gen_bool(0.7) {
+                    *cell = Cell::Filled(rng.gen());
+                }
+            }
+
+            assert_eq!(prune_by_rescanning(&grid), grid.prune_dangling_nodes());
+        }
     }
 
     #[test]
-    fn test_grid_pos_below_some() {
-        let grid = new_2x2_grid();
-        let starting_grid_pos = GridPos { x: 0, y: 1 };
-        let expected = Some(GridPos { x: 0, y: 0 });
-        let actual = grid.below(starting_grid_pos);
+    fn test_explain_cell() {
+        let grid = Grid::new_from_str(
+            r#"
+            .....
+            cc.cc
+            "#,
+        )
+        .unwrap();
+
+        let explanation = grid.explain_cell(gp(1, 0));
+
+        assert!(explanation.contains(
+            "left neighbor at (x: 0, y: 0) holds 'c' caret: connects, the lines meet at (x: 1, y: 0)"
+        ));
+        assert!(explanation.contains("right neighbor at (x: 2, y: 0) holds empty: doesn't connect"));
+        assert!(explanation.contains("reaches left edge: yes"));
+        assert!(explanation.contains("reaches right edge: no"));
+    }
+
+    #[test]
+    fn test_get_row_and_set_row() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            r..
+            ...
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            Some(vector![Cell::from_str("r"), Cell::Empty, Cell::Empty]),
+            grid.get_row(1)
+        );
+        assert_eq!(None, grid.get_row(2));
 
-        assert_eq!(expected, actual);
+        grid.set_row(0, Vector::from(vec![Cell::from_str("c"); 3]))
+            .unwrap();
+
+        let expected = Grid::new_from_str(
+            r#"
+            r..
+            CCC
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected, grid);
     }
 
     #[test]
-    fn test_grid_pos_below_none() {
-        let grid = new_2x2_grid();
-        let starting_grid_pos = GridPos { x: 0, y: 0 };
-        let expected = None;
-        let actual = grid.below(starting_grid_pos);
-
-        assert_eq!(expected, actual);
+    fn test_set_row_errors() {
+        let mut grid = Grid::new(3, 2);
+
+        assert!(matches!(
+            grid.set_row(2, Vector::from(vec![Cell::Empty; 3])),
+            Err(GunpeyLibError::RowOutOfBounds { y: 2, height: 2 })
+        ));
+        assert!(matches!(
+            grid.set_row(0, Vector::from(vec![Cell::from_str("c"); 2])),
+            Err(GunpeyLibError::InvalidRowLength(2, 3))
+        ));
+        assert_eq!(Grid::new(3, 2), grid);
     }
 
     #[test]
-    fn test_edges_should_be_detected_1() {
-        #[rustfmt::skip]
-        let chars: CharGrid = vec![
-            vec!['/','/'],
-            vec!['/','/'],
-        ];
-        let grid = Grid::new_from_chars(chars);
-        let mut expected: Vec<(GridPos, GridPos)> = vec![
-            (GridPos::new(0, 0), GridPos::new(1, 1)),
-            (GridPos::new(1, 1), GridPos::new(0, 0)),
-        ];
-        expected.sort();
+    fn test_recalculate_active_cells_with_straight_fragments() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            v...
+            ..hh
+            hhv.
+            "#,
+        )
+        .unwrap();
 
-        let mut actual = grid.edges();
-        actual.sort();
+        grid.recalculate_active_cells();
 
-        assert_eq!(expected, actual)
+        let expected = Grid::new_from_str(
+            r#"
+            v...
+            ..HH
+            HHV.
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected, grid);
     }
 
     #[test]
-    fn test_edges_should_be_detected_2() {
-        #[rustfmt::skip]
-        let chars: CharGrid = vec![
-            vec!['∨','∨'],
-            vec!['∧','∧'],
-        ];
-        let grid = Grid::new_from_chars(chars);
-        let expected: Vec<(GridPos, GridPos)> = vec![
-            (GridPos::new(0, 0), GridPos::new(1, 0)),
-            (GridPos::new(1, 0), GridPos::new(0, 0)),
-            (GridPos::new(0, 1), GridPos::new(1, 1)),
-            (GridPos::new(1, 1), GridPos::new(0, 1)),
-        ];
-        let actual = grid.edges();
+    fn test_set_cell() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            ...
+            c.c
+            "#,
+        )
+        .unwrap();
 
-        assert_eq!(expected, actual)
+        grid.set_cell(&gp(1, 0), Cell::from_str("c")).unwrap();
+
+        let expected = Grid::new_from_str(
+            r#"
+            ...
+            CCC
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected, grid);
     }
 
     #[test]
-    fn test_edges_should_not_be_detected() {
-        #[rustfmt::skip]
-        let chars: CharGrid = vec![
-            vec!['∨','/'],
-            vec!['∧','.'],
-        ];
-        let grid = Grid::new_from_chars(chars);
-        let expected: Vec<(GridPos, GridPos)> = vec![];
-        let actual = grid.edges();
-
-        assert_eq!(expected, actual)
+    fn test_set_cell_out_of_bounds() {
+        let mut grid = Grid::new(3, 2);
+
+        assert!(matches!(
+            grid.set_cell(&gp(3, 0), Cell::from_str("c")),
+            Err(GunpeyLibError::OutOfBounds {
+                width: 3,
+                height: 2,
+                ..
+            })
+        ));
+        assert!(grid.set_cell(&gp(0, -1), Cell::from_str("c")).is_err());
+        assert_eq!(Grid::new(3, 2), grid);
     }
 
-    // '∧'
-    // '∨'
-    // '\\'
-    // '/'
-
-    // #endregion
-
     #[test]
-    fn test_recalculate_active_cells_are_active_1() {
-        #[rustfmt::skip]
-        let chars: CharGrid = vec![
-            vec!['.','∧','.'],
-            vec!['/','.','∨'],
-        ];
-        #[rustfmt::skip]
-        let expected_active: Bitmask = vec![
-            vec![0, 0, 0],
-            vec![0, 0, 0],
-        ];
-        let mut grid = Grid::new_from_chars(chars);
+    fn test_bitmask_from_str() {
+        let expected: Bitmask = vec![vec![0, 1, 0], vec![1, 0, 1]];
 
-        // None should be active before recalculation
-        let actual_active = grid.as_active_bitmask();
-        assert_eq!(expected_active, actual_active);
+        let actual = bitmask_from_str(
+            r#"
 
-        grid.recalculate_active_cells();
+            010
+            101
 
-        #[rustfmt::skip]
-        let expected_active: Bitmask = vec![
-            vec![0, 1, 0],
-            vec![1, 0, 1],
-        ];
+            "#,
+        );
 
-        // All should be active after recalculation
-        let actual_active = grid.as_active_bitmask();
-        assert_eq!(expected_active, actual_active);
+        assert_eq!(expected, actual);
     }
 
     #[test]
-    fn test_recalculate_active_cells_are_active_2() {
-        #[rustfmt::skip]
-        let chars: CharGrid = vec![
-            vec!['.','.','.','∧','.'],
-            vec!['∧','∧','/','.','\\'],
-            vec!['.','\\','\\','/','∨'],
-            vec!['.','.','∨','\\','∧'],
-            vec!['.','.','.','.','∨'],
-        ];
-        #[rustfmt::skip]
-        let expected_active: Bitmask = vec![
-            vec![0, 0, 0, 0, 0],
-            vec![0, 0, 0, 0, 0],
-            vec![0, 0, 0, 0, 0],
-            vec![0, 0, 0, 0, 0],
-            vec![0, 0, 0, 0, 0],
-        ];
-        let mut grid = Grid::new_from_chars(chars);
-
-        // None should be active before recalculation
-        let actual_active = grid.as_active_bitmask();
-        assert_eq!(expected_active, actual_active);
+    #[should_panic(expected = "'2' isn't a bit")]
+    fn test_bitmask_from_str_rejects_other_chars() {
+        bitmask_from_str("012");
+    }
 
+    #[test]
+    fn test_active_bitmask_matches() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            .r..
+            iiii
+            "#,
+        )
+        .unwrap();
         grid.recalculate_active_cells();
+        let expected = bitmask_from_str(
+            r#"
+            0000
+            1111
+            "#,
+        );
 
-        #[rustfmt::skip]
-        let expected_active: Bitmask = vec![
-            vec![0, 0, 0, 1, 0],
-            vec![1, 1, 1, 0, 1],
-            vec![0, 1, 1, 1, 1],
-            vec![0, 0, 1, 1, 1],
-            vec![0, 0, 0, 0, 1],
-        ];
-
-        // All should be active after recalculation
-        let actual_active = grid.as_active_bitmask();
-        assert_eq!(expected_active, actual_active);
+        assert!(grid.active_bitmask_matches(&expected));
+        assert!(grid.active_bitmask_matches(&grid.as_active_bitmask()));
+        // Flipped top to bottom
+        assert!(!grid.active_bitmask_matches(&bitmask_from_str("1111\n0000")));
+        // Missing a row, or a column
+        assert!(!grid.active_bitmask_matches(&bitmask_from_str("1111")));
+        assert!(!grid.active_bitmask_matches(&bitmask_from_str("000\n111")));
     }
 
     #[test]
-    fn test_recalculate_active_cells_are_active_3() {
-        #[rustfmt::skip]
-        let grid = r#"
+    fn test_are_cells_connected() {
+        // The same board as test_recalculate_active_cells_are_active_3
+        let grid = Grid::new_from_str(
+            r#"
             .rc
             ri.
-        "#;
-        #[rustfmt::skip]
-        let expected_active: Bitmask = vec![
-            vec![0, 0, 0],
-            vec![0, 0, 0],
-        ];
-        let mut grid = Grid::new_from_str(grid);
-
-        // None should be active before recalculation
-        let actual_active = grid.as_active_bitmask();
-        assert_eq!(expected_active, actual_active);
+            "#,
+        )
+        .unwrap();
+
+        // The line from wall to wall
+        assert!(grid.are_cells_connected(gp(0, 0), gp(1, 0)));
+        assert!(grid.are_cells_connected(gp(0, 0), gp(2, 1)));
+        assert!(grid.are_cells_connected(gp(2, 1), gp(0, 0)));
+        assert!(grid.are_cells_connected(gp(1, 0), gp(1, 0)));
+        // The slash is a dead end so it never becomes active, but it still touches the line
+        assert!(grid.are_cells_connected(gp(1, 1), gp(0, 0)));
+        assert!(grid.are_cells_connected(gp(2, 1), gp(1, 1)));
+        // Empty and out of bounds cells
+        assert!(!grid.are_cells_connected(gp(0, 1), gp(0, 1)));
+        assert!(!grid.are_cells_connected(gp(0, 0), gp(2, 0)));
+        assert!(!grid.are_cells_connected(gp(0, 0), gp(3, 0)));
+        assert!(!grid.are_cells_connected(gp(-1, 0), gp(0, 0)));
+
+        // Two fragments with a gap between them
+        let grid = Grid::new_from_str(
+            r#"
+            ...
+            c.c
+            "#,
+        )
+        .unwrap();
+        assert!(!grid.are_cells_connected(gp(0, 0), gp(2, 0)));
+    }
 
+    #[test]
+    fn test_display_round_trips_active_flags() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            .r..w
+            iiiih
+            v...c
+            "#,
+        )
+        .unwrap();
         grid.recalculate_active_cells();
 
-        #[rustfmt::skip]
-        let expected_active: Bitmask = vec![
-            vec![0, 0, 1],
-            vec![1, 1, 0],
-        ];
+        let displayed = grid.to_string();
 
-        // Connected cells should be active after recalculation
-        let actual_active = grid.as_active_bitmask();
-        assert_eq!(expected_active, actual_active);
+        assert_eq!(".r..W\nIIIIh\nv...c\n", displayed);
+        assert_eq!(grid, Grid::new_from_str(&displayed).unwrap());
     }
 
     #[test]
-    fn test_recalculate_active_cells_all_inactive() {
-        #[rustfmt::skip]
-        let chars: CharGrid = vec![
-            vec!['/','.'],
-            vec!['.','/'],
-        ];
-        #[rustfmt::skip]
-        let expected_active: Bitmask = vec![
-            vec![0,0],
-            vec![0,0],
-        ];
+    fn test_blocked_cell_holds_up_its_column() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            c.
+            r.
+            ..
+            #.
+            ..
+            "#,
+        )
+        .unwrap();
 
-        let mut grid = Grid::new_from_chars(chars);
+        let moves = grid.apply_gravity_with_moves();
 
-        // None should be active before recalculation
-        let actual_active = grid.as_active_bitmask();
-        assert_eq!(expected_active, actual_active);
+        // The fragments land on the blocked cell instead of falling to the bottom
+        let expected = Grid::new_from_str(
+            r#"
+            ..
+            c.
+            r.
+            #.
+            ..
+            "#,
+        )
+        .unwrap();
+        assert_eq!(expected, grid);
+        assert_eq!(vec![(gp(0, 3), gp(0, 2)), (gp(0, 4), gp(0, 3))], moves);
+    }
 
+    #[test]
+    fn test_blocked_cell_breaks_chains() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            ...
+            c#c
+            "#,
+        )
+        .unwrap();
         grid.recalculate_active_cells();
+        assert_eq!(0, grid.count_active_cells());
 
-        // None should be active after recalculation either
-        let actual_active = grid.as_active_bitmask();
-        assert_eq!(expected_active, actual_active);
+        grid.set_cell(&gp(1, 0), Cell::from_str("c")).unwrap();
+        assert_eq!(3, grid.count_active_cells());
     }
 
     #[test]
-    fn test_recalculate_active_cells_1() {
+    fn test_blocked_cells_cant_be_swapped() {
         let mut grid = Grid::new_from_str(
             r#"
-        .....
-        .....
-        .....
-        .....
-        .....
-        .....
-        .....
-        .....
-        .crlr
-        ic...
-        "#,
-        );
+            c.
+            #r
+            "#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            grid.swap_cells(gp(0, 0), gp(0, 1)),
+            Err(GunpeyLibError::CantSwapBlocked(pos)) if pos == gp(0, 0)
+        ));
+        assert_eq!(vec![(gp(1, 0), gp(1, 1))], grid.legal_swaps());
+        assert!(!grid.swap_is_noop(gp(0, 0), gp(1, 1)));
+    }
 
-        let expected_active = bitmask_from_str(
+    #[test]
+    fn test_blocked_cells_dont_top_out_the_grid() {
+        let grid = Grid::new_from_str(
             r#"
-        00000
-        00000
-        00000
-        00000
-        00000
-        00000
-        00000
-        00000
-        00000
-        01111
-        10000
-        "#,
-        );
-
-        // None should be active before recalculation
-        let actual_active = grid.as_active_bitmask();
-        assert_eq!(expected_active, actual_active);
-
-        grid.recalculate_active_cells();
+            #..
+            c..
+            "#,
+        )
+        .unwrap();
 
-        // None should be active after recalculation either
-        let actual_active = grid.as_active_bitmask();
-        assert_eq!(expected_active, actual_active);
+        assert!(!grid.is_topped_out());
+        assert_eq!(0.0, grid.row_fill_ratio(1));
     }
 
     #[test]
-    fn test_pop_top_row() {
-        let mut grid = Grid::new_from_str(
+    fn test_from_annotated_str_with_blocked_cells() {
+        let grid = Grid::from_annotated_str(
             r#"
-        .cc
-        .l.
-        ..l
-        "#,
-        );
+            # a hole in the middle
+            .#. # blocked cells work anywhere but the start of a row
+            ccc
+            "#,
+        )
+        .unwrap();
 
-        let expected_popped_row = vector![
-            Cell::Empty,
-            Cell::Filled(LineFragment::from_str("c")),
-            Cell::Filled(LineFragment::from_str("c"))
-        ];
-        let actual_popped_row = grid.pop_top_row();
+        assert_eq!(Grid::new_from_str(".#.\nccc").unwrap(), grid);
+        assert!(grid.is_cell_blocked(&gp(1, 1)));
+    }
 
-        assert_eq!(expected_popped_row, actual_popped_row);
+    #[test]
+    fn test_from_annotated_str_skips_every_line_starting_with_a_hash() {
+        let grid = Grid::from_annotated_str("#comment\nc.\n#\n.c\n#c").unwrap();
+        assert_eq!(Grid::new_from_str("c.\n.c").unwrap(), grid);
+
+        // A row of blocked cells reads as a comment too
+        let grid = Grid::from_annotated_str("c\n#\nc").unwrap();
+        assert_eq!(Grid::new_from_str("c\nc").unwrap(), grid);
     }
 }
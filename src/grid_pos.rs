@@ -7,6 +7,7 @@ pub fn gp(x: isize, y: isize) -> GridPos {
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GridPos {
     pub y: isize,
     pub x: isize,
@@ -22,6 +23,27 @@ impl GridPos {
         (x, y)
     }
 
+    /// How many steps apart two positions are moving only up, down, left and right
+    pub fn manhattan_distance(&self, other: &GridPos) -> usize {
+        self.x.abs_diff(other.x) + self.y.abs_diff(other.y)
+    }
+
+    /// The eight positions around this one, going clockwise from the left. Some of them may be
+    /// off the grid.
+    pub fn neighbors(&self) -> [GridPos; 8] {
+        [
+            Adjacency::Left,
+            Adjacency::AboveLeft,
+            Adjacency::Above,
+            Adjacency::AboveRight,
+            Adjacency::Right,
+            Adjacency::BelowRight,
+            Adjacency::Below,
+            Adjacency::BelowLeft,
+        ]
+        .map(|adjacency| *self + adjacency.offset().expect("neighbors have an offset"))
+    }
+
     #[rustfmt::skip]
     pub fn adjacency(&self, other: &Self) -> Adjacency {
         match (
@@ -175,4 +197,47 @@ mod tests {
 
         assert_eq!(Adjacency::NotAdjacent, actual);
     }
+
+    #[test]
+    fn test_manhattan_distance() {
+        let pos = GridPos::new(1, 2);
+
+        assert_eq!(0, pos.manhattan_distance(&pos));
+        assert_eq!(1, pos.manhattan_distance(&GridPos::new(1, 3)));
+        assert_eq!(2, pos.manhattan_distance(&GridPos::new(2, 3)));
+        assert_eq!(7, pos.manhattan_distance(&GridPos::new(4, 6)));
+        assert_eq!(5, pos.manhattan_distance(&GridPos::new(-1, -1)));
+        assert_eq!(
+            pos.manhattan_distance(&GridPos::new(4, -3)),
+            GridPos::new(4, -3).manhattan_distance(&pos)
+        );
+    }
+
+    #[test]
+    fn test_neighbors() {
+        let expected = [
+            GridPos::new(0, 2),
+            GridPos::new(0, 3),
+            GridPos::new(1, 3),
+            GridPos::new(2, 3),
+            GridPos::new(2, 2),
+            GridPos::new(2, 1),
+            GridPos::new(1, 1),
+            GridPos::new(0, 1),
+        ];
+
+        assert_eq!(expected, GridPos::new(1, 2).neighbors());
+    }
+
+    #[test]
+    fn test_neighbors_of_origin_leave_the_grid() {
+        let neighbors = GridPos::new(0, 0).neighbors();
+
+        assert_eq!(GridPos::new(-1, 0), neighbors[0]);
+        assert_eq!(GridPos::new(-1, -1), neighbors[7]);
+        for neighbor in neighbors {
+            let distance = GridPos::new(0, 0).manhattan_distance(&neighbor);
+            assert!(distance == 1 || distance == 2);
+        }
+    }
 }
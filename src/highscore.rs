@@ -0,0 +1,144 @@
+use crate::error::GunpeyLibError;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many scores are kept, anything below the last one is dropped when saving
+pub const MAX_HIGH_SCORES: usize = 10;
+
+/// One finished run on the high score table
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScoreEntry {
+    pub name: String,
+    pub points: u64,
+    /// When the run ended, in seconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+impl ScoreEntry {
+    /// An entry for a run that just ended
+    pub fn new(name: impl Into<String>, points: u64) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+
+        Self {
+            name: name.into(),
+            points,
+            timestamp,
+        }
+    }
+}
+
+/// Read the high scores saved at `path`, best first. A missing or unreadable file is treated
+/// as an empty table so a bad file never stops the game from starting.
+pub fn load_high_scores(path: &Path) -> Vec<ScoreEntry> {
+    let json = match std::fs::read_to_string(path) {
+        Ok(json) => json,
+        Err(_) => return Vec::new(),
+    };
+
+    match serde_json::from_str::<Vec<ScoreEntry>>(&json) {
+        Ok(mut scores) => {
+            sort_and_truncate(&mut scores);
+            scores
+        }
+        Err(err) => {
+            warn!(
+                "Ignoring corrupt high score file {}: {}",
+                path.display(),
+                err
+            );
+            Vec::new()
+        }
+    }
+}
+
+/// Add `entry` to the high scores saved at `path` and write the top `MAX_HIGH_SCORES` back,
+/// returning the updated table. Ties keep the older score ahead of the new one.
+pub fn save_high_score(path: &Path, entry: ScoreEntry) -> Result<Vec<ScoreEntry>, GunpeyLibError> {
+    let mut scores = load_high_scores(path);
+    scores.push(entry);
+    sort_and_truncate(&mut scores);
+
+    let json = serde_json::to_string_pretty(&scores).map_err(std::io::Error::from)?;
+    std::fs::write(path, json)?;
+
+    Ok(scores)
+}
+
+fn sort_and_truncate(scores: &mut Vec<ScoreEntry>) {
+    // Stable, so equal scores stay in the order they were set
+    scores.sort_by_key(|entry| Reverse(entry.points));
+    scores.truncate(MAX_HIGH_SCORES);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "gunpey_high_scores_{}_{}.json",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        path
+    }
+
+    #[test]
+    fn test_load_missing_file() {
+        let path = temp_path("missing");
+
+        assert!(load_high_scores(&path).is_empty());
+    }
+
+    #[test]
+    fn test_load_corrupt_file() {
+        let path = temp_path("corrupt");
+        std::fs::write(&path, "not json at all").unwrap();
+
+        assert!(load_high_scores(&path).is_empty());
+
+        // Saving over a corrupt file starts a fresh table
+        let scores = save_high_score(&path, ScoreEntry::new("zelda", 40)).unwrap();
+        assert_eq!(1, scores.len());
+        assert_eq!(scores, load_high_scores(&path));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_save_keeps_best_scores_first() {
+        let path = temp_path("sorted");
+
+        save_high_score(&path, ScoreEntry::new("a", 20)).unwrap();
+        save_high_score(&path, ScoreEntry::new("b", 50)).unwrap();
+        save_high_score(&path, ScoreEntry::new("c", 20)).unwrap();
+        let scores = load_high_scores(&path);
+
+        let names: Vec<_> = scores.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(vec!["b", "a", "c"], names);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_save_drops_scores_past_the_limit() {
+        let path = temp_path("limit");
+
+        for points in 0..MAX_HIGH_SCORES as u64 + 5 {
+            save_high_score(&path, ScoreEntry::new("player", points)).unwrap();
+        }
+        let scores = load_high_scores(&path);
+
+        assert_eq!(MAX_HIGH_SCORES, scores.len());
+        assert_eq!(MAX_HIGH_SCORES as u64 + 4, scores[0].points);
+        assert_eq!(5, scores[MAX_HIGH_SCORES - 1].points);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
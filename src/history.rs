@@ -0,0 +1,214 @@
+use crate::{error::GunpeyLibError, grid::Grid, grid_pos::GridPos};
+use std::collections::VecDeque;
+
+/// How many swaps front-ends remember unless they pick their own depth
+pub const DEFAULT_UNDO_DEPTH: usize = 50;
+
+/// Remembers recent swaps so they can be undone and redone. Swapping the same two cells again
+/// puts them back, so each entry is just the pair of positions that were swapped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UndoHistory {
+    undo_stack: VecDeque<(GridPos, GridPos)>,
+    redo_stack: Vec<(GridPos, GridPos)>,
+    max_depth: usize,
+}
+
+impl Default for UndoHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_UNDO_DEPTH)
+    }
+}
+
+impl UndoHistory {
+    /// Create an empty history that remembers at most `max_depth` swaps. Once it's full the oldest
+    /// swap is forgotten to make room.
+    pub fn new(max_depth: usize) -> Self {
+        Self {
+            undo_stack: VecDeque::with_capacity(max_depth),
+            redo_stack: Vec::new(),
+            max_depth,
+        }
+    }
+
+    /// Record a swap that was just made. Anything that was undone can't be redone after this.
+    pub fn push(&mut self, a: GridPos, b: GridPos) {
+        self.redo_stack.clear();
+        if self.max_depth == 0 {
+            return;
+        }
+
+        if self.undo_stack.len() == self.max_depth {
+            self.undo_stack.pop_front();
+        }
+        self.undo_stack.push_back((a, b));
+    }
+
+    /// Swap the most recent swap back, returning the positions that were swapped
+    /// or `None` if there was nothing to undo. If the grid refuses the swap it stays in the
+    /// history.
+    pub fn undo(&mut self, grid: &mut Grid) -> Result<Option<(GridPos, GridPos)>, GunpeyLibError> {
+        let (a, b) = match self.undo_stack.pop_back() {
+            Some(swap) => swap,
+            None => return Ok(None),
+        };
+
+        if let Err(err) = grid.swap_cells(a, b) {
+            self.undo_stack.push_back((a, b));
+            return Err(err);
+        }
+        self.redo_stack.push((a, b));
+
+        Ok(Some((a, b)))
+    }
+
+    /// Make the most recently undone swap again, returning the positions that were swapped or
+    /// `None` if there was nothing to redo.
+    pub fn redo(&mut self, grid: &mut Grid) -> Result<Option<(GridPos, GridPos)>, GunpeyLibError> {
+        let (a, b) = match self.redo_stack.pop() {
+            Some(swap) => swap,
+            None => return Ok(None),
+        };
+
+        if let Err(err) = grid.swap_cells(a, b) {
+            self.redo_stack.push((a, b));
+            return Err(err);
+        }
+        self.undo_stack.push_back((a, b));
+
+        Ok(Some((a, b)))
+    }
+
+    /// Forget every swap, e.g. after cells are cleared and the old swaps no longer make sense.
+    pub fn clear(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+    }
+
+    /// Follow the cells up a row after the stack rises, so undo still swaps the cells that were
+    /// swapped. If a swap would be moved off the top of a grid `height` rows tall then the whole
+    /// history is forgotten, since the swaps after it only make sense if it can be undone too.
+    pub fn shift_up_one_row(&mut self, height: usize) {
+        let top = height as isize - 1;
+        let off_the_top = self
+            .undo_stack
+            .iter()
+            .chain(self.redo_stack.iter())
+            .any(|(a, b)| a.y >= top || b.y >= top);
+        if off_the_top {
+            self.clear();
+            return;
+        }
+
+        let one_row = GridPos::new(0, 1);
+        for (a, b) in self.undo_stack.iter_mut().chain(self.redo_stack.iter_mut()) {
+            *a = *a + one_row;
+            *b = *b + one_row;
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid_pos::gp;
+
+    #[test]
+    fn test_undo_and_redo_swap() {
+        let original = Grid::new_from_str(
+            r#"
+            .r.
+            c.c
+            "#,
+        )
+        .unwrap();
+        let mut grid = original.clone();
+        let mut history = UndoHistory::default();
+
+        grid.swap_cells(gp(1, 0), gp(1, 1)).unwrap();
+        grid.recalculate_active_cells();
+        history.push(gp(1, 0), gp(1, 1));
+        let swapped = grid.clone();
+
+        assert_eq!(Some((gp(1, 0), gp(1, 1))), history.undo(&mut grid).unwrap());
+        assert_eq!(original.recalculated(), grid);
+        assert_eq!(None, history.undo(&mut grid).unwrap());
+
+        assert_eq!(Some((gp(1, 0), gp(1, 1))), history.redo(&mut grid).unwrap());
+        assert_eq!(swapped, grid);
+        assert_eq!(None, history.redo(&mut grid).unwrap());
+    }
+
+    #[test]
+    fn test_push_forgets_redo() {
+        let mut grid = Grid::new(3, 2);
+        let mut history = UndoHistory::default();
+
+        history.push(gp(0, 0), gp(0, 1));
+        history.undo(&mut grid).unwrap();
+        assert!(history.can_redo());
+
+        history.push(gp(1, 0), gp(1, 1));
+        assert!(!history.can_redo());
+    }
+
+    #[test]
+    fn test_history_is_capped() {
+        let mut grid = Grid::new(3, 2);
+        let mut history = UndoHistory::new(2);
+
+        history.push(gp(0, 0), gp(0, 1));
+        history.push(gp(1, 0), gp(1, 1));
+        history.push(gp(2, 0), gp(2, 1));
+
+        assert_eq!(Some((gp(2, 0), gp(2, 1))), history.undo(&mut grid).unwrap());
+        assert_eq!(Some((gp(1, 0), gp(1, 1))), history.undo(&mut grid).unwrap());
+        assert_eq!(None, history.undo(&mut grid).unwrap());
+    }
+
+    #[test]
+    fn test_undo_after_rows_rise() {
+        let original = Grid::new_from_str(
+            r#"
+            ...
+            .r.
+            c.c
+            "#,
+        )
+        .unwrap();
+        let new_row = Grid::new_from_str("...\nl.i").unwrap().get_row(0).unwrap();
+        let mut grid = original.clone();
+        let mut history = UndoHistory::default();
+
+        grid.swap_cells(gp(1, 0), gp(1, 1)).unwrap();
+        history.push(gp(1, 0), gp(1, 1));
+        grid.pop_top_row();
+        grid.push_bottom_row(new_row.clone()).unwrap();
+        history.shift_up_one_row(grid.height);
+
+        assert_eq!(Some((gp(1, 1), gp(1, 2))), history.undo(&mut grid).unwrap());
+        let mut expected = original;
+        expected.pop_top_row();
+        expected.push_bottom_row(new_row).unwrap();
+        assert_eq!(expected.as_chars(), grid.as_chars());
+    }
+
+    #[test]
+    fn test_rise_forgets_swaps_pushed_off_the_top() {
+        let mut history = UndoHistory::default();
+        history.push(gp(0, 0), gp(0, 1));
+        history.push(gp(1, 1), gp(1, 2));
+
+        history.shift_up_one_row(3);
+
+        assert!(!history.can_undo());
+        assert!(!history.can_redo());
+    }
+}
@@ -2,36 +2,149 @@ pub mod adjacency;
 pub mod cell;
 pub mod error;
 pub mod grid;
-pub mod grid_algorithms;
 pub mod grid_iterator_2d;
 pub mod grid_pos;
+#[cfg(feature = "serde")]
+pub mod highscore;
+pub mod history;
 pub mod line_fragment;
+pub mod replay;
+pub mod score;
+pub mod solver;
 
 use cell::Cell;
 use druid::im::Vector;
+use error::GunpeyLibError;
 use grid::Grid;
-use rand::Rng;
+use grid_pos::gp;
+use line_fragment::{LineFragment, WeightedKindDistribution};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::ops::Range;
+
+/// Approximately half of a new row should be filled with line segments
+pub const DEFAULT_FILL_RATIO: Range<f32> = 40.0..60.0;
 
 pub struct NewRowGenerationParams {
     pub width: usize,
+    /// The percentage of cells that should be filled is picked from this range for every row.
+    /// It's clamped to `0.0..=100.0` and an empty range always fills `start` percent of the row.
+    pub fill_ratio: Range<f32>,
+    /// How often each kind of fragment shows up, or `None` for an even mix
+    pub kind_weights: Option<WeightedKindDistribution>,
+    /// When set the row is generated from `seeded_rng(seed)` instead of the RNG passed to
+    /// `new_random_row`, so the same seed always makes the same row
+    pub seed: Option<u64>,
+    /// The grid the row is about to be pushed onto. When set, rows that would already hold part
+    /// of a line as soon as they're pushed are rerolled, and if that keeps failing the cells that
+    /// would be active are left empty instead. Clone the grid before popping its top row.
+    pub avoid_active_on: Option<Grid>,
 }
 
-pub fn new_random_row(
-    rng: &mut impl Rng,
-    // TODO is this really how you destructure params? Feels goofy
-    NewRowGenerationParams { width }: NewRowGenerationParams,
-) -> Vector<Cell> {
-    // TODO make this a param
-    // Approximately half of a new row should be filled with line segments. This chooses a proportion close to half.
-    let percent_of_row_filled_with_cells = rng.gen_range(40.0..60.0);
-
-    (0..width)
+/// The settings a game is played with. Front-ends build the grid and anything else that needs
+/// to know the board size from one of these, so they can't disagree about it.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GameConfig {
+    pub width: usize,
+    pub height: usize,
+    /// How many rows rise each second
+    pub updates_per_second: f64,
+    /// See `NewRowGenerationParams::fill_ratio`
+    pub fill_ratio: Range<f32>,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            width: 5,
+            height: 10,
+            // A new row rises every four seconds
+            updates_per_second: 0.25,
+            fill_ratio: DEFAULT_FILL_RATIO,
+        }
+    }
+}
+
+impl GameConfig {
+    /// An empty grid the size of the board
+    pub fn new_grid(&self) -> Grid {
+        Grid::new(self.width, self.height)
+    }
+}
+
+/// How many times a row that arrives connected is rerolled before its active cells are emptied
+const MAX_ROW_REROLLS: usize = 32;
+
+/// An RNG that produces the same numbers every time it's created with the same seed, for
+/// reproducible games and tests
+pub fn seeded_rng(seed: u64) -> StdRng {
+    StdRng::seed_from_u64(seed)
+}
+
+pub fn new_random_row(rng: &mut impl Rng, params: NewRowGenerationParams) -> Vector<Cell> {
+    match params.seed {
+        Some(seed) => unconnected_random_row(&mut seeded_rng(seed), &params),
+        None => unconnected_random_row(rng, &params),
+    }
+}
+
+fn unconnected_random_row(rng: &mut impl Rng, params: &NewRowGenerationParams) -> Vector<Cell> {
+    let grid = match &params.avoid_active_on {
+        Some(grid) => grid,
+        None => return random_row(rng, params),
+    };
+
+    for _ in 0..MAX_ROW_REROLLS {
+        let row = random_row(rng, params);
+        if active_after_push(grid, &row).is_empty() {
+            return row;
+        }
+    }
+
+    // Taking fragments away can't connect anything new, so one pass is enough
+    let mut row = random_row(rng, params);
+    for x in active_after_push(grid, &row) {
+        row[x] = Cell::Empty;
+    }
+
+    row
+}
+
+/// The columns of `row` that would be active after cycling it onto `grid`
+fn active_after_push(grid: &Grid, row: &Vector<Cell>) -> Vec<usize> {
+    let mut grid = grid.clone();
+    grid.pop_top_row();
+    if grid.push_bottom_row(row.clone()).is_err() {
+        return Vec::new();
+    }
+
+    (0..grid.width)
+        .filter(|&x| grid.is_cell_active(&gp(x as isize, 0)))
+        .collect()
+}
+
+fn random_row(rng: &mut impl Rng, params: &NewRowGenerationParams) -> Vector<Cell> {
+    let start = params.fill_ratio.start.clamp(0.0, 100.0);
+    let end = params.fill_ratio.end.clamp(0.0, 100.0);
+    let percent_of_row_filled_with_cells = if start < end {
+        rng.gen_range(start..end)
+    } else {
+        start
+    };
+
+    (0..params.width)
         .into_iter()
         .map(|_| {
             let f = rng.gen::<f32>() * 100.0;
             // Here, we use the proportion we chose earlier an fill out our cells based on that proportion
             if f < percent_of_row_filled_with_cells {
-                Cell::Filled(rng.gen())
+                match params.kind_weights {
+                    Some(kind_weights) => Cell::Filled(LineFragment {
+                        kind: kind_weights.sample_with_weights(rng),
+                        is_active: false,
+                    }),
+                    None => Cell::Filled(rng.gen()),
+                }
             } else {
                 Cell::Empty
             }
@@ -39,6 +152,220 @@ pub fn new_random_row(
         .collect()
 }
 
+/// Push a new random row onto the bottom of `grid` and drop its top row, the way the stack
+/// rises during a game. Returns `Ok(false)` without touching the grid or `rng` if the stack has
+/// already reached the top. New rows are filled according to `config.fill_ratio`.
+pub fn cycle_rows(
+    grid: &mut Grid,
+    rng: &mut impl Rng,
+    config: &GameConfig,
+) -> Result<bool, GunpeyLibError> {
+    if grid.is_topped_out() {
+        return Ok(false);
+    }
+
+    let new_row_params = NewRowGenerationParams {
+        width: grid.width,
+        fill_ratio: config.fill_ratio.clone(),
+        kind_weights: None,
+        seed: None,
+        avoid_active_on: Some(grid.clone()),
+    };
+
+    let _ = grid.pop_top_row();
+    let new_row = new_random_row(rng, new_row_params);
+    grid.push_bottom_row(new_row)?;
+    grid.recalculate_active_cells();
+
+    Ok(true)
+}
+
+/// An empty grid the size of `GameConfig::default()`
 pub fn new_small_grid() -> Grid {
-    Grid::new(5, 10)
+    GameConfig::default().new_grid()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row_to_string(row: &Vector<Cell>) -> String {
+        row.iter().map(Cell::to_str).collect()
+    }
+
+    #[test]
+    fn test_seeded_rng_makes_the_same_rows() {
+        let mut rng = seeded_rng(1008);
+        let rows: Vec<_> = (0..4)
+            .map(|_| {
+                let params = NewRowGenerationParams {
+                    width: 5,
+                    fill_ratio: DEFAULT_FILL_RATIO,
+                    kind_weights: None,
+                    seed: None,
+                    avoid_active_on: None,
+                };
+                row_to_string(&new_random_row(&mut rng, params))
+            })
+            .collect();
+
+        assert_eq!(rows, vec!["rc..i", "cc.rc", "....c", "....r"]);
+    }
+
+    #[test]
+    fn test_new_random_row_prefers_params_seed() {
+        let params = |seed| NewRowGenerationParams {
+            width: 8,
+            fill_ratio: DEFAULT_FILL_RATIO,
+            kind_weights: None,
+            seed: Some(seed),
+            avoid_active_on: None,
+        };
+
+        let a = new_random_row(&mut seeded_rng(1), params(42));
+        let b = new_random_row(&mut seeded_rng(2), params(42));
+
+        assert_eq!(a, b);
+        assert_eq!(a, new_random_row(&mut seeded_rng(42), params(42)));
+    }
+
+    #[test]
+    fn test_fill_ratio_extremes() {
+        let params = |fill_ratio| NewRowGenerationParams {
+            width: 20,
+            fill_ratio,
+            kind_weights: None,
+            seed: None,
+            avoid_active_on: None,
+        };
+        let mut rng = seeded_rng(1009);
+
+        let empty_row = new_random_row(&mut rng, params(0.0..0.0));
+        assert!(empty_row.iter().all(Cell::is_empty));
+
+        let full_row = new_random_row(&mut rng, params(100.0..100.0));
+        assert!(!full_row.iter().any(Cell::is_empty));
+
+        // Out of range ratios are clamped rather than panicking
+        let clamped_row = new_random_row(&mut rng, params(150.0..200.0));
+        assert!(!clamped_row.iter().any(Cell::is_empty));
+        let clamped_row = new_random_row(&mut rng, params(-50.0..-10.0));
+        assert!(clamped_row.iter().all(Cell::is_empty));
+    }
+
+    #[test]
+    fn test_kind_weights() {
+        let params = NewRowGenerationParams {
+            width: 200,
+            fill_ratio: 100.0..100.0,
+            kind_weights: Some(WeightedKindDistribution::new([0.0, 0.0, 1.0, 0.0])),
+            seed: Some(1010),
+            avoid_active_on: None,
+        };
+
+        let row = new_random_row(&mut seeded_rng(0), params);
+
+        assert!(row
+            .iter()
+            .all(|cell| cell.kind() == Some(line_fragment::LineFragmentKind::LeftSlash)));
+    }
+
+    #[test]
+    fn test_kind_weights_all_zero_picks_every_kind() {
+        let kind_weights = WeightedKindDistribution::new([0.0; 4]);
+        let mut rng = seeded_rng(1010);
+
+        let kinds: std::collections::BTreeSet<_> = (0..200)
+            .map(|_| kind_weights.sample_with_weights(&mut rng))
+            .collect();
+
+        assert_eq!(kinds.len(), 4);
+    }
+
+    #[test]
+    fn test_avoid_active_on() {
+        let grid = Grid::new_from_str(
+            r#"
+            .....
+            .....
+            .....
+            r...i
+            "#,
+        )
+        .unwrap();
+        let params = |avoid_active_on| NewRowGenerationParams {
+            width: grid.width,
+            fill_ratio: 80.0..100.0,
+            kind_weights: None,
+            seed: None,
+            avoid_active_on,
+        };
+        let cycle = |row| {
+            let mut grid = grid.clone();
+            grid.pop_top_row();
+            grid.push_bottom_row(row).unwrap();
+            (0..grid.width).any(|x| grid.is_cell_active(&gp(x as isize, 0)))
+        };
+
+        let mut rng = seeded_rng(1031);
+        let connected_without_flag = (0..200)
+            .filter(|_| cycle(new_random_row(&mut rng, params(None))))
+            .count();
+        // Make sure the board can actually produce connected rows
+        assert!(connected_without_flag > 0);
+
+        let mut rng = seeded_rng(1031);
+        for _ in 0..200 {
+            let row = new_random_row(&mut rng, params(Some(grid.clone())));
+            assert!(
+                !cycle(row.clone()),
+                "{} arrived connected",
+                row_to_string(&row)
+            );
+        }
+    }
+
+    #[test]
+    fn test_avoid_active_on_empties_cells_when_rerolls_run_out() {
+        // Every row this makes is a line on its own so rerolling never helps
+        let params = NewRowGenerationParams {
+            width: 4,
+            fill_ratio: 100.0..100.0,
+            kind_weights: Some(WeightedKindDistribution::new([1.0, 0.0, 0.0, 0.0])),
+            seed: None,
+            avoid_active_on: Some(Grid::new(4, 3)),
+        };
+
+        let row = new_random_row(&mut seeded_rng(1031), params);
+
+        assert_eq!("....", row_to_string(&row));
+    }
+
+    #[test]
+    fn test_cycle_rows_follows_config() {
+        let config = GameConfig {
+            width: 7,
+            height: 4,
+            fill_ratio: 0.0..0.0,
+            ..GameConfig::default()
+        };
+        let mut grid = config.new_grid();
+        grid.set_cell(&gp(0, 0), Cell::from_str("c")).unwrap();
+
+        assert!(cycle_rows(&mut grid, &mut seeded_rng(1038), &config).unwrap());
+
+        assert_eq!((7, 4), (grid.width, grid.height));
+        assert_eq!(".......", row_to_string(&grid.get_row(0).unwrap()));
+        assert_eq!("c......", row_to_string(&grid.get_row(1).unwrap()));
+    }
+
+    #[test]
+    fn test_cycle_rows_stops_at_the_top() {
+        let config = GameConfig::default();
+        let mut grid = Grid::new_from_str("c....\n.....").unwrap();
+        let expected = grid.clone();
+
+        assert!(!cycle_rows(&mut grid, &mut seeded_rng(1038), &config).unwrap());
+        assert_eq!(expected, grid);
+    }
 }
@@ -1,13 +1,26 @@
+use crate::{
+    error::GunpeyLibError,
+    grid_pos::{gp, GridPos},
+};
 use druid::Data;
 use rand::{distributions::Standard, prelude::Distribution, Rng};
 use std::fmt::Display;
 
-#[derive(Debug, Clone, Copy, Data, PartialEq)]
+#[derive(Debug, Clone, Copy, Data, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LineFragmentKind {
     Caret,
     InvertedCaret,
     LeftSlash,
     RightSlash,
+    /// Spans all four corners of its cell so it connects to any fragment it touches
+    Wild,
+    /// A straight line along the bottom of its cell. Not part of classic Gunpey, so random rows
+    /// never include it.
+    Horizontal,
+    /// A straight line along the left side of its cell. Not part of classic Gunpey, so random
+    /// rows never include it.
+    Vertical,
 }
 
 impl Display for LineFragmentKind {
@@ -20,6 +33,9 @@ impl Display for LineFragmentKind {
                 LineFragmentKind::InvertedCaret => "inverted caret",
                 LineFragmentKind::LeftSlash => "left slash",
                 LineFragmentKind::RightSlash => "right slash",
+                LineFragmentKind::Wild => "wild",
+                LineFragmentKind::Horizontal => "horizontal",
+                LineFragmentKind::Vertical => "vertical",
             }
         )
     }
@@ -27,12 +43,21 @@ impl Display for LineFragmentKind {
 
 impl LineFragmentKind {
     pub fn from_char(c: &char) -> Self {
+        Self::try_from_char(*c).unwrap_or_else(|err| unreachable!("{}", err))
+    }
+
+    /// Like `from_char` but returns an error for anything that isn't a fragment symbol instead
+    /// of panicking, for input that didn't come from this crate.
+    pub fn try_from_char(c: char) -> Result<Self, GunpeyLibError> {
         match c {
-            '∧' => LineFragmentKind::Caret,
-            '∨' => LineFragmentKind::InvertedCaret,
-            '\\' => LineFragmentKind::LeftSlash,
-            '/' => LineFragmentKind::RightSlash,
-            _ => unreachable!(),
+            '∧' => Ok(LineFragmentKind::Caret),
+            '∨' => Ok(LineFragmentKind::InvertedCaret),
+            '\\' => Ok(LineFragmentKind::LeftSlash),
+            '/' => Ok(LineFragmentKind::RightSlash),
+            '*' => Ok(LineFragmentKind::Wild),
+            '─' => Ok(LineFragmentKind::Horizontal),
+            '│' => Ok(LineFragmentKind::Vertical),
+            _ => Err(GunpeyLibError::InvalidCellChar(c)),
         }
     }
 
@@ -42,6 +67,22 @@ impl LineFragmentKind {
             LineFragmentKind::InvertedCaret => '∨',
             LineFragmentKind::LeftSlash => '\\',
             LineFragmentKind::RightSlash => '/',
+            LineFragmentKind::Wild => '*',
+            LineFragmentKind::Horizontal => '─',
+            LineFragmentKind::Vertical => '│',
+        }
+    }
+
+    /// The corners of a cell that this kind of fragment touches, relative to the cell's position
+    pub fn corner_offsets(&self) -> Vec<GridPos> {
+        match self {
+            LineFragmentKind::Caret => vec![gp(0, 0), gp(1, 0)],
+            LineFragmentKind::InvertedCaret => vec![gp(0, 1), gp(1, 1)],
+            LineFragmentKind::LeftSlash => vec![gp(0, 1), gp(1, 0)],
+            LineFragmentKind::RightSlash => vec![gp(0, 0), gp(1, 1)],
+            LineFragmentKind::Wild => vec![gp(0, 0), gp(0, 1), gp(1, 1), gp(1, 0)],
+            LineFragmentKind::Horizontal => vec![gp(0, 0), gp(1, 0)],
+            LineFragmentKind::Vertical => vec![gp(0, 0), gp(0, 1)],
         }
     }
 }
@@ -58,7 +99,60 @@ impl Distribution<LineFragmentKind> for Standard {
     }
 }
 
+/// Picks fragment kinds in proportion to their weights instead of uniformly like `Standard`.
+/// Weights are in the order caret, inverted caret, left slash, right slash. Negative weights
+/// count as zero and if every weight is zero kinds are picked uniformly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WeightedKindDistribution {
+    pub weights: [f32; 4],
+}
+
+impl WeightedKindDistribution {
+    const KINDS: [LineFragmentKind; 4] = [
+        LineFragmentKind::Caret,
+        LineFragmentKind::InvertedCaret,
+        LineFragmentKind::LeftSlash,
+        LineFragmentKind::RightSlash,
+    ];
+
+    pub fn new(weights: [f32; 4]) -> Self {
+        Self { weights }
+    }
+
+    pub fn sample_with_weights<R: Rng + ?Sized>(&self, rng: &mut R) -> LineFragmentKind {
+        let weights = self.weights.map(|weight| weight.max(0.0));
+        let total: f32 = weights.iter().sum();
+        if total <= 0.0 {
+            return rng.gen();
+        }
+
+        let mut choice = rng.gen_range(0.0..total);
+        for (kind, weight) in Self::KINDS.iter().zip(weights) {
+            if choice < weight {
+                return *kind;
+            }
+            choice -= weight;
+        }
+
+        // Rounding can leave a sliver at the end, give it to the last kind that can be picked
+        Self::KINDS
+            .iter()
+            .zip(weights)
+            .rev()
+            .find(|(_, weight)| *weight > 0.0)
+            .map(|(kind, _)| *kind)
+            .unwrap()
+    }
+}
+
+impl Distribution<LineFragmentKind> for WeightedKindDistribution {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> LineFragmentKind {
+        self.sample_with_weights(rng)
+    }
+}
+
 #[derive(Debug, Clone, Copy, Data, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LineFragment {
     pub kind: LineFragmentKind,
     pub is_active: bool,
@@ -75,6 +169,10 @@ impl LineFragment {
     }
 
     pub fn from_str(lf_str: &str) -> Self {
+        Self::try_from_str(lf_str).unwrap_or_else(|| unreachable!(r#"invalid lf_str "{}""#, lf_str))
+    }
+
+    pub fn try_from_str(lf_str: &str) -> Option<Self> {
         let (is_active, kind) = match lf_str {
             "C" => (true, LineFragmentKind::Caret),
             "c" => (false, LineFragmentKind::Caret),
@@ -84,16 +182,53 @@ impl LineFragment {
             "l" => (false, LineFragmentKind::LeftSlash),
             "R" => (true, LineFragmentKind::RightSlash),
             "r" => (false, LineFragmentKind::RightSlash),
-            _ => unreachable!(r#"invalid lf_str "{}""#, lf_str),
+            "W" => (true, LineFragmentKind::Wild),
+            "w" => (false, LineFragmentKind::Wild),
+            "H" => (true, LineFragmentKind::Horizontal),
+            "h" => (false, LineFragmentKind::Horizontal),
+            "V" => (true, LineFragmentKind::Vertical),
+            "v" => (false, LineFragmentKind::Vertical),
+            _ => return None,
         };
 
-        Self { is_active, kind }
+        Some(Self { is_active, kind })
     }
 
     pub fn to_char(&self) -> char {
         self.kind.to_char()
     }
 
+    /// Turn the fragment a quarter turn clockwise: caret, right slash, inverted caret, left slash
+    /// and back to caret. Straight fragments swap between horizontal and vertical and wild
+    /// fragments stay the same. The fragment is deactivated since its old flag described a
+    /// different shape, so recalculate the grid afterwards.
+    pub fn rotate_cw(&mut self) {
+        self.kind = match self.kind {
+            LineFragmentKind::Caret => LineFragmentKind::RightSlash,
+            LineFragmentKind::RightSlash => LineFragmentKind::InvertedCaret,
+            LineFragmentKind::InvertedCaret => LineFragmentKind::LeftSlash,
+            LineFragmentKind::LeftSlash => LineFragmentKind::Caret,
+            LineFragmentKind::Wild => LineFragmentKind::Wild,
+            LineFragmentKind::Horizontal => LineFragmentKind::Vertical,
+            LineFragmentKind::Vertical => LineFragmentKind::Horizontal,
+        };
+        self.is_active = false;
+    }
+
+    /// The opposite of `rotate_cw`
+    pub fn rotate_ccw(&mut self) {
+        self.kind = match self.kind {
+            LineFragmentKind::Caret => LineFragmentKind::LeftSlash,
+            LineFragmentKind::LeftSlash => LineFragmentKind::InvertedCaret,
+            LineFragmentKind::InvertedCaret => LineFragmentKind::RightSlash,
+            LineFragmentKind::RightSlash => LineFragmentKind::Caret,
+            LineFragmentKind::Wild => LineFragmentKind::Wild,
+            LineFragmentKind::Horizontal => LineFragmentKind::Vertical,
+            LineFragmentKind::Vertical => LineFragmentKind::Horizontal,
+        };
+        self.is_active = false;
+    }
+
     pub fn to_str(&self) -> &'static str {
         match self {
             &LineFragment { is_active, kind } => match kind {
@@ -105,11 +240,57 @@ impl LineFragment {
                 LineFragmentKind::LeftSlash => "l",
                 LineFragmentKind::RightSlash if is_active => "R",
                 LineFragmentKind::RightSlash => "r",
+                LineFragmentKind::Wild if is_active => "W",
+                LineFragmentKind::Wild => "w",
+                LineFragmentKind::Horizontal if is_active => "H",
+                LineFragmentKind::Horizontal => "h",
+                LineFragmentKind::Vertical if is_active => "V",
+                LineFragmentKind::Vertical => "v",
             },
         }
     }
 }
 
+/// The two ways grids are written out as text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grammar {
+    /// `c`, `i`, `l`, `r`, `w`, `h` and `v`, uppercase when active. Used by `Grid::new_from_str`.
+    Letters,
+    /// `∧`, `∨`, `\`, `/`, `*`, `─` and `│`. Used by `Grid::new_from_chars`.
+    Symbols,
+}
+
+impl Grammar {
+    /// Returns true if `c` is a fragment in this grammar. `.` is an empty cell and `#` is a
+    /// blocked cell in both.
+    pub fn accepts(&self, c: char) -> bool {
+        c == '.'
+            || c == '#'
+            || match self {
+                Grammar::Letters => {
+                    LineFragment::try_from_str(c.encode_utf8(&mut [0; 4])).is_some()
+                }
+                Grammar::Symbols => LineFragmentKind::try_from_char(c).is_ok(),
+            }
+    }
+
+    pub fn other(&self) -> Self {
+        match self {
+            Grammar::Letters => Grammar::Symbols,
+            Grammar::Symbols => Grammar::Letters,
+        }
+    }
+}
+
+impl Display for Grammar {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Grammar::Letters => write!(f, "letter"),
+            Grammar::Symbols => write!(f, "symbol"),
+        }
+    }
+}
+
 impl Distribution<LineFragment> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> LineFragment {
         LineFragment {
@@ -0,0 +1,232 @@
+use crate::{
+    cycle_rows, error::GunpeyLibError, grid::Grid, grid_pos::GridPos, score::Score, seeded_rng,
+    GameConfig,
+};
+use rand::{rngs::StdRng, Rng};
+
+/// Something the player did that changed the grid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ReplayEvent {
+    /// Two cells were swapped and anything that lit up was cleared
+    Swap(GridPos, GridPos),
+    /// The stack rose by one row
+    CycleRows,
+}
+
+impl ReplayEvent {
+    /// Make this move on `grid`, taking any new rows from `rng` and filling them as `config`
+    /// says, and award `score` for anything it clears. The egui front-end plays every move
+    /// through here so the game and its replay can't drift apart.
+    ///
+    /// A swap that the grid refuses still clears whatever was already active.
+    pub fn apply(
+        &self,
+        grid: &mut Grid,
+        rng: &mut impl Rng,
+        config: &GameConfig,
+        score: &mut Score,
+    ) -> Result<(), GunpeyLibError> {
+        match *self {
+            ReplayEvent::Swap(a, b) => {
+                // swap_cells recalculates whenever the swap could have changed anything
+                let swapped = grid.swap_cells(a, b);
+                grid.clear_active_cells_scored(score, 1);
+
+                swapped
+            }
+            ReplayEvent::CycleRows => cycle_rows(grid, rng, config).map(|_| ()),
+        }
+    }
+}
+
+/// A whole game, stored as the seed its rows came from and every move made since it started.
+/// The grid at any point can be rebuilt from this alone, so it's enough to save or share a game.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Replay {
+    seed: u64,
+    config: GameConfig,
+    events: Vec<ReplayEvent>,
+}
+
+impl Replay {
+    /// Start recording a game played with `config` on an empty grid, whose rows come from
+    /// `seeded_rng(seed)`
+    pub fn new(seed: u64, config: GameConfig) -> Self {
+        Self {
+            seed,
+            config,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn config(&self) -> &GameConfig {
+        &self.config
+    }
+
+    /// Everything recorded so far, oldest first
+    pub fn events(&self) -> &[ReplayEvent] {
+        &self.events
+    }
+
+    /// The grid the game started with
+    pub fn initial_grid(&self) -> Grid {
+        self.config.new_grid()
+    }
+
+    /// The RNG live play should take rows from for the replay to match it
+    pub fn rng(&self) -> StdRng {
+        seeded_rng(self.seed)
+    }
+
+    /// Add `event` to the end of the game
+    pub fn record(&mut self, event: ReplayEvent) {
+        self.events.push(event);
+    }
+
+    pub fn record_swap(&mut self, a: GridPos, b: GridPos) {
+        self.record(ReplayEvent::Swap(a, b));
+    }
+
+    pub fn record_cycle(&mut self) {
+        self.record(ReplayEvent::CycleRows);
+    }
+
+    /// Rebuild the grid as it was after the last recorded event. Moves that failed during the
+    /// game fail the same way here and are skipped.
+    pub fn replay(&self) -> Grid {
+        let mut grid = self.initial_grid();
+        let mut rng = self.rng();
+        let mut score = Score::default();
+
+        for event in &self.events {
+            let _ = event.apply(&mut grid, &mut rng, &self.config, &mut score);
+        }
+
+        grid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid_pos::gp;
+
+    #[test]
+    fn test_replay_reproduces_live_grid() {
+        let config = GameConfig::default();
+        let mut replay = Replay::new(1234, config.clone());
+        let mut live_grid = replay.initial_grid();
+        let mut live_rng = replay.rng();
+        let mut live_score = Score::default();
+
+        // Play the way the front-ends do, recording as we go
+        let mut play = |grid: &mut Grid, replay: &mut Replay, event: ReplayEvent| {
+            let _ = event.apply(grid, &mut live_rng, &config, &mut live_score);
+            replay.record(event);
+        };
+
+        for _ in 0..3 {
+            play(&mut live_grid, &mut replay, ReplayEvent::CycleRows);
+        }
+        play(
+            &mut live_grid,
+            &mut replay,
+            ReplayEvent::Swap(gp(0, 0), gp(1, 1)),
+        );
+        play(
+            &mut live_grid,
+            &mut replay,
+            ReplayEvent::Swap(gp(2, 1), gp(3, 0)),
+        );
+        play(&mut live_grid, &mut replay, ReplayEvent::CycleRows);
+        play(
+            &mut live_grid,
+            &mut replay,
+            ReplayEvent::Swap(gp(1, 2), gp(2, 3)),
+        );
+        play(&mut live_grid, &mut replay, ReplayEvent::CycleRows);
+        play(
+            &mut live_grid,
+            &mut replay,
+            ReplayEvent::Swap(gp(3, 1), gp(4, 2)),
+        );
+
+        assert_eq!(9, replay.events().len());
+        assert_ne!(replay.initial_grid(), live_grid);
+        assert_eq!(live_grid, replay.replay());
+    }
+
+    #[test]
+    fn test_apply_swap_recalculates_once() {
+        use crate::grid::RECALCULATION_COUNT;
+
+        let mut grid = Grid::new_from_str(
+            r#"
+            .r.
+            c.c
+            "#,
+        )
+        .unwrap();
+        let mut rng = seeded_rng(0);
+        let recalculations_before = RECALCULATION_COUNT.with(|count| count.get());
+
+        ReplayEvent::Swap(gp(1, 0), gp(1, 1))
+            .apply(
+                &mut grid,
+                &mut rng,
+                &GameConfig::default(),
+                &mut Score::default(),
+            )
+            .unwrap();
+
+        assert_eq!(
+            1,
+            RECALCULATION_COUNT.with(|count| count.get()) - recalculations_before
+        );
+        assert_eq!(grid.clone().recalculated(), grid);
+    }
+
+    #[test]
+    fn test_empty_replay_is_the_initial_grid() {
+        let config = GameConfig {
+            width: 7,
+            height: 9,
+            ..GameConfig::default()
+        };
+        let replay = Replay::new(0, config);
+
+        assert_eq!(Grid::new(7, 9), replay.replay());
+    }
+
+    #[test]
+    fn test_different_seeds_make_different_games() {
+        let mut a = Replay::new(1, GameConfig::default());
+        let mut b = Replay::new(2, GameConfig::default());
+        for _ in 0..4 {
+            a.record_cycle();
+            b.record_cycle();
+        }
+
+        assert_ne!(a.replay(), b.replay());
+    }
+
+    #[test]
+    fn test_failed_swap_is_skipped() {
+        let mut replay = Replay::new(99, GameConfig::default());
+        replay.record_cycle();
+        replay.record_swap(gp(0, 0), gp(9, 9));
+        replay.record_swap(gp(1, 0), gp(1, 0));
+
+        let mut expected = replay.initial_grid();
+        cycle_rows(&mut expected, &mut replay.rng(), replay.config()).unwrap();
+        expected.clear_active_cells();
+
+        assert_eq!(expected, replay.replay());
+    }
+}
@@ -0,0 +1,72 @@
+use crate::{grid::Grid, grid_pos::GridPos};
+use druid::Data;
+
+/// Points awarded for each cell in a cleared line, before any multiplier
+pub const POINTS_PER_CELL: u64 = 10;
+
+/// The player's score, shared by every front-end so they all award the same points
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Data)]
+pub struct Score {
+    pub points: u64,
+}
+
+impl Score {
+    /// Award points for clearing `cells` cells. The multiplier rewards clearing several lines in
+    /// a row, pass 1 for a single clear.
+    pub fn add_cleared(&mut self, cells: usize, chain_multiplier: u32) {
+        self.points += cells as u64 * POINTS_PER_CELL * chain_multiplier as u64;
+    }
+}
+
+impl Grid {
+    /// `clear_active_cells`, then award points for every cell that was cleared
+    pub fn clear_active_cells_scored(
+        &mut self,
+        score: &mut Score,
+        chain_multiplier: u32,
+    ) -> Vec<GridPos> {
+        let cleared = self.clear_active_cells();
+        score.add_cleared(cleared.len(), chain_multiplier);
+
+        cleared
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_cleared() {
+        let mut score = Score::default();
+
+        score.add_cleared(4, 1);
+        assert_eq!(score.points, 40);
+
+        score.add_cleared(4, 3);
+        assert_eq!(score.points, 160);
+
+        score.add_cleared(0, 5);
+        assert_eq!(score.points, 160);
+    }
+
+    #[test]
+    fn test_clear_active_cells_scored() {
+        let mut grid = Grid::new_from_str(
+            r#"
+            .r..
+            cccc
+            "#,
+        )
+        .unwrap();
+        grid.recalculate_active_cells();
+        let mut score = Score::default();
+
+        let cleared = grid.clear_active_cells_scored(&mut score, 2);
+
+        assert_eq!(cleared.len(), 4);
+        assert_eq!(score.points, 80);
+        assert!(grid.clear_active_cells_scored(&mut score, 2).is_empty());
+        assert_eq!(score.points, 80);
+    }
+}
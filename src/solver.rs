@@ -0,0 +1,118 @@
+use crate::{grid::Grid, grid_pos::GridPos};
+
+/// A swap worth pointing out to the player
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hint {
+    pub swap: (GridPos, GridPos),
+    /// How many more cells are active after the swap than before it
+    pub gained_active: usize,
+}
+
+impl Grid {
+    /// Find the legal swap that activates the most cells. Ties go to the swap nearest the bottom
+    /// left. Returns `None` if no swap would activate anything.
+    pub fn best_hint(&self) -> Option<Hint> {
+        let active_count = self.count_active_cells();
+        let mut best_hint: Option<Hint> = None;
+
+        for (a, b) in self.legal_swaps() {
+            let mut swapped = self.clone();
+            if swapped.swap_cells(a, b).is_err() {
+                continue;
+            }
+
+            let gained_active = swapped.count_active_cells().saturating_sub(active_count);
+            let is_better = best_hint
+                .map(|hint| gained_active > hint.gained_active)
+                .unwrap_or(gained_active > 0);
+            if is_better {
+                best_hint = Some(Hint {
+                    swap: (a, b),
+                    gained_active,
+                });
+            }
+        }
+
+        best_hint
+    }
+
+    /// The first of the `legal_swaps`, bottom left first, that leaves at least one line from wall
+    /// to wall. Meant for a hint button rather than anything that runs every frame: every swap is
+    /// tried on its own copy of the grid and recalculated, so on a full board this is roughly
+    /// `width * height` full recalculations. This grid is never changed.
+    ///
+    /// A board that already has an active line returns the first swap that keeps one.
+    pub fn find_winning_swap(&self) -> Option<(GridPos, GridPos)> {
+        self.legal_swaps().into_iter().find(|&(a, b)| {
+            let mut swapped = self.clone();
+            // `swap_cells` recalculates the active cells itself
+            swapped.swap_cells(a, b).is_ok() && swapped.count_active_cells() > 0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid_pos::gp;
+
+    #[test]
+    fn test_best_hint_picks_biggest_gain() {
+        let grid = Grid::new_from_str(
+            r#"
+            ....
+            .c..
+            c.cc
+            "#,
+        )
+        .unwrap();
+
+        let expected = Hint {
+            swap: (gp(1, 0), gp(1, 1)),
+            gained_active: 4,
+        };
+        assert_eq!(Some(expected), grid.best_hint());
+    }
+
+    #[test]
+    fn test_best_hint_without_helpful_swaps() {
+        let grid = Grid::new_from_str(
+            r#"
+            c...
+            ...c
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(None, grid.best_hint());
+    }
+
+    #[test]
+    fn test_find_winning_swap() {
+        let grid = Grid::new_from_str(
+            r#"
+            ....
+            .c..
+            c.cc
+            "#,
+        )
+        .unwrap();
+        let original = grid.clone();
+
+        assert_eq!(Some((gp(1, 0), gp(1, 1))), grid.find_winning_swap());
+        assert_eq!(original, grid);
+    }
+
+    #[test]
+    fn test_find_winning_swap_without_a_win() {
+        let grid = Grid::new_from_str(
+            r#"
+            c...
+            ...c
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(None, grid.find_winning_swap());
+    }
+}